@@ -0,0 +1,22 @@
+#ifndef KLIPPER_ESTIMATOR_H
+#define KLIPPER_ESTIMATOR_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct Estimator Estimator;
+
+Estimator *ke_estimator_new(const char *limits_json);
+int ke_estimator_feed(Estimator *est, const char *gcode);
+int ke_estimator_finish(Estimator *est);
+double ke_estimator_total_time(const Estimator *est);
+char *ke_estimator_result_json(const Estimator *est);
+void ke_estimator_free(Estimator *est);
+void ke_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,181 @@
+//! C ABI for embedding the estimator in other tools.
+//!
+//! An estimator is created from a JSON printer configuration, in the same format as produced by
+//! the `dump-config` command. Gcode text is then fed to it in arbitrary chunks, after which the
+//! total time and a JSON breakdown can be retrieved. Strings returned by this library must be
+//! released with `ke_string_free`.
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+
+use lib_klipper::estimation::{self, EstimationState};
+use lib_klipper::planner::{Planner, PrinterLimits};
+
+pub struct Estimator {
+    // Taken once finished
+    estimator: Option<estimation::Estimator>,
+    result: Option<EstimationState>,
+    // Trailing partial line from the last fed chunk
+    pending: String,
+}
+
+impl Estimator {
+    fn new(limits_json: &str) -> Option<Estimator> {
+        let mut limits: PrinterLimits = serde_json::from_str(limits_json).ok()?;
+        limits.recalculate();
+        Some(Estimator {
+            estimator: Some(estimation::Estimator::new(Planner::from_limits(limits))),
+            result: None,
+            pending: String::new(),
+        })
+    }
+
+    fn feed(&mut self, text: &str) -> bool {
+        let estimator = match self.estimator.as_mut() {
+            Some(estimator) => estimator,
+            None => return false,
+        };
+        self.pending.push_str(text);
+        let complete = match self.pending.rfind('\n') {
+            Some(idx) => idx + 1,
+            None => return true,
+        };
+        let lines = self.pending.drain(..complete).collect::<String>();
+        // Lines after an invalid one are still fed, as they're already taken from `pending`
+        let mut ok = true;
+        for line in lines.lines() {
+            ok &= estimator.feed_line(line).is_ok();
+        }
+        ok
+    }
+
+    fn finish(&mut self) -> bool {
+        let mut estimator = match self.estimator.take() {
+            Some(estimator) => estimator,
+            None => return false,
+        };
+        let line = std::mem::take(&mut self.pending);
+        let ok = line.is_empty() || estimator.feed_line(&line).is_ok();
+        self.result = Some(estimator.finish());
+        ok
+    }
+
+    /// Total time so far, which is the final total once finished
+    fn total_time(&self) -> f64 {
+        match (self.estimator.as_ref(), self.result.as_ref()) {
+            (Some(estimator), _) => estimator.partial_time(),
+            (_, Some(result)) => result.sequences.iter().map(|s| s.total_time).sum(),
+            _ => 0.0,
+        }
+    }
+}
+
+fn status(ok: bool) -> c_int {
+    if ok {
+        0
+    } else {
+        -1
+    }
+}
+
+unsafe fn str_arg<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok()
+}
+
+/// Creates a new estimator from a JSON printer configuration.
+/// Returns a null pointer if the configuration is invalid.
+///
+/// # Safety
+///
+/// `limits_json` must be a valid, NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ke_estimator_new(limits_json: *const c_char) -> *mut Estimator {
+    match str_arg(limits_json).and_then(Estimator::new) {
+        Some(est) => Box::into_raw(Box::new(est)),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Feeds a chunk of gcode text to the estimator. Lines may be split across chunks.
+/// Returns 0 on success, or -1 if any line couldn't be parsed. Such lines are skipped, while the
+/// other lines of the chunk are still fed.
+///
+/// # Safety
+///
+/// `est` must have been returned by `ke_estimator_new`, and `gcode` must be a valid,
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ke_estimator_feed(est: *mut Estimator, gcode: *const c_char) -> c_int {
+    match (est.as_mut(), str_arg(gcode)) {
+        (Some(est), Some(gcode)) => status(est.feed(gcode)),
+        _ => -1,
+    }
+}
+
+/// Finishes estimation, processing any remaining input. Must be called before reading results,
+/// and no more input can be fed after it. Returns 0 on success, or -1 on error.
+///
+/// # Safety
+///
+/// `est` must have been returned by `ke_estimator_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ke_estimator_finish(est: *mut Estimator) -> c_int {
+    match est.as_mut() {
+        Some(est) => status(est.finish()),
+        None => -1,
+    }
+}
+
+/// Returns the total estimated time in seconds. Before `ke_estimator_finish`, this is the time of
+/// the moves planned so far.
+///
+/// # Safety
+///
+/// `est` must have been returned by `ke_estimator_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ke_estimator_total_time(est: *const Estimator) -> f64 {
+    est.as_ref().map_or(0.0, Estimator::total_time)
+}
+
+/// Returns the estimation result as a JSON string, in the same format as the `estimate` command's
+/// JSON output, to be released with `ke_string_free`. Returns a null pointer if the estimator
+/// isn't finished.
+///
+/// # Safety
+///
+/// `est` must have been returned by `ke_estimator_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ke_estimator_result_json(est: *const Estimator) -> *mut c_char {
+    est.as_ref()
+        .and_then(|est| est.result.as_ref())
+        .and_then(|result| serde_json::to_string(result).ok())
+        .and_then(|s| CString::new(s).ok())
+        .map_or(std::ptr::null_mut(), CString::into_raw)
+}
+
+/// Releases an estimator.
+///
+/// # Safety
+///
+/// `est` must have been returned by `ke_estimator_new` and not released before.
+#[no_mangle]
+pub unsafe extern "C" fn ke_estimator_free(est: *mut Estimator) {
+    if !est.is_null() {
+        drop(Box::from_raw(est));
+    }
+}
+
+/// Releases a string returned by this library.
+///
+/// # Safety
+///
+/// `s` must have been returned by this library and not released before.
+#[no_mangle]
+pub unsafe extern "C" fn ke_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
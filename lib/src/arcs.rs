@@ -7,6 +7,11 @@ use crate::planner::{OperationSequence, PositionMode, ToolheadState};
 #[derive(Debug, Default)]
 pub struct ArcState {
     plane: Plane,
+    /// Upper bound on the number of segments a single arc is split into. Arcs needing more
+    /// aren't planned, and are recorded in `oversized_arc`.
+    pub max_segments: Option<usize>,
+    /// Number of segments needed by the first arc that exceeded `max_segments`
+    pub oversized_arc: Option<usize>,
 }
 
 impl ArcState {
@@ -15,7 +20,7 @@ impl ArcState {
     }
 
     pub fn generate_arc(
-        &self,
+        &mut self,
         toolhead_state: &mut ToolheadState,
         op_sequence: &mut OperationSequence,
         move_kind: Option<Kind>,
@@ -32,15 +37,25 @@ impl ArcState {
             direction,
             args.mm_per_arc_segment,
         );
+        if segments > self.max_segments.unwrap_or(usize::MAX) {
+            self.oversized_arc.get_or_insert(segments);
+            return 0;
+        }
         let mut e_base = toolhead_state.position.w;
         let e_per_move = args.e.map_or(0.0, |e| (e - e_base) / (segments as f64));
 
-        toolhead_state.set_speed(args.velocity);
+        if let Some(v) = args.velocity {
+            toolhead_state.set_speed(v);
+        }
 
         let old_pos_mode = toolhead_state.position_modes;
         toolhead_state.position_modes = [PositionMode::Absolute; 4];
-        for segment in arc {
-            e_base += e_per_move;
+        for (i, segment) in arc.enumerate() {
+            // Land exactly on the requested E, rather than accumulating rounding errors
+            e_base = match args.e {
+                Some(e) if i + 1 == segments => e,
+                _ => e_base + e_per_move,
+            };
             let coord = [
                 Some(segment.x),
                 Some(segment.y),
@@ -67,7 +82,7 @@ impl ArcState {
             ToolheadState::new_element(
                 c,
                 toolhead_state.position.as_ref()[axis],
-                toolhead_state.position_modes[axis],
+                toolhead_state.position_mode(axis),
             )
         };
 
@@ -112,9 +127,7 @@ impl ArcState {
                     .map_or(toolhead_state.position.z, |c| map_coord(c, 2)),
             ),
             e: params.get_number::<f64>('E').map(|c| map_coord(c, 3)),
-            velocity: params
-                .get_number::<f64>('F')
-                .map_or(toolhead_state.velocity, |v| v / 60.0),
+            velocity: params.get_number::<f64>('F').map(|v| v / 60.0),
             axes,
             offset,
             mm_per_arc_segment,
@@ -126,7 +139,7 @@ impl ArcState {
 struct ArcArgs {
     target: Vec3,
     e: Option<f64>,
-    velocity: f64,
+    velocity: Option<f64>,
     axes: (usize, usize, usize),
     offset: (f64, f64),
     mm_per_arc_segment: f64,
@@ -160,11 +173,16 @@ impl ArcArgs {
             angular_travel -= 2.0 * std::f64::consts::PI;
         }
 
-        if angular_travel == 0.0
-            && current_position[alpha_axis] == target_position[alpha_axis]
+        // A full circle is given by the target coinciding with the start position. The angular
+        // travel computed above is then only approximately 0 or -2PI due to rounding of the
+        // center, so set it directly.
+        if current_position[alpha_axis] == target_position[alpha_axis]
             && current_position[beta_axis] == target_position[beta_axis]
         {
-            angular_travel = 2.0 * std::f64::consts::PI
+            angular_travel = match direction {
+                ArcDirection::Clockwise => -2.0 * std::f64::consts::PI,
+                ArcDirection::CounterClockwise => 2.0 * std::f64::consts::PI,
+            };
         }
 
         let linear_travel = target_position[helical_axis] - current_position[helical_axis];
@@ -176,7 +194,13 @@ impl ArcArgs {
             flat_mm.abs()
         };
 
-        let segments = ((mm_of_travel / mm_per_arc_segment).floor() as usize).max(1);
+        // A non-positive segment length plans the arc as a single move to the target
+        let segments = if mm_per_arc_segment > 0.0 {
+            (mm_of_travel / mm_per_arc_segment).floor() as usize
+        } else {
+            1
+        }
+        .max(1);
 
         let theta_per_segment = angular_travel / (segments as f64);
         let linear_per_segment = linear_travel / (segments as f64);
@@ -207,15 +231,10 @@ pub enum ArcDirection {
     CounterClockwise,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
 pub enum Plane {
+    #[default]
     XY,
     XZ,
     YZ,
 }
-
-impl Default for Plane {
-    fn default() -> Self {
-        Self::XY
-    }
-}
@@ -0,0 +1,656 @@
+//! Summarizes planned moves into estimation results, as reported by the `estimate` command.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use glam::DVec4;
+use ordered_float::NotNan;
+use serde::{ser::SerializeSeq, Serialize, Serializer};
+
+use crate::gcode::{parse_gcode, GCodeCommand, GCodeParseError};
+pub use crate::kind_tracker::MoveKind;
+use crate::planner::{
+    Delay, Planner, PlanningMove, PlanningOperation, SpeedLimit, DEFAULT_DRAIN_INTERVAL,
+};
+
+pub use crate::planner::DEFAULT_FILAMENT_DIAMETER;
+
+/// Filament density assumed when `filament_density` isn't configured, that of PLA
+pub const DEFAULT_FILAMENT_DENSITY: f64 = 1.24;
+
+/// Cross section in mm² of filament with the given diameter
+pub fn filament_cross_section(diameter: f64) -> f64 {
+    std::f64::consts::PI * (diameter / 2.0).powf(2.0)
+}
+
+/// Weight in g of the given length of filament, with diameter in mm and density in g/cm³
+pub fn filament_weight(length: f64, diameter: f64, density: f64) -> f64 {
+    // mm³ to cm³
+    length * filament_cross_section(diameter) / 1000.0 * density
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct EstimationState {
+    /// Filament diameter in mm that flows and weights are based on
+    pub filament_diameter: f64,
+    pub sequences: Vec<EstimationSequence>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub pressure_advance: BTreeMap<String, Vec<f64>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub objects: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    #[serde(skip)]
+    underruns: Option<UnderrunModel>,
+    /// Print time attributed to object, kind and phase, keyed by folded stacks like
+    /// `Part_1;Perimeter;Cruise`, see [`EstimationState::folded_stacks`]
+    #[serde(skip)]
+    pub time_attribution: BTreeMap<String, f64>,
+    /// Time spent in each phase of the estimation, if profiling
+    #[serde(skip)]
+    pub timings: Option<PhaseTimings>,
+}
+
+/// Time the estimator itself spent in each phase, see [`Estimator::profile`]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PhaseTimings {
+    /// Parsing gcode lines. The estimator only sees this for [`Estimator::feed_line`], callers
+    /// parsing themselves can add their own time here.
+    pub parse: Duration,
+    /// Turning commands into moves with [`Planner::process_cmd`]
+    pub plan: Duration,
+    /// Running lookahead over planned moves and summarizing the results
+    pub lookahead: Duration,
+}
+
+/// Models the move queue between the host and the toolhead. The host produces moves at a fixed
+/// rate, but can only run `buffer_size` moves ahead of the toolhead. If the toolhead finishes
+/// its queued moves before the host has produced the next one, it stalls.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct UnderrunModel {
+    move_interval: f64,
+    buffer_size: usize,
+    starts: VecDeque<f64>,
+    host_time: f64,
+    end_time: f64,
+}
+
+impl UnderrunModel {
+    pub fn new(move_rate: f64, buffer_size: usize) -> UnderrunModel {
+        UnderrunModel {
+            move_interval: 1.0 / move_rate,
+            buffer_size: buffer_size.max(1),
+            ..Default::default()
+        }
+    }
+
+    /// Queues a move, returning the time the toolhead stalls waiting for it
+    fn add_move(&mut self, move_time: f64) -> f64 {
+        let mut host_time = self.host_time + self.move_interval;
+        if self.starts.len() >= self.buffer_size {
+            // The host can't produce a move until one in the queue has started
+            host_time = host_time.max(self.starts.pop_front().unwrap_or(0.0));
+        }
+        let start = self.end_time.max(host_time);
+        let stall = start - self.end_time;
+        self.starts.push_back(start);
+        self.host_time = host_time;
+        self.end_time = start + move_time;
+        stall
+    }
+
+    fn add_delay(&mut self, delay: f64) {
+        self.end_time += delay;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct EstimationSequence {
+    pub total_time: f64,
+    pub total_distance: f64,
+    pub total_extrude_distance: f64,
+    pub total_deposited_distance: f64,
+    /// Weight of the net extrusion in g
+    pub filament_weight: f64,
+    /// Filament used per extruder, only given if multiple extruders were used
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub extruder_usage: BTreeMap<String, FilamentUsage>,
+    pub max_flow: Option<f64>,
+    // Fastest the filament moved while printing, in mm/s
+    #[serde(skip)]
+    max_filament_speed: Option<f64>,
+    pub max_speed: Option<f64>,
+    pub num_moves: usize,
+    pub num_pauses: usize,
+    pub total_z_time: f64,
+    pub total_output_time: f64,
+    pub total_travel_time: f64,
+    pub total_travel_distance: f64,
+    pub num_travel_moves: usize,
+    /// Travel moves too short to reach their requested velocity
+    pub num_short_travel_moves: usize,
+    /// Travels including the retraction, Z hop and unretraction around them, see
+    /// [`TravelManeuvers`]
+    pub travel_maneuvers: TravelManeuvers,
+    // Maneuver moves since the last printing move
+    #[serde(skip)]
+    pending_maneuver: TravelManeuvers,
+    // Whether the last retraction wasn't followed by an unretraction yet
+    #[serde(skip)]
+    retracted: bool,
+    pub total_extrude_only_time: f64,
+    /// Extra time extrude-only moves took due to the extruder's limits, compared to moving at
+    /// the toolhead's limits
+    pub total_extruder_limited_time: f64,
+    /// Time spent retracting, unretracting and in Z hops while retracted
+    pub total_retraction_time: f64,
+    /// Moves pulling filament back, either slicer retractions or firmware `G10`s
+    pub num_retractions: usize,
+    /// Junctions so sharp the toolhead nearly stops, like direction reversals, see
+    /// [`SHARP_CORNER_VELOCITY`]
+    pub num_sharp_corners: usize,
+    pub total_underrun_time: f64,
+    pub phase_times: EstimationPhaseTimes,
+    pub speed_limit_times: SpeedLimitTimes,
+    pub kind_times: BTreeMap<String, f64>,
+    /// `kind_times` grouped by canonical kind, comparable across slicers
+    pub feature_times: BTreeMap<MoveKind, f64>,
+    pub speed_limited_by_planner: BTreeMap<String, SpeedLimitedMoves>,
+    #[serde(serialize_with = "serialize_layer_times")]
+    pub layer_times: BTreeMap<NotNan<f64>, f64>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub marker_layer_times: BTreeMap<usize, f64>,
+    /// Time into the sequence at which the last extrusion of each layer, by Z height, ended
+    #[serde(skip)]
+    pub layer_end_times: BTreeMap<NotNan<f64>, f64>,
+    /// Like `layer_end_times`, by slicer layer marker
+    #[serde(skip)]
+    pub marker_layer_end_times: BTreeMap<usize, f64>,
+}
+
+/// Filament used by a single extruder
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct FilamentUsage {
+    /// Net extrusion in mm
+    pub length: f64,
+    /// Weight in g
+    pub weight: f64,
+}
+
+/// Travel maneuvers: runs of moves between printed segments that include an XY travel, along
+/// with any retraction, wipe, Z hop and unretraction around it. Their time is the real cost of
+/// a travel, rather than just that of the XY move.
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct TravelManeuvers {
+    pub count: usize,
+    pub time: f64,
+    /// Time spent in the parts other than the XY travel moves
+    pub overhead_time: f64,
+}
+
+/// Moves that didn't reach their requested velocity, due to cornering or acceleration
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct SpeedLimitedMoves {
+    pub count: usize,
+    pub time: f64,
+}
+
+/// Highest junction speed in mm/s at which a corner counts as sharp
+pub const SHARP_CORNER_VELOCITY: f64 = 1.0;
+
+/// Longest extruder-only retraction still considered a retraction rather than e.g. a filament
+/// unload
+const MAX_RETRACTION_DISTANCE: f64 = 10.0;
+
+/// The part of a retraction a move is
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum RetractionPart {
+    Retract,
+    Unretract,
+    /// Lifting the nozzle while retracted, or lowering it again
+    ZHop,
+}
+
+impl RetractionPart {
+    /// Classifies moves generated by firmware retraction by their kind. Slicer retractions and
+    /// unretractions are short extruder-only moves pulling filament back or pushing it forward,
+    /// and their Z hops Z-only moves while retracted.
+    fn classify(m: &PlanningMove, kind: &str, retracted: bool) -> Option<RetractionPart> {
+        let retract = kind.starts_with("Firmware retract");
+        if retract || kind.starts_with("Firmware unretract") {
+            return Some(if kind.ends_with("Z hop") {
+                RetractionPart::ZHop
+            } else if retract {
+                RetractionPart::Retract
+            } else {
+                RetractionPart::Unretract
+            });
+        }
+        let d = m.delta();
+        if m.is_extrude_only_move() && d.w.abs() <= MAX_RETRACTION_DISTANCE {
+            return Some(if d.w < 0.0 {
+                RetractionPart::Retract
+            } else {
+                RetractionPart::Unretract
+            });
+        }
+        let z_only = m.is_kinematic_move() && d.x == 0.0 && d.y == 0.0 && d.w == 0.0;
+        (retracted && z_only).then_some(RetractionPart::ZHop)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct EstimationPhaseTimes {
+    pub acceleration: f64,
+    pub cruise: f64,
+    pub deceleration: f64,
+}
+
+/// Time spent in moves by what limited their top speed, see [`SpeedLimit`]
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct SpeedLimitTimes {
+    pub feedrate: f64,
+    pub velocity_limits: f64,
+    pub acceleration: f64,
+    pub cornering: f64,
+}
+
+fn serialize_layer_times<S: Serializer>(
+    lts: &BTreeMap<NotNan<f64>, f64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut seq = serializer.serialize_seq(Some(lts.len()))?;
+
+    for (z, t) in lts {
+        seq.serialize_element(&[z, t])?;
+    }
+
+    seq.end()
+}
+
+impl EstimationSequence {
+    /// Adds a move to the pending travel maneuver if it's part of one, otherwise ends it
+    fn add_maneuver_move(&mut self, m: &PlanningMove, retraction: Option<RetractionPart>) {
+        let d = m.delta();
+        let no_extrusion = !m.is_extrude_move();
+        let travel = m.is_kinematic_move() && no_extrusion && (d.x != 0.0 || d.y != 0.0);
+        let hop = m.is_kinematic_move() && no_extrusion && !travel;
+        let wipe = m.is_kinematic_move() && d.w < 0.0;
+        if !(travel || hop || wipe || retraction.is_some()) {
+            self.end_maneuver();
+            return;
+        }
+        let pending = &mut self.pending_maneuver;
+        pending.time += m.total_time();
+        if travel {
+            pending.count = 1;
+        } else {
+            pending.overhead_time += m.total_time();
+        }
+    }
+
+    /// Counts the pending travel maneuver, unless it didn't include any travel
+    fn end_maneuver(&mut self) {
+        let pending = std::mem::take(&mut self.pending_maneuver);
+        if pending.count > 0 {
+            let m = &mut self.travel_maneuvers;
+            m.count += 1;
+            m.time += pending.time;
+            m.overhead_time += pending.overhead_time;
+        }
+    }
+}
+
+impl EstimationState {
+    pub fn drain(&mut self, planner: &mut Planner) {
+        let mut ops = planner.drain_ready();
+        while let Some(o) = ops.next() {
+            self.add(ops.planner(), &o);
+        }
+    }
+
+    /// Lines of the folded stack format used by flamegraph tools, `object;kind;phase value`, with
+    /// values in whole microseconds. Frames outside of any object are under `(no object)`.
+    pub fn folded_stacks(&self) -> impl Iterator<Item = String> + '_ {
+        self.time_attribution
+            .iter()
+            .map(|(stack, t)| format!("{} {}", stack, (t * 1e6).round() as u64))
+    }
+
+    fn attribute(&mut self, object: Option<&str>, kind: &str, phase: &str, t: f64) {
+        if t <= 0.0 {
+            return;
+        }
+        // Semicolons separate frames, and the value follows the last space
+        let frame = |s: &str| s.replace(';', "_");
+        let stack = format!(
+            "{};{};{}",
+            frame(object.unwrap_or("(no object)")),
+            frame(kind),
+            frame(phase)
+        );
+        *self.time_attribution.entry(stack).or_insert(0.0) += t;
+    }
+
+    fn add(&mut self, planner: &Planner, op: &PlanningOperation) {
+        match op {
+            PlanningOperation::Move(m) => self.add_move(planner, m),
+            PlanningOperation::Delay(Delay::Pause(t)) => {
+                let t = t.as_secs_f64();
+                self.attribute(None, "Dwell", "Delay", t);
+                if let Some(underruns) = self.underruns.as_mut() {
+                    underruns.add_delay(t);
+                }
+                let seq = self.get_cur_seq();
+                seq.total_time += t;
+                let kind = "Dwell";
+                if let Some(kt) = seq.kind_times.get_mut(kind) {
+                    *kt += t;
+                } else {
+                    seq.kind_times.insert(kind.to_string(), t);
+                }
+            }
+            PlanningOperation::Delay(Delay::Indeterminate(t, k)) => {
+                // If current sequence has moves or there is no sequence, make a new one
+                if self
+                    .sequences
+                    .last()
+                    .map(|s| s.num_moves != 0)
+                    .unwrap_or(true)
+                {
+                    self.sequences.push(EstimationSequence::default());
+                }
+                let t = t.as_secs_f64();
+                if let Some(underruns) = self.underruns.as_mut() {
+                    underruns.add_delay(t);
+                }
+                let kind = planner.kind_str(k).unwrap_or("Other");
+                self.attribute(None, kind, "Delay", t);
+                let seq = self.sequences.last_mut().unwrap();
+                seq.total_time += t;
+                if kind == "Pause" {
+                    seq.num_pauses += 1;
+                }
+                if let Some(kt) = seq.kind_times.get_mut(kind) {
+                    *kt += t;
+                } else {
+                    seq.kind_times.insert(kind.to_string(), t);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn get_cur_seq(&mut self) -> &mut EstimationSequence {
+        if self.sequences.is_empty() {
+            self.sequences.push(EstimationSequence::default());
+        }
+        self.sequences.last_mut().unwrap()
+    }
+
+    fn add_move(&mut self, planner: &Planner, m: &PlanningMove) {
+        let stall = self
+            .underruns
+            .as_mut()
+            .map_or(0.0, |u| u.add_move(m.total_time()));
+        let kind = planner.move_kind_label(m);
+        let object = planner.move_object_str(m);
+        if self.sequences.last().map_or(true, |s| s.num_moves == 0) {
+            self.attribute(object, kind, "Startup", 0.25);
+        }
+        self.attribute(object, kind, "Underrun", stall);
+        self.attribute(object, kind, "Acceleration", m.accel_time());
+        self.attribute(object, kind, "Cruise", m.cruise_time());
+        self.attribute(object, kind, "Deceleration", m.decel_time());
+        let seq = self.get_cur_seq();
+        if seq.num_moves == 0 {
+            seq.total_time += 0.25;
+        }
+        seq.total_time += stall;
+        seq.total_underrun_time += stall;
+
+        seq.total_time += m.total_time();
+        seq.total_distance += m.distance;
+        seq.total_extrude_distance += m.end.w - m.start.w;
+        seq.num_moves += 1;
+        seq.max_speed = Some(seq.max_speed.unwrap_or(0.0).max(m.peak_velocity()));
+
+        match (m.is_extrude_move(), m.is_kinematic_move()) {
+            (true, true) => {
+                seq.total_output_time += m.total_time();
+                // Wipes retract while moving, which doesn't deposit anything
+                seq.total_deposited_distance += m.delta().w.max(0.0);
+                // Converted to flow once the filament diameter is known, as slicers usually give
+                // it at the end of the file
+                let speed = m.delta().w / m.total_time();
+                seq.max_filament_speed = Some(seq.max_filament_speed.unwrap_or(0.0).max(speed));
+            }
+            (true, false) => {
+                seq.total_extrude_only_time += m.total_time();
+                if let Some(t) = m.extruder_unlimited_time {
+                    seq.total_extruder_limited_time += (m.total_time() - t).max(0.0);
+                }
+            }
+            (false, true) => {
+                seq.total_travel_time += m.total_time();
+                seq.total_travel_distance += m.distance;
+                seq.num_travel_moves += 1;
+                if matches!(
+                    m.speed_limit(),
+                    SpeedLimit::Cornering | SpeedLimit::Acceleration
+                ) {
+                    seq.num_short_travel_moves += 1;
+                }
+            }
+            _ => {}
+        }
+
+        {
+            let pt = &mut seq.phase_times;
+            pt.acceleration += m.accel_time();
+            pt.cruise += m.cruise_time();
+            pt.deceleration += m.decel_time();
+        }
+
+        {
+            let st = &mut seq.speed_limit_times;
+            let t = match m.speed_limit() {
+                SpeedLimit::Feedrate => &mut st.feedrate,
+                SpeedLimit::VelocityLimits => &mut st.velocity_limits,
+                SpeedLimit::Acceleration => &mut st.acceleration,
+                SpeedLimit::Cornering => &mut st.cornering,
+            };
+            *t += m.total_time();
+        }
+
+        let retraction = RetractionPart::classify(m, kind, seq.retracted);
+        if let Some(part) = retraction {
+            seq.total_retraction_time += m.total_time();
+            match part {
+                RetractionPart::Retract => {
+                    seq.num_retractions += 1;
+                    seq.retracted = true;
+                }
+                RetractionPart::Unretract => seq.retracted = false,
+                RetractionPart::ZHop => {}
+            }
+        }
+        if m.start_corner_v2 <= SHARP_CORNER_VELOCITY * SHARP_CORNER_VELOCITY {
+            seq.num_sharp_corners += 1;
+        }
+        seq.add_maneuver_move(m, retraction);
+        if let Some(t) = seq.kind_times.get_mut(kind) {
+            *t += m.total_time();
+        } else {
+            seq.kind_times.insert(kind.to_string(), m.total_time());
+        }
+
+        let extruder = planner.move_extruder_str(m);
+        let de = m.end.w - m.start.w;
+        if let Some(u) = seq.extruder_usage.get_mut(extruder) {
+            u.length += de;
+        } else {
+            let length = de;
+            let usage = FilamentUsage {
+                length,
+                weight: 0.0,
+            };
+            seq.extruder_usage.insert(extruder.to_string(), usage);
+        }
+
+        if !m.reached_requested() {
+            let limited = seq
+                .speed_limited_by_planner
+                .entry(kind.to_string())
+                .or_default();
+            limited.count += 1;
+            limited.time += m.total_time();
+        }
+
+        let printing = m.is_kinematic_move() && m.delta().w > 0.0;
+        if let Some(layer) = m.layer {
+            *seq.marker_layer_times.entry(layer).or_insert(0.0) += m.total_time();
+            if printing {
+                seq.marker_layer_end_times.insert(layer, seq.total_time);
+            }
+        }
+
+        if (m.start.z - m.end.z).abs() < f64::EPSILON {
+            let z = NotNan::new((m.start.z * 1000.0).round() / 1000.0).unwrap();
+            *seq.layer_times.entry(z).or_insert(0.0) += m.total_time();
+            if printing {
+                seq.layer_end_times.insert(z, seq.total_time);
+            }
+        } else {
+            seq.total_z_time += m.total_time();
+        }
+    }
+}
+
+/// Streaming estimation: commands can be fed as they arrive, e.g. while a file is being
+/// uploaded, with a partial total available at any time
+#[derive(Debug)]
+pub struct Estimator {
+    planner: Planner,
+    state: EstimationState,
+    commands: usize,
+    moves: usize,
+    drain_interval: usize,
+}
+
+impl Estimator {
+    pub fn new(planner: Planner) -> Estimator {
+        Estimator {
+            planner,
+            state: EstimationState::default(),
+            commands: 0,
+            moves: 0,
+            drain_interval: DEFAULT_DRAIN_INTERVAL,
+        }
+    }
+
+    /// Sets the number of commands fed between summarizing the planned moves, see
+    /// [`DEFAULT_DRAIN_INTERVAL`]
+    pub fn drain_interval(mut self, interval: usize) -> Self {
+        self.drain_interval = interval.max(1);
+        self
+    }
+
+    /// Models stalls caused by the host not keeping up with short moves, see [`UnderrunModel`]
+    pub fn underrun_model(mut self, model: Option<UnderrunModel>) -> Self {
+        self.state.underruns = model;
+        self
+    }
+
+    /// Records the time spent in each phase, reported in [`EstimationState::timings`]
+    pub fn profile(mut self, enabled: bool) -> Self {
+        self.state.timings = enabled.then(PhaseTimings::default);
+        self
+    }
+
+    pub fn feed(&mut self, cmd: &GCodeCommand) {
+        self.plan(|planner| planner.process_cmd(cmd));
+    }
+
+    /// Feeds a move given as positions rather than as gcode, see [`Planner::plan_move`]
+    pub fn feed_move(&mut self, start: Option<DVec4>, end: DVec4, velocity: f64) {
+        self.plan(|planner| planner.plan_move(start, end, velocity));
+    }
+
+    fn plan(&mut self, f: impl FnOnce(&mut Planner) -> usize) {
+        let start = self.state.timings.is_some().then(Instant::now);
+        self.moves += f(&mut self.planner);
+        self.commands += 1;
+        if let (Some(timings), Some(start)) = (self.state.timings.as_mut(), start) {
+            timings.plan += start.elapsed();
+        }
+        if self.commands % self.drain_interval == 0 {
+            self.drain();
+        }
+    }
+
+    pub fn feed_line(&mut self, line: &str) -> Result<(), GCodeParseError> {
+        let start = self.state.timings.is_some().then(Instant::now);
+        let cmd = parse_gcode(line)?;
+        if let (Some(timings), Some(start)) = (self.state.timings.as_mut(), start) {
+            timings.parse += start.elapsed();
+        }
+        self.feed(&cmd);
+        Ok(())
+    }
+
+    fn drain(&mut self) {
+        let start = Instant::now();
+        self.state.drain(&mut self.planner);
+        if let Some(timings) = self.state.timings.as_mut() {
+            timings.lookahead += start.elapsed();
+        }
+    }
+
+    /// Number of planning operations produced so far, including fills for commands that don't
+    /// move. See [`Planner::num_moves`] for the number of moves.
+    pub fn num_operations(&self) -> usize {
+        self.moves
+    }
+
+    /// The planner commands are fed to
+    pub fn planner(&self) -> &Planner {
+        &self.planner
+    }
+
+    /// Time of everything planned so far. Moves still waiting on lookahead aren't included.
+    pub fn partial_time(&self) -> f64 {
+        self.state.sequences.iter().map(|s| s.total_time).sum()
+    }
+
+    pub fn finish(mut self) -> EstimationState {
+        self.planner.finalize();
+        self.drain();
+        let diameter = self.planner.filament_diameter();
+        self.state.filament_diameter = diameter;
+        self.state.pressure_advance = self.planner.pressure_advance;
+        self.state.objects = self.planner.objects;
+        let density = self.planner.toolhead_state.limits.filament_density;
+        let density = density.unwrap_or(DEFAULT_FILAMENT_DENSITY);
+        for seq in self.state.sequences.iter_mut() {
+            seq.end_maneuver();
+            let cross_section = filament_cross_section(diameter);
+            seq.max_flow = seq.max_filament_speed.map(|v| v * cross_section);
+            seq.filament_weight = filament_weight(seq.total_extrude_distance, diameter, density);
+            for (kind, t) in seq.kind_times.iter() {
+                *seq.feature_times
+                    .entry(MoveKind::classify(kind))
+                    .or_insert(0.0) += t;
+            }
+            if seq.extruder_usage.len() <= 1 {
+                seq.extruder_usage.clear();
+            }
+            for u in seq.extruder_usage.values_mut() {
+                u.weight = filament_weight(u.length, diameter, density);
+            }
+        }
+        let warnings = self.planner.toolhead_state.warnings.iter();
+        self.state.warnings = warnings.map(|w| w.to_string()).collect();
+        self.state
+    }
+}
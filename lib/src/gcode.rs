@@ -2,6 +2,7 @@ use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::io::{self, BufRead};
 
+use regex::Regex;
 use thiserror::Error;
 
 #[derive(Debug, PartialEq, PartialOrd, Clone)]
@@ -17,6 +18,8 @@ pub enum GCodeOperation {
     Traditional {
         letter: char,
         code: u16,
+        /// Subcode of fractional codes, e.g. the `1` in `G92.1`
+        minor: Option<u16>,
         params: GCodeTraditionalParams,
     },
     Extended {
@@ -57,9 +60,13 @@ impl Display for GCodeOperation {
             GCodeOperation::Traditional {
                 letter,
                 code,
+                minor,
                 params,
             } => {
                 write!(f, "{}{}", letter, code)?;
+                if let Some(minor) = minor {
+                    write!(f, ".{}", minor)?;
+                }
                 if !params.is_empty() {
                     write!(f, " ")?;
                     params.fmt(f)?;
@@ -193,39 +200,288 @@ impl Display for GCodeCommand {
 
 #[derive(Error, Debug)]
 pub enum GCodeReadError {
-    #[error("IO error")]
+    #[error("IO error: {0}")]
     IO(#[from] io::Error),
-    #[error("invalid gcode")]
-    ParseError(#[from] GCodeParseError),
+    #[error("line {line} isn't valid UTF-8")]
+    InvalidUtf8 { line: usize },
+    #[error("invalid gcode on line {line}: {content}")]
+    ParseError {
+        line: usize,
+        content: String,
+        #[source]
+        source: GCodeParseError,
+    },
+}
+
+impl GCodeReadError {
+    /// Whether the error is limited to a single line, so reading can continue after it
+    pub fn is_line_error(&self) -> bool {
+        !matches!(self, GCodeReadError::IO(_))
+    }
+}
+
+/// Byte order mark some editors put at the start of UTF-8 files
+pub const UTF8_BOM: char = '\u{feff}';
+
+/// Reads a line ending in `\n`, `\r\n`, or a lone `\r` into `buf`, including the line ending.
+/// Returns the number of bytes read, 0 at the end of the input.
+pub fn read_line<R: BufRead>(rdr: &mut R, buf: &mut Vec<u8>) -> io::Result<usize> {
+    let mut read = 0;
+    let mut pending_cr = false;
+    loop {
+        let available = match rdr.fill_buf() {
+            Ok(available) => available,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            return Ok(read);
+        }
+        if pending_cr {
+            if available[0] == b'\n' {
+                buf.push(b'\n');
+                rdr.consume(1);
+                read += 1;
+            }
+            return Ok(read);
+        }
+        match available.iter().position(|&c| c == b'\n' || c == b'\r') {
+            Some(i) => {
+                let c = available[i];
+                buf.extend_from_slice(&available[..=i]);
+                rdr.consume(i + 1);
+                read += i + 1;
+                if c == b'\n' {
+                    return Ok(read);
+                }
+                // Might be followed by a `\n`
+                pending_cr = true;
+            }
+            None => {
+                let n = available.len();
+                buf.extend_from_slice(available);
+                rdr.consume(n);
+                read += n;
+            }
+        }
+    }
+}
+
+/// Whether a line starts (`true`) or ends (`false`) an embedded thumbnail, written by
+/// PrusaSlicer and derivatives as `; thumbnail begin 16x16 1234` up to `; thumbnail end`, or
+/// with e.g. `thumbnail_JPG` for other image formats
+pub fn thumbnail_marker(line: &[u8]) -> Option<bool> {
+    let rest = trim_ascii(line.strip_prefix(b";")?);
+    let rest = rest.strip_prefix(b"thumbnail")?;
+    let rest = match rest.iter().position(|&c| c == b' ') {
+        Some(i)
+            if rest[..i]
+                .iter()
+                .all(|&c| c == b'_' || c.is_ascii_alphabetic()) =>
+        {
+            &rest[i..]
+        }
+        _ => return None,
+    };
+    match trim_ascii(rest) {
+        r if r.starts_with(b"begin") => Some(true),
+        b"end" => Some(false),
+        _ => None,
+    }
+}
+
+/// Follows embedded thumbnails line by line, see [`thumbnail_marker`]
+#[derive(Debug, Default)]
+pub struct ThumbnailTracker {
+    start: Option<usize>,
+    unterminated: Option<usize>,
+}
+
+impl ThumbnailTracker {
+    /// Whether line `n` belongs to a thumbnail. Thumbnails only consist of comments, so a block
+    /// missing its end marker ends at the first line that isn't one.
+    pub fn is_thumbnail(&mut self, line: &[u8], n: usize) -> bool {
+        match (thumbnail_marker(line), self.start) {
+            (Some(true), _) => {
+                self.start = Some(n);
+                true
+            }
+            (Some(false), _) => {
+                self.start = None;
+                true
+            }
+            (None, Some(start)) => {
+                let content = trim_ascii(line);
+                if content.is_empty() || content.starts_with(b";") {
+                    return true;
+                }
+                self.unterminated.get_or_insert(start);
+                self.start = None;
+                false
+            }
+            (None, None) => false,
+        }
+    }
+
+    /// Line number of the first thumbnail that ended without its end marker
+    pub fn unterminated_line(&self) -> Option<usize> {
+        self.unterminated
+    }
+}
+
+fn trim_ascii(s: &[u8]) -> &[u8] {
+    let start = s
+        .iter()
+        .position(|c| !c.is_ascii_whitespace())
+        .unwrap_or(s.len());
+    let end = s
+        .iter()
+        .rposition(|c| !c.is_ascii_whitespace())
+        .map_or(start, |i| i + 1);
+    &s[start..end]
 }
 
 pub struct GCodeReader<R: BufRead> {
     rdr: R,
     buf: String,
+    line: usize,
+    decimal_comma: bool,
+    comma_decimal_line: Option<usize>,
+    line_errors: usize,
+    thumbnails: ThumbnailTracker,
 }
 impl<R: BufRead> GCodeReader<R> {
     pub fn new(rdr: R) -> GCodeReader<R> {
         GCodeReader {
             rdr,
             buf: String::new(),
+            line: 0,
+            decimal_comma: false,
+            comma_decimal_line: None,
+            line_errors: 0,
+            thumbnails: ThumbnailTracker::default(),
         }
     }
 
+    /// Accept commas as decimal separators in traditional gcode parameters, e.g. `G1 X10,5`
+    pub fn decimal_comma(mut self, enabled: bool) -> Self {
+        self.decimal_comma = enabled;
+        self
+    }
+
     pub fn buffer(&self) -> &str {
         self.buf.as_str()
     }
+
+    /// First line number, counting from 1, that seemed to use comma decimal separators which
+    /// were not accepted and thus not parsed
+    pub fn comma_decimal_line(&self) -> Option<usize> {
+        self.comma_decimal_line
+    }
+
+    /// Number of lines that couldn't be read as gcode so far
+    pub fn line_errors(&self) -> usize {
+        self.line_errors
+    }
+
+    /// First line number of a thumbnail that lacked its end marker, see
+    /// [`ThumbnailTracker::is_thumbnail`]
+    pub fn unterminated_thumbnail_line(&self) -> Option<usize> {
+        self.thumbnails.unterminated_line()
+    }
+
+    fn parse_line(&mut self) -> Result<GCodeCommand, GCodeParseError> {
+        let cmd = parse_gcode(&self.buf)?;
+        if !matches!(
+            cmd.op,
+            GCodeOperation::Move { .. } | GCodeOperation::Traditional { .. }
+        ) {
+            return Ok(cmd);
+        }
+        let code = self.buf.split(';').next().unwrap_or_default();
+        let rewritten = match comma_decimal_params(code) {
+            Some(rewritten) => rewritten,
+            None => return Ok(cmd),
+        };
+        if !self.decimal_comma {
+            self.comma_decimal_line.get_or_insert(self.line);
+            return Ok(cmd);
+        }
+        parse_gcode(&format!("{}{}", rewritten, &self.buf[code.len()..]))
+    }
+}
+
+/// Traditional commands whose parameters are free text rather than numbers
+const TEXT_COMMANDS: [&str; 2] = ["M117", "M118"];
+
+/// Rewrites numeric parameters with comma decimal separators in a motion or setting command to
+/// use dots, e.g. `G1 X10,5` to `G1 X10.5`. Returns `None` if there are none.
+fn comma_decimal_params(code: &str) -> Option<String> {
+    lazy_static! {
+        static ref RE_COMMA_PARAM: Regex = Regex::new(r"^[A-Za-z][+-]?\d+,\d+$").unwrap();
+    }
+    let mut words = code.split_whitespace();
+    let command = words.next()?.to_ascii_uppercase();
+    if !(command.starts_with('G') || command.starts_with('M'))
+        || TEXT_COMMANDS.contains(&command.as_str())
+    {
+        return None;
+    }
+    let mut found = false;
+    let mut rewritten = command;
+    for word in words {
+        rewritten.push(' ');
+        if RE_COMMA_PARAM.is_match(word) {
+            found = true;
+            rewritten.push_str(&word.replace(',', "."));
+        } else {
+            rewritten.push_str(word);
+        }
+    }
+    found.then_some(rewritten)
 }
 
 impl<R: BufRead> Iterator for GCodeReader<R> {
     type Item = Result<GCodeCommand, GCodeReadError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.buf.clear();
-        match self.rdr.read_line(&mut self.buf) {
-            Ok(0) => None,
-            Ok(_) => Some(parse_gcode(&self.buf).map_err(|e| e.into())),
-            Err(e) => Some(Err(GCodeReadError::IO(e))),
+        let mut buf = std::mem::take(&mut self.buf).into_bytes();
+        buf.clear();
+        match read_line(&mut self.rdr, &mut buf) {
+            Ok(0) => return None,
+            Ok(_) => self.line += 1,
+            Err(e) => return Some(Err(GCodeReadError::IO(e))),
+        }
+        let line = self.line;
+        // Thumbnails are long blocks of base64 comments, not worth parsing
+        if self.thumbnails.is_thumbnail(&buf, line) {
+            buf.clear();
+            self.buf = String::from_utf8(buf).unwrap_or_default();
+            return Some(Ok(GCodeCommand {
+                op: GCodeOperation::Nop,
+                comment: None,
+            }));
         }
+        self.buf = match String::from_utf8(buf) {
+            Ok(s) => s,
+            Err(_) => {
+                self.line_errors += 1;
+                return Some(Err(GCodeReadError::InvalidUtf8 { line }));
+            }
+        };
+        // Files saved by some Windows editors start with a byte order mark
+        if line == 1 && self.buf.starts_with(UTF8_BOM) {
+            self.buf.drain(..UTF8_BOM.len_utf8());
+        }
+        let res = self.parse_line();
+        if res.is_err() {
+            self.line_errors += 1;
+        }
+        Some(res.map_err(|source| GCodeReadError::ParseError {
+            line,
+            content: self.buf.trim_end().to_string(),
+            source,
+        }))
     }
 }
 
@@ -320,10 +576,20 @@ mod parser {
             Ok((value, processed)) => (s.slice(processed..), value),
             Err(_) => return Err(Err::Error(Error::from_error_kind(s, ErrorKind::Digit))),
         };
+        let (s, minor) = opt(minor_code)(s)?;
         let (s, _) = skip_space(s)?;
         let (s, params) = separated_list0(space1, traditional_param)(s)?;
         let (s, comment) = opt(comment)(s)?;
-        Ok((s, (map_traditional(letter, code, params), comment)))
+        Ok((s, (map_traditional(letter, code, minor, params), comment)))
+    }
+
+    fn minor_code(s: &str) -> IResult<&str, u16> {
+        let (s, _) = char('.')(s)?;
+        match lexical_core::parse_partial::<u16>(s.as_bytes()) {
+            Ok((_, 0)) => Err(Err::Error(Error::from_error_kind(s, ErrorKind::Digit))),
+            Ok((value, processed)) => Ok((s.slice(processed..), value)),
+            Err(_) => Err(Err::Error(Error::from_error_kind(s, ErrorKind::Digit))),
+        }
     }
 
     fn traditional_param(s: &str) -> IResult<&str, (char, &str)> {
@@ -332,9 +598,14 @@ mod parser {
         Ok((s, (letter.to_ascii_uppercase(), value)))
     }
 
-    fn map_traditional(letter: char, code: u16, params: Vec<(char, &str)>) -> GCodeOperation {
-        match (letter, code) {
-            ('G', 0 | 1) => {
+    fn map_traditional(
+        letter: char,
+        code: u16,
+        minor: Option<u16>,
+        params: Vec<(char, &str)>,
+    ) -> GCodeOperation {
+        match (letter, code, minor) {
+            ('G', 0 | 1, None) => {
                 let mut x = None;
                 let mut y = None;
                 let mut z = None;
@@ -361,6 +632,7 @@ mod parser {
             _ => GCodeOperation::Traditional {
                 letter,
                 code,
+                minor,
                 params: GCodeTraditionalParams(
                     params
                         .into_iter()
@@ -398,14 +670,14 @@ mod parser {
         take_while1(|c: char| c.is_alphabetic() || c == '_')(s)
     }
 
-    fn extended_param(s: &str) -> IResult<&str, (&str, Cow<str>)> {
+    fn extended_param(s: &str) -> IResult<&str, (&str, Cow<'_, str>)> {
         let (s, k) = take_until("=")(s)?;
         let (s, _) = tag("=")(s)?;
         let (s, v) = maybe_quoted_string(s)?;
         Ok((s, (k, v)))
     }
 
-    fn maybe_quoted_string(s: &str) -> IResult<&str, Cow<str>> {
+    fn maybe_quoted_string(s: &str) -> IResult<&str, Cow<'_, str>> {
         // Implement shlex non-posix like argument parsing, as used in Klipper
         let quoted = map(
             tuple((char('"'), take_till(|c| c == '"'), char('"'))),
@@ -1,10 +1,25 @@
 use std::collections::HashMap;
 
+use serde::{Serialize, Serializer};
+
+/// Where move kinds are derived from
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum KindSource {
+    /// Only `;TYPE:` markers set the kind
+    #[default]
+    TypeOnly,
+    /// Any comment on a move line is also treated as a kind
+    Comment,
+    /// Kinds aren't tracked
+    None,
+}
+
 #[derive(Debug, Default)]
 pub struct KindTracker {
     pub i2k: HashMap<String, u16>,
     pub k2i: HashMap<u16, String>,
     pub current_kind: Option<Kind>,
+    pub source: KindSource,
 }
 
 impl KindTracker {
@@ -28,7 +43,15 @@ impl KindTracker {
         self.k2i.get(&k.0).expect("missing kind")
     }
 
+    /// The canonical kind of a tracked kind, see [`MoveKind::classify`]
+    pub fn canonical_kind(&self, k: Kind) -> MoveKind {
+        MoveKind::classify(self.resolve_kind(k))
+    }
+
     pub fn kind_from_comment(&mut self, comment: &Option<String>) -> Option<Kind> {
+        if self.source != KindSource::Comment {
+            return self.current_kind;
+        }
         comment
             .as_ref()
             .map(|s| s.trim())
@@ -44,9 +67,96 @@ impl KindTracker {
     }
 
     pub fn set_current(&mut self, kind: Option<Kind>) {
-        self.current_kind = kind;
+        if self.source != KindSource::None {
+            self.current_kind = kind;
+        }
     }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct Kind(u16);
+
+/// Slicer-independent kind of a move. Slicers name the same features differently, e.g. the
+/// outermost wall is `External perimeter` in PrusaSlicer, `Outer wall` in OrcaSlicer and
+/// `WALL-OUTER` in Cura, which [`MoveKind::classify`] maps to the same variant.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MoveKind {
+    Perimeter,
+    ExternalPerimeter,
+    Infill,
+    SolidInfill,
+    Support,
+    Bridge,
+    Skirt,
+    Travel,
+    Retract,
+    /// Any other kind, with the name as given
+    Custom(String),
+}
+
+impl MoveKind {
+    /// Maps a kind name, as given by `;TYPE:` markers, comments or the planner itself, to its
+    /// canonical kind. Matching ignores case and treats `-` and `_` as spaces.
+    pub fn classify(kind: &str) -> MoveKind {
+        let name = kind.trim().to_lowercase().replace(['-', '_'], " ");
+        match name.as_str() {
+            "external perimeter" | "outer wall" | "wall outer" | "outer perimeter" => {
+                MoveKind::ExternalPerimeter
+            }
+            "perimeter" | "inner wall" | "wall inner" | "inner perimeter"
+            | "overhang perimeter" | "overhang wall" => MoveKind::Perimeter,
+            "internal infill" | "sparse infill" | "infill" | "fill" => MoveKind::Infill,
+            "solid infill"
+            | "top solid infill"
+            | "internal solid infill"
+            | "top surface"
+            | "bottom surface"
+            | "skin"
+            | "solid fill"
+            | "solid layer" => MoveKind::SolidInfill,
+            "bridge infill" | "internal bridge infill" | "bridge" | "internal bridge" => {
+                MoveKind::Bridge
+            }
+            "support material"
+            | "support material interface"
+            | "support"
+            | "support interface"
+            | "support transition"
+            | "dense support" => MoveKind::Support,
+            "skirt" | "skirt/brim" | "brim" => MoveKind::Skirt,
+            "travel" | "object travel" | "move to next layer" => MoveKind::Travel,
+            n if n.starts_with("firmware retract")
+                || n.starts_with("firmware unretract")
+                || n == "retract"
+                || n == "unretract" =>
+            {
+                MoveKind::Retract
+            }
+            _ => MoveKind::Custom(kind.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for MoveKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MoveKind::Perimeter => write!(f, "Perimeter"),
+            MoveKind::ExternalPerimeter => write!(f, "External perimeter"),
+            MoveKind::Infill => write!(f, "Infill"),
+            MoveKind::SolidInfill => write!(f, "Solid infill"),
+            MoveKind::Support => write!(f, "Support"),
+            MoveKind::Bridge => write!(f, "Bridge"),
+            MoveKind::Skirt => write!(f, "Skirt"),
+            MoveKind::Travel => write!(f, "Travel"),
+            MoveKind::Retract => write!(f, "Retract"),
+            MoveKind::Custom(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+// Serialized by name, so maps keyed by kind stay JSON objects
+impl Serialize for MoveKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
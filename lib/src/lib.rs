@@ -2,9 +2,11 @@
 extern crate lazy_static;
 
 pub mod arcs;
+pub mod estimation;
 pub mod firmware_retraction;
 pub mod gcode;
 mod kind_tracker;
+pub mod moonraker;
 pub mod planner;
 pub mod slicer;
 
@@ -0,0 +1,217 @@
+//! Printer limits from a Klipper config, as reported by Moonraker's
+//! `printer/objects/query?configfile=settings` endpoint.
+
+use std::collections::BTreeMap;
+
+use glam::DVec3;
+use serde::Deserialize;
+
+use crate::firmware_retraction::FirmwareRetractionOptions;
+use crate::planner::{parse_probe_count, AxisHoming, ExtruderLimits, MoveChecker, PrinterLimits};
+
+#[derive(Debug, Deserialize)]
+struct MoonrakerConfig {
+    printer: PrinterConfig,
+    extruder: ExtruderConfig,
+    firmware_retraction: Option<FirmwareRetractionConfig>,
+    gcode_arcs: Option<GcodeArcsConfig>,
+    bed_mesh: Option<BedMeshConfig>,
+    stepper_x: Option<StepperConfig>,
+    stepper_y: Option<StepperConfig>,
+    stepper_z: Option<StepperConfig>,
+    #[serde(flatten)]
+    other: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PrinterConfig {
+    kinematics: Option<String>,
+    max_velocity: f64,
+    max_accel: f64,
+    max_accel_to_decel: Option<f64>,
+    minimum_cruise_ratio: Option<f64>,
+    square_corner_velocity: f64,
+
+    max_x_velocity: Option<f64>,
+    max_x_accel: Option<f64>,
+    max_y_velocity: Option<f64>,
+    max_y_accel: Option<f64>,
+    max_z_velocity: Option<f64>,
+    max_z_accel: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExtruderConfig {
+    max_extrude_only_velocity: f64,
+    max_extrude_only_accel: f64,
+    instantaneous_corner_velocity: f64,
+    max_extrude_only_distance: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FirmwareRetractionConfig {
+    retract_length: f64,
+    unretract_extra_length: f64,
+    unretract_speed: f64,
+    retract_speed: f64,
+    #[serde(default)]
+    lift_z: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct GcodeArcsConfig {
+    resolution: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StepperConfig {
+    // Unset when homing with a probe as the virtual endstop
+    position_endstop: Option<f64>,
+    homing_speed: Option<f64>,
+    second_homing_speed: Option<f64>,
+    homing_retract_dist: Option<f64>,
+    homing_positive_dir: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BedMeshConfig {
+    // Either a list or a string, depending on the Klipper version
+    probe_count: Option<serde_json::Value>,
+}
+
+impl PrinterLimits {
+    /// Limits from the `configfile.settings` object of a Moonraker query, on top of the
+    /// defaults
+    pub fn from_moonraker_settings(
+        settings: &serde_json::Value,
+    ) -> Result<PrinterLimits, serde_json::Error> {
+        let mut limits = PrinterLimits::default();
+        limits.apply_moonraker_settings(settings)?;
+        Ok(limits)
+    }
+
+    /// Limits from Moonraker JSON: either a full `printer/objects/query?configfile=settings`
+    /// response, or just the `configfile.settings` object of one
+    pub fn from_moonraker_json(json: &str) -> Result<PrinterLimits, serde_json::Error> {
+        let value = serde_json::from_str::<serde_json::Value>(json)?;
+        let settings = value
+            .pointer("/result/status/configfile/settings")
+            .unwrap_or(&value);
+        Self::from_moonraker_settings(settings)
+    }
+
+    /// Applies the `configfile.settings` object of a Moonraker query to these limits
+    pub fn apply_moonraker_settings(
+        &mut self,
+        settings: &serde_json::Value,
+    ) -> Result<(), serde_json::Error> {
+        let cfg = MoonrakerConfig::deserialize(settings)?;
+        self.kinematics = cfg.printer.kinematics;
+        self.set_max_velocity(cfg.printer.max_velocity);
+        self.set_max_acceleration(cfg.printer.max_accel);
+        if let Some(v) = cfg.printer.minimum_cruise_ratio {
+            self.set_minimum_cruise_ratio(v);
+        } else if let Some(v) = cfg.printer.max_accel_to_decel {
+            self.set_max_accel_to_decel(v);
+        }
+        self.set_square_corner_velocity(cfg.printer.square_corner_velocity);
+        self.set_instant_corner_velocity(cfg.extruder.instantaneous_corner_velocity);
+
+        // Like Klipper, a `[gcode_arcs]` section without a resolution uses 1mm segments
+        self.mm_per_arc_segment = cfg.gcode_arcs.map(|cfg| cfg.resolution.unwrap_or(1.0));
+        self.max_extrude_only_distance = cfg.extruder.max_extrude_only_distance;
+        self.bed_mesh_probe_points =
+            cfg.bed_mesh
+                .and_then(|cfg| cfg.probe_count)
+                .and_then(|v| match v {
+                    serde_json::Value::String(s) => parse_probe_count(&s),
+                    v => parse_probe_count(&v.to_string().replace(['[', ']'], "")),
+                });
+
+        self.firmware_retraction = cfg.firmware_retraction.map(|fr| FirmwareRetractionOptions {
+            retract_length: fr.retract_length,
+            unretract_extra_length: fr.unretract_extra_length,
+            unretract_speed: fr.unretract_speed,
+            retract_speed: fr.retract_speed,
+            lift_z: fr.lift_z,
+        });
+
+        let steppers = [
+            ("x", cfg.stepper_x),
+            ("y", cfg.stepper_y),
+            ("z", cfg.stepper_z),
+        ];
+        for (axis, stepper) in steppers {
+            if let Some((s, position_endstop)) =
+                stepper.and_then(|s| s.position_endstop.map(|p| (s, p)))
+            {
+                let homing = AxisHoming {
+                    position_endstop,
+                    homing_speed: s.homing_speed,
+                    second_homing_speed: s.second_homing_speed,
+                    homing_retract_dist: s.homing_retract_dist,
+                    homing_positive_dir: s.homing_positive_dir.unwrap_or(false),
+                };
+                self.homing.insert(axis.to_string(), homing);
+            }
+        }
+
+        let limits = [
+            (
+                DVec3::X,
+                cfg.printer.max_x_velocity,
+                cfg.printer.max_x_accel,
+            ),
+            (
+                DVec3::Y,
+                cfg.printer.max_y_velocity,
+                cfg.printer.max_y_accel,
+            ),
+            (
+                DVec3::Z,
+                cfg.printer.max_z_velocity,
+                cfg.printer.max_z_accel,
+            ),
+        ];
+
+        for (axis, m, a) in limits {
+            if let (Some(max_velocity), Some(max_accel)) = (m, a) {
+                self.move_checkers.push(MoveChecker::AxisLimiter {
+                    axis,
+                    max_velocity,
+                    max_accel,
+                });
+            }
+        }
+
+        self.move_checkers.push(MoveChecker::ExtruderLimiter {
+            max_velocity: cfg.extruder.max_extrude_only_velocity,
+            max_accel: cfg.extruder.max_extrude_only_accel,
+        });
+
+        // Additional extruders are named `extruder1`, `extruder2`, etc.
+        let extra_extruders = cfg
+            .other
+            .into_iter()
+            .filter(|(name, _)| {
+                name.strip_prefix("extruder").map_or(false, |n| {
+                    !n.is_empty() && n.chars().all(|c| c.is_ascii_digit())
+                })
+            })
+            .filter_map(|(name, v)| Some((name, serde_json::from_value::<ExtruderConfig>(v).ok()?)))
+            .collect::<Vec<_>>();
+        if !extra_extruders.is_empty() {
+            let to_limits = |e: &ExtruderConfig| ExtruderLimits {
+                max_velocity: e.max_extrude_only_velocity,
+                max_accel: e.max_extrude_only_accel,
+                instant_corner_velocity: e.instantaneous_corner_velocity,
+            };
+            self.extruders
+                .insert("extruder".into(), to_limits(&cfg.extruder));
+            for (name, e) in extra_extruders {
+                self.extruders.insert(name, to_limits(&e));
+            }
+        }
+        Ok(())
+    }
+}
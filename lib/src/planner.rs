@@ -1,17 +1,28 @@
-use std::collections::VecDeque;
-use std::f64::EPSILON;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::time::Duration;
 
 use crate::arcs::ArcState;
 pub use crate::firmware_retraction::FirmwareRetractionOptions;
 use crate::firmware_retraction::FirmwareRetractionState;
-use crate::gcode::{GCodeCommand, GCodeOperation};
+use crate::gcode::{GCodeCommand, GCodeExtendedParams, GCodeOperation};
 
+pub use crate::kind_tracker::KindSource;
 use crate::kind_tracker::{Kind, KindTracker};
+use crate::slicer::{parse_filament_diameter, CuraSettings, FeatureAccelerations};
 use glam::Vec4Swizzles;
 use glam::{DVec3 as Vec3, DVec4 as Vec4};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+/// Number of commands processed between draining the planner of ready operations. Draining only
+/// yields moves lookahead has fully resolved, so the interval doesn't affect results. Longer
+/// intervals keep more moves buffered, using more memory, while shorter ones process them in
+/// smaller batches, with more overhead.
+pub const DEFAULT_DRAIN_INTERVAL: usize = 1000;
+
+/// Filament diameter assumed when `filament_diameter` isn't configured or given by the slicer
+pub const DEFAULT_FILAMENT_DIAMETER: f64 = 1.75;
+
 #[derive(Debug)]
 pub struct Planner {
     operations: OperationSequence,
@@ -19,6 +30,58 @@ pub struct Planner {
     pub kind_tracker: KindTracker,
     pub firmware_retraction: Option<FirmwareRetractionState>,
     pub arc_state: ArcState,
+    // Limits as configured, restored by `SET_VELOCITY_LIMIT RESET=1`
+    config_limits: PrinterLimits,
+    /// Time assumed for each `PAUSE`/`M0`/`M1`
+    pub pause_time: Duration,
+    /// Time assumed for each point probed by `G29`/`BED_MESH_CALIBRATE`, which otherwise count
+    /// as indeterminate time
+    pub probe_point_time: Option<Duration>,
+    /// Time assumed for a `T<n>` that changes the active tool, e.g. on toolchangers and IDEX
+    pub tool_change_time: Option<Duration>,
+    /// Per-tool overrides of `tool_change_time`, by the number of the tool changed to
+    pub tool_change_times: BTreeMap<u16, Duration>,
+    /// Whether `G28` is planned as moves to the endstops of the axes with homing settings in
+    /// `PrinterLimits::homing`, rather than counted as indeterminate time
+    pub model_homing: bool,
+    /// Pressure advance values set per extruder, in order of appearance. Only informational,
+    /// as pressure advance doesn't affect toolhead motion
+    pub pressure_advance: BTreeMap<String, Vec<f64>>,
+    /// Names of objects defined with `EXCLUDE_OBJECT_DEFINE`
+    pub objects: Vec<String>,
+    /// Upper-cased names of objects whose moves are skipped, as when canceled with
+    /// `EXCLUDE_OBJECT`
+    pub excluded_objects: BTreeSet<String>,
+    /// Upper-cased name of the only object whose commands are timed. Everything outside it is
+    /// still planned to keep the toolhead state, but dropped from the results.
+    pub only_object: Option<String>,
+    // Upper-cased name of the object between `EXCLUDE_OBJECT_START` and `EXCLUDE_OBJECT_END`
+    current_object: Option<String>,
+    // Set between `EXCLUDE_OBJECT_END` and the next `EXCLUDE_OBJECT_START`, where travel moves
+    // go between objects rather than belonging to one
+    between_objects: bool,
+    // Set once `M82`/`M83` is seen, after which slicer headers no longer pick the extrusion mode
+    explicit_extrusion_mode: bool,
+    /// Accelerations applied when a `;TYPE:` marker switches feature, as the slicer intended
+    pub feature_accelerations: Option<FeatureAccelerations>,
+    /// Filament diameter given by the slicer settings in the file
+    pub detected_filament_diameter: Option<f64>,
+    // Cura's settings block seen so far
+    cura_settings: CuraSettings,
+    // Configured overhead not yet added, which happens with the first command
+    pending_overhead: Option<Duration>,
+    // States saved by `SAVE_GCODE_STATE`, by upper-cased name
+    saved_states: BTreeMap<String, SavedGCodeState>,
+}
+
+/// Toolhead state saved by `SAVE_GCODE_STATE`
+#[derive(Debug, Clone)]
+struct SavedGCodeState {
+    position: Vec4,
+    position_modes: [PositionMode; 4],
+    position_offset: Vec4,
+    velocity: f64,
+    limits: PrinterLimits,
 }
 
 impl Planner {
@@ -27,12 +90,34 @@ impl Planner {
             .firmware_retraction
             .as_ref()
             .map(|_| FirmwareRetractionState::default());
+        let pending_overhead = limits
+            .overhead_seconds
+            .filter(|s| *s > 0.0)
+            .map(Duration::from_secs_f64);
         Planner {
             operations: OperationSequence::default(),
-            toolhead_state: ToolheadState::from_limits(limits),
+            toolhead_state: ToolheadState::from_limits(limits.clone()),
             kind_tracker: KindTracker::new(),
             firmware_retraction,
             arc_state: ArcState::default(),
+            config_limits: limits,
+            pause_time: Duration::from_secs_f64(0.1),
+            probe_point_time: None,
+            tool_change_time: None,
+            tool_change_times: BTreeMap::new(),
+            model_homing: false,
+            pressure_advance: BTreeMap::new(),
+            objects: Vec::new(),
+            excluded_objects: BTreeSet::new(),
+            only_object: None,
+            current_object: None,
+            between_objects: false,
+            explicit_extrusion_mode: false,
+            feature_accelerations: None,
+            detected_filament_diameter: None,
+            cura_settings: CuraSettings::default(),
+            pending_overhead,
+            saved_states: BTreeMap::new(),
         }
     }
 
@@ -40,9 +125,94 @@ impl Planner {
     /// open move sequence.
     /// Returns the number of planning operations the command resulted in
     pub fn process_cmd(&mut self, cmd: &GCodeCommand) -> usize {
-        if let Some(m) = Self::is_dwell(cmd, &mut self.kind_tracker) {
+        if let Some(object) = self.only_object.as_ref() {
+            // The print overhead doesn't belong to any one object
+            self.pending_overhead = None;
+            if self.current_object.as_ref() != Some(object) {
+                // Moves of the object before and after others aren't connected
+                return self.plan_dropped(cmd, true);
+            }
+        }
+        if let Some(overhead) = self.pending_overhead.take() {
+            let kind = self.kind_tracker.get_kind("Overhead");
+            self.operations
+                .add_delay(Delay::Indeterminate(overhead, Some(kind)));
+            return 1 + self.process_cmd(cmd);
+        }
+        let excluded = self
+            .current_object
+            .as_ref()
+            .map_or(false, |o| self.excluded_objects.contains(o));
+        if excluded && Self::is_motion(cmd) {
+            // Like Klipper, drop moves of excluded objects. The gcode position follows them, so
+            // relative moves and absolute extrusion carry on from it, but the toolhead stays put
+            // until the first move after them.
+            let m = &mut self.toolhead_state;
+            let physical = m.position.xyz() + std::mem::take(&mut m.physical_offset);
+            let n = self.plan_dropped(cmd, false);
+            let m = &mut self.toolhead_state;
+            m.physical_offset = physical - m.position.xyz();
+            return n;
+        }
+        self.plan_cmd(cmd)
+    }
+
+    /// Plans a move given as positions rather than as gcode, from `start` to `end` at `velocity`
+    /// in mm/s. Without a start, the move continues from the current position.
+    /// Returns the number of planning operations the move resulted in
+    pub fn plan_move(&mut self, start: Option<Vec4>, end: Vec4, velocity: f64) -> usize {
+        let state = &mut self.toolhead_state;
+        if let Some(start) = start {
+            state.position = start;
+        }
+        state.set_speed(velocity);
+        let modes = std::mem::replace(&mut state.position_modes, [PositionMode::Absolute; 4]);
+        let m = state.perform_move(end.to_array().map(Some));
+        state.position_modes = modes;
+        self.check_extrude_only_distance(&m);
+        self.operations.add_move(m, &self.toolhead_state);
+        1
+    }
+
+    /// Plans a command to keep the toolhead state up to date, but drops the moves it results in,
+    /// leaving a single fill. With `stop`, the moves before and after dropped ones come to a stop
+    /// instead of joining at a corner.
+    fn plan_dropped(&mut self, cmd: &GCodeCommand, stop: bool) -> usize {
+        let ops = std::mem::take(&mut self.operations);
+        self.plan_cmd(cmd);
+        let dropped = std::mem::replace(&mut self.operations, ops);
+        if stop && dropped.num_moves > 0 {
+            self.operations.end_move_sequence();
+        } else {
+            self.operations.add_fill();
+        }
+        1
+    }
+
+    fn is_motion(cmd: &GCodeCommand) -> bool {
+        matches!(
+            cmd.op,
+            GCodeOperation::Move { .. }
+                | GCodeOperation::Traditional {
+                    letter: 'G',
+                    code: 2 | 3 | 10 | 11,
+                    minor: None,
+                    ..
+                }
+        )
+    }
+
+    fn plan_cmd(&mut self, cmd: &GCodeCommand) -> usize {
+        if let Some(n) = self.plan_homing(cmd) {
+            return n;
+        }
+        if let Some(m) = self.bed_mesh_delay(cmd) {
+            self.operations.add_delay(m);
+        } else if let Some(m) = Self::is_dwell(cmd, &mut self.kind_tracker, self.pause_time) {
             self.operations.add_delay(m);
         } else if let GCodeOperation::Move { x, y, z, e, f } = &cmd.op {
+            // The feedrate applies to the move on the same line, e.g. the retraction speed of
+            // `G1 E-1 F2400`, so it has to be set before the move is planned
             if let Some(v) = f {
                 self.toolhead_state.set_speed(v / 60.0);
             }
@@ -50,16 +220,43 @@ impl Planner {
             let move_kind = self.kind_tracker.kind_from_comment(&cmd.comment);
 
             if x.is_some() || y.is_some() || z.is_some() || e.is_some() {
+                if !self.toolhead_state.speed_set {
+                    self.toolhead_state
+                        .add_warning(PlannerWarning::MoveWithoutFeedrate);
+                }
                 let mut m = self.toolhead_state.perform_move([*x, *y, *z, *e]);
                 m.kind = move_kind;
+                if self.between_objects
+                    && self.kind_tracker.source != KindSource::None
+                    && m.is_kinematic_move()
+                    && !m.is_extrude_move()
+                {
+                    m.kind = Some(self.kind_tracker.get_kind("Object travel"));
+                }
+                self.check_extrude_only_distance(&m);
                 self.operations.add_move(m, &self.toolhead_state);
             } else {
                 self.operations.add_fill();
             }
+        } else if let GCodeOperation::Traditional {
+            letter,
+            code,
+            minor: Some(minor),
+            ..
+        } = &cmd.op
+        {
+            if let ('G', 92, 1) = (letter, code, minor) {
+                // Drop the G92 offsets, returning to machine coordinates
+                let m = &mut self.toolhead_state;
+                m.position += m.position_offset;
+                m.position_offset = Vec4::ZERO;
+            }
+            self.operations.add_fill();
         } else if let GCodeOperation::Traditional {
             letter,
             code,
             params,
+            ..
         } = &cmd.op
         {
             match (letter, code) {
@@ -105,30 +302,71 @@ impl Planner {
                     self.arc_state.set_plane(crate::arcs::Plane::YZ);
                 }
                 ('G', 92) => {
-                    if let Some(v) = params.get_number::<f64>('X') {
-                        self.toolhead_state.position.x = v;
-                    }
-                    if let Some(v) = params.get_number::<f64>('Y') {
-                        self.toolhead_state.position.y = v;
+                    let m = &mut self.toolhead_state;
+                    for (axis, &c) in ['X', 'Y', 'Z', 'E'].iter().enumerate() {
+                        if let Some(v) = params.get_number::<f64>(c) {
+                            m.position_offset[axis] += m.position[axis] - v;
+                            m.position[axis] = v;
+                        }
                     }
-                    if let Some(v) = params.get_number::<f64>('Z') {
-                        self.toolhead_state.position.z = v;
+                }
+                ('T', n) => {
+                    let name = match n {
+                        0 => "extruder".to_string(),
+                        n => format!("extruder{}", n),
+                    };
+                    let changed = self.toolhead_state.extruder != name;
+                    self.toolhead_state.activate_extruder(&name);
+                    let time = self
+                        .tool_change_times
+                        .get(n)
+                        .or(self.tool_change_time.as_ref());
+                    if let (true, Some(&time)) = (changed, time) {
+                        let kind = self.kind_tracker.get_kind("Tool change");
+                        self.operations
+                            .add_delay(Delay::Indeterminate(time, Some(kind)));
+                        return 1;
                     }
-                    if let Some(v) = params.get_number::<f64>('E') {
-                        self.toolhead_state.position.w = v;
+                }
+                ('M', 205) => {
+                    let limits = &mut self.toolhead_state.limits;
+                    if let Some(scv) = params.get_number::<f64>('S') {
+                        limits.set_square_corner_velocity(scv);
+                    } else {
+                        let x = params.get_number::<f64>('X');
+                        let y = params.get_number::<f64>('Y');
+                        let jd = match (x, y) {
+                            (Some(x), Some(y)) => Some(x.min(y)),
+                            (x, y) => x.or(y),
+                        };
+                        if let Some(jd) = jd {
+                            limits.set_junction_deviation(jd);
+                        }
                     }
                 }
-                ('M', 82) => self.toolhead_state.position_modes[3] = PositionMode::Absolute,
-                ('M', 83) => self.toolhead_state.position_modes[3] = PositionMode::Relative,
+                // Fan speeds don't affect motion, they're only tracked
+                ('M', 106) => {
+                    let speed = params.get_number::<f64>('S').unwrap_or(255.0);
+                    self.toolhead_state.fan_speed = (speed / 255.0).clamp(0.0, 1.0);
+                }
+                ('M', 107) => self.toolhead_state.fan_speed = 0.0,
+                ('G', 90) => self.toolhead_state.position_modes[..3].fill(PositionMode::Absolute),
+                ('G', 91) => self.toolhead_state.position_modes[..3].fill(PositionMode::Relative),
+                ('M', 82) => {
+                    self.toolhead_state.position_modes[3] = PositionMode::Absolute;
+                    self.explicit_extrusion_mode = true;
+                }
+                ('M', 83) => {
+                    self.toolhead_state.position_modes[3] = PositionMode::Relative;
+                    self.explicit_extrusion_mode = true;
+                }
                 ('M', 204) => {
                     let s = params.get_number::<f64>('S');
                     let p = params.get_number::<f64>('P');
                     let t = params.get_number::<f64>('T');
                     match (s, p, t) {
-                        (Some(s), _, _) => self.toolhead_state.limits.set_max_acceleration(s),
-                        (_, Some(p), Some(t)) => {
-                            self.toolhead_state.limits.set_max_acceleration(p.min(t))
-                        }
+                        (Some(s), _, _) => self.set_acceleration(s),
+                        (_, Some(p), Some(t)) => self.set_acceleration(p.min(t)),
                         _ => {}
                     }
                 }
@@ -138,11 +376,17 @@ impl Planner {
         } else if let GCodeOperation::Extended { command, params } = &cmd.op {
             match command.as_str() {
                 "set_velocity_limit" => {
+                    // Not a Klipper parameter, but lets macros undo their overrides
+                    if params.get_number::<u32>("reset").unwrap_or(0) != 0 {
+                        self.toolhead_state
+                            .limits
+                            .reset_velocity_limits(&self.config_limits);
+                    }
                     if let Some(v) = params.get_number::<f64>("velocity") {
                         self.toolhead_state.limits.set_max_velocity(v);
                     }
                     if let Some(v) = params.get_number::<f64>("accel") {
-                        self.toolhead_state.limits.set_max_acceleration(v);
+                        self.set_acceleration(v);
                     }
                     if let Some(v) = params.get_number::<f64>("accel_to_decel") {
                         self.toolhead_state.limits.set_max_accel_to_decel(v);
@@ -151,22 +395,107 @@ impl Planner {
                         self.toolhead_state.limits.set_square_corner_velocity(v);
                     }
                 }
+                "activate_extruder" => {
+                    if let Some(name) = params.get_string("extruder") {
+                        self.toolhead_state.activate_extruder(name);
+                    }
+                }
+                "set_pressure_advance" => {
+                    if let Some(v) = params.get_number::<f64>("advance") {
+                        let extruder = params
+                            .get_string("extruder")
+                            .unwrap_or(&self.toolhead_state.extruder);
+                        let values = self
+                            .pressure_advance
+                            .entry(extruder.to_string())
+                            .or_default();
+                        if values.last() != Some(&v) {
+                            values.push(v);
+                        }
+                    }
+                }
+                "exclude_object_define" => {
+                    if let Some(name) = params.get_string("name") {
+                        if !self.objects.iter().any(|o| o == name) {
+                            self.objects.push(name.to_string());
+                        }
+                    }
+                }
+                "exclude_object_start" => {
+                    let name = params.get_string("name");
+                    self.current_object = name.map(str::to_uppercase);
+                    self.toolhead_state.enter_object(name);
+                    self.between_objects = false;
+                }
+                "exclude_object_end" => {
+                    self.current_object = None;
+                    self.toolhead_state.enter_object(None);
+                    self.between_objects = true;
+                }
+                "exclude_object" => {
+                    if let Some(name) = params.get_string("name") {
+                        self.excluded_objects.insert(name.to_uppercase());
+                    }
+                }
                 "set_retraction" => {
                     let m = &mut self.toolhead_state;
                     if let Some(fr) = self.firmware_retraction.as_ref() {
                         fr.set_options(m, params);
                     }
                 }
+                "save_gcode_state" => {
+                    let m = &self.toolhead_state;
+                    self.saved_states.insert(
+                        Self::gcode_state_name(params),
+                        SavedGCodeState {
+                            position: m.position,
+                            position_modes: m.position_modes,
+                            position_offset: m.position_offset,
+                            velocity: m.velocity,
+                            limits: m.limits.clone(),
+                        },
+                    );
+                }
+                "restore_gcode_state" => return self.restore_gcode_state(params),
+                "set_kinematic_position" => {
+                    // Sets the machine position, the G92 offsets still apply on top of it
+                    let m = &mut self.toolhead_state;
+                    for (axis, name) in ["x", "y", "z"].iter().enumerate() {
+                        if let Some(v) = params.get_number::<f64>(name) {
+                            m.position[axis] = v - m.position_offset[axis];
+                        }
+                    }
+                }
                 _ => {}
             }
             self.operations.add_fill();
-        } else if cmd.op.is_nop() && cmd.comment.is_some() {
-            let comment = cmd.comment.as_ref().unwrap(); // Same, we checked for is_some
-
+        } else if let Some(comment) = cmd.comment.as_ref().filter(|_| cmd.op.is_nop()) {
             if let Some(comment) = comment.strip_prefix("TYPE:") {
                 // IdeaMaker only gives us `TYPE:`s
                 let kind = self.kind_tracker.get_kind(comment);
                 self.kind_tracker.set_current(Some(kind));
+                self.apply_feature_acceleration(comment);
+                self.operations.add_fill();
+            } else if let Some(height) = comment.strip_prefix("HEIGHT:") {
+                if let Ok(height) = height.trim().parse() {
+                    self.toolhead_state.set_layer_height(height);
+                }
+                self.operations.add_fill();
+            } else if Self::is_layer_marker(comment) {
+                self.toolhead_state.layer = Some(self.toolhead_state.layer.map_or(0, |l| l + 1));
+                self.operations.add_fill();
+            } else if let Some(mode) =
+                Self::extrusion_mode_header(comment).filter(|_| !self.explicit_extrusion_mode)
+            {
+                self.toolhead_state.position_modes[3] = mode;
+                self.operations.add_fill();
+            } else if self.cura_settings.add_comment(comment) {
+                if let Some(diameter) = self.cura_settings.filament_diameter() {
+                    self.detected_filament_diameter = Some(diameter);
+                }
+                self.operations.add_fill();
+            } else if let Some(diameter) = parse_filament_diameter(comment) {
+                self.detected_filament_diameter = Some(diameter);
                 self.operations.add_fill();
             } else if let Some(cmd) = comment.trim_start().strip_prefix("ESTIMATOR_ADD_TIME ") {
                 if let Some((duration, kind)) = Self::parse_buffer_cmd(&mut self.kind_tracker, cmd)
@@ -187,17 +516,210 @@ impl Planner {
         1 // Most commands result in a single planning op
     }
 
+    /// Sets the acceleration the slicer configured for a feature, as read from its settings by
+    /// `--slicer-accel`, capped to the configured `max_accel`
+    fn apply_feature_acceleration(&mut self, feature: &str) {
+        let accel = match self
+            .feature_accelerations
+            .as_ref()
+            .and_then(|f| f.get(feature))
+        {
+            Some(accel) => accel,
+            None => return,
+        };
+        // Klipper's configured limit is the most the slicer can get without macros raising it
+        let limit = self.config_limits.max_acceleration;
+        if accel > limit {
+            self.toolhead_state
+                .add_warning(PlannerWarning::FeatureAccelerationCapped {
+                    feature: feature.trim().into(),
+                    acceleration: accel,
+                    limit,
+                });
+        }
+        self.set_acceleration(accel.min(limit));
+    }
+
+    /// Sets the acceleration requested by gcode. Like Klipper, which rejects the command, zero
+    /// or negative values are ignored rather than stalling every following move.
+    fn set_acceleration(&mut self, accel: f64) {
+        if accel > 0.0 {
+            self.toolhead_state.limits.set_max_acceleration(accel);
+        } else {
+            self.toolhead_state
+                .add_warning(PlannerWarning::InvalidAcceleration(accel));
+        }
+    }
+
+    fn gcode_state_name(params: &GCodeExtendedParams) -> String {
+        params
+            .get_string("name")
+            .unwrap_or("default_state")
+            .to_uppercase()
+    }
+
+    /// Restores a state saved by `SAVE_GCODE_STATE`. Unlike in Klipper, velocity limits are
+    /// restored as well, so macros changing them don't affect the rest of the print.
+    fn restore_gcode_state(&mut self, params: &GCodeExtendedParams) -> usize {
+        let state = match self.saved_states.get(&Self::gcode_state_name(params)) {
+            Some(state) => state.clone(),
+            None => {
+                self.operations.add_fill();
+                return 1;
+            }
+        };
+        let m = &mut self.toolhead_state;
+        m.position_modes = state.position_modes;
+        m.velocity = state.velocity;
+        m.limits = state.limits;
+        // The toolhead doesn't move, but is now addressed through the saved offsets. The
+        // extruder position is restored as is.
+        let offset_change = m.position_offset - state.position_offset;
+        m.position = (m.position.xyz() + offset_change.xyz()).extend(state.position.w);
+        m.position_offset = state.position_offset;
+
+        if params.get_number::<u32>("move").unwrap_or(0) == 0 {
+            self.operations.add_fill();
+            return 1;
+        }
+        let v = m.velocity;
+        if let Some(speed) = params.get_number::<f64>("move_speed").filter(|v| *v > 0.0) {
+            m.velocity = speed;
+        }
+        let modes = m.position_modes;
+        m.position_modes = [PositionMode::Absolute; 4];
+        let p = state.position;
+        let mv = m.perform_move([Some(p.x), Some(p.y), Some(p.z), None]);
+        m.position_modes = modes;
+        m.velocity = v;
+        self.operations.add_move(mv, &self.toolhead_state);
+        1
+    }
+
+    /// Warns about extrude-only moves longer than `max_extrude_only_distance`, which Klipper
+    /// rejects. They're still planned as a single move.
+    fn check_extrude_only_distance(&mut self, m: &PlanningMove) {
+        let limit = match self.toolhead_state.limits.max_extrude_only_distance {
+            Some(limit) if limit > 0.0 => limit,
+            _ => return,
+        };
+        if m.is_extrude_only_move() && m.delta().w.abs() > limit {
+            self.toolhead_state
+                .add_warning(PlannerWarning::ExtrudeOnlyMoveTooLong { limit });
+        }
+    }
+
     /// Performs final processing on the final sequence, if one is active.
     pub fn finalize(&mut self) {
         self.operations.flush();
     }
 
-    fn is_dwell(cmd: &GCodeCommand, kind_tracker: &mut KindTracker) -> Option<Delay> {
+    /// Time spent probing by `G29` or `BED_MESH_CALIBRATE`. This is only modeled if both the
+    /// time per point and the number of points are known, otherwise it's indeterminate.
+    fn bed_mesh_delay(&mut self, cmd: &GCodeCommand) -> Option<Delay> {
+        let configured = self.toolhead_state.limits.bed_mesh_probe_points;
+        let points = match &cmd.op {
+            GCodeOperation::Traditional {
+                letter: 'G',
+                code: 29,
+                minor: None,
+                ..
+            } => configured,
+            GCodeOperation::Extended { command, params } if command == "bed_mesh_calibrate" => {
+                params
+                    .get_string("probe_count")
+                    .and_then(parse_probe_count)
+                    .or(configured)
+            }
+            _ => return None,
+        };
+        Some(match (self.probe_point_time, points) {
+            (Some(t), Some(n)) => {
+                Delay::Indeterminate(t * n as u32, Some(self.kind_tracker.get_kind("Bed mesh")))
+            }
+            _ => Delay::Indeterminate(
+                Duration::from_secs_f64(0.1),
+                Some(self.kind_tracker.get_kind("Indeterminate time")),
+            ),
+        })
+    }
+
+    /// Plans `G28` as homing moves when `model_homing` is set. Like Klipper, the axes are homed
+    /// one after another: a move to the endstop from the current position at `homing_speed`,
+    /// a retract by `homing_retract_dist`, then a second, slower approach. Axes without homing
+    /// settings aren't modeled, falling back to indeterminate time if none of them have any.
+    fn plan_homing(&mut self, cmd: &GCodeCommand) -> Option<usize> {
+        let params = match &cmd.op {
+            GCodeOperation::Traditional {
+                letter: 'G',
+                code: 28,
+                minor: None,
+                params,
+            } if self.model_homing => params,
+            _ => return None,
+        };
+        let all = !['X', 'Y', 'Z']
+            .iter()
+            .any(|&c| params.get_string(c).is_some());
+        let axes = ['x', 'y', 'z']
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| all || params.get_string(c.to_ascii_uppercase()).is_some())
+            .filter_map(|(axis, c)| Some((axis, *self.config_limits.homing.get(&c.to_string())?)))
+            .collect::<Vec<_>>();
+        if axes.is_empty() {
+            return None;
+        }
+
+        let kind = Some(self.kind_tracker.get_kind("Homing"));
+        let velocity = self.toolhead_state.velocity;
+        let mut n = 0;
+        for (axis, homing) in axes {
+            // Homing ends at the endstop in machine coordinates, dropping any `G92` offset
+            let m = &mut self.toolhead_state;
+            m.position[axis] += m.position_offset[axis];
+            m.position_offset[axis] = 0.0;
+
+            let speed = homing.homing_speed.unwrap_or(5.0);
+            let second_speed = homing.second_homing_speed.unwrap_or(speed / 2.0);
+            let retract = homing.homing_retract_dist.unwrap_or(5.0);
+            let away = if homing.homing_positive_dir {
+                -1.0
+            } else {
+                1.0
+            };
+            let endstop = homing.position_endstop;
+
+            let mut moves = vec![(endstop - m.position[axis], speed)];
+            if retract > 0.0 {
+                moves.push((away * retract, speed));
+                moves.push((-away * retract, second_speed));
+            }
+            for (distance, speed) in moves {
+                let mut axes = [None; 4];
+                axes[axis] = Some(distance);
+                self.toolhead_state.velocity = speed;
+                let pm = self.toolhead_state.perform_relative_move(axes, kind);
+                self.operations.add_move(pm, &self.toolhead_state);
+                n += 1;
+            }
+            self.toolhead_state.position[axis] = endstop;
+        }
+        self.toolhead_state.velocity = velocity;
+        Some(n)
+    }
+
+    fn is_dwell(
+        cmd: &GCodeCommand,
+        kind_tracker: &mut KindTracker,
+        pause_time: Duration,
+    ) -> Option<Delay> {
         let indef = Duration::from_secs_f64(0.1);
         match &cmd.op {
             GCodeOperation::Traditional {
                 letter: 'G',
                 code: 4,
+                minor: None,
                 params,
             } => Some(Delay::Pause(Duration::from_secs_f64(
                 params.get_number('P').map_or(0.25, |v: f64| v / 1000.0),
@@ -205,6 +727,7 @@ impl Planner {
             GCodeOperation::Traditional {
                 letter: 'G',
                 code: 28,
+                minor: None,
                 ..
             } => Some(Delay::Indeterminate(
                 indef,
@@ -213,6 +736,7 @@ impl Planner {
             GCodeOperation::Traditional {
                 letter: 'M',
                 code: 109 | 190,
+                minor: None,
                 ..
             } => Some(Delay::Indeterminate(
                 indef,
@@ -224,15 +748,65 @@ impl Planner {
             GCodeOperation::Traditional {
                 letter: 'M',
                 code: 600,
+                minor: None,
                 ..
             } => Some(Delay::Indeterminate(
                 indef,
                 Some(kind_tracker.get_kind("Indeterminate time")),
             )),
+            GCodeOperation::Traditional {
+                letter: 'M',
+                code: 0 | 1,
+                minor: None,
+                ..
+            } => Some(Delay::Indeterminate(
+                pause_time,
+                Some(kind_tracker.get_kind("Pause")),
+            )),
+            GCodeOperation::Extended { command: cmd, .. } if cmd == "pause" => Some(
+                Delay::Indeterminate(pause_time, Some(kind_tracker.get_kind("Pause"))),
+            ),
             _ => None,
         }
     }
 
+    /// Checks for the layer change markers emitted by slicers:
+    /// `LAYER:<n>` by Cura and ideaMaker, `LAYER_CHANGE` by PrusaSlicer and derivatives, and
+    /// `layer <n>, Z = <z>` by Simplify3D.
+    fn is_layer_marker(comment: &str) -> bool {
+        lazy_static! {
+            static ref RE_S3D: Regex = Regex::new(r"^\s*layer \d+, Z = ").unwrap();
+        }
+        comment.starts_with("LAYER:") || comment == "LAYER_CHANGE" || RE_S3D.is_match(comment)
+    }
+
+    /// Checks for slicer comments stating the extrusion mode, used when the file has no
+    /// `M82`/`M83` of its own: `use_relative_e_distances = <0|1>` by PrusaSlicer and derivatives,
+    /// `relativeEdistances,<0|1>` by Simplify3D, and plain `relative extrusion` or
+    /// `absolute extrusion`.
+    fn extrusion_mode_header(comment: &str) -> Option<PositionMode> {
+        lazy_static! {
+            static ref RE_SETTING: Regex = Regex::new(
+                r"^\s*(?:use_relative_e_distances\s*=|relativeEdistances,)\s*([01])\s*$"
+            )
+            .unwrap();
+        }
+        let relative = if let Some(c) = RE_SETTING.captures(comment) {
+            &c[1] == "1"
+        } else {
+            match comment.trim().to_ascii_lowercase().as_str() {
+                "relative extrusion" => true,
+                "absolute extrusion" => false,
+                _ => return None,
+            }
+        };
+        Some(if relative {
+            PositionMode::Relative
+        } else {
+            PositionMode::Absolute
+        })
+    }
+
     fn parse_buffer_cmd(kind_tracker: &mut KindTracker, cmd: &str) -> Option<(f64, Option<Kind>)> {
         let (a, b) = cmd
             .split_once(' ')
@@ -246,14 +820,61 @@ impl Planner {
         self.operations.next_operation()
     }
 
-    pub fn iter(&mut self) -> PlanningOperationIter {
+    /// Number of moves planned so far, including those already drained. Unlike the operations
+    /// counted by `process_cmd`, this leaves out fills and delays.
+    pub fn num_moves(&self) -> usize {
+        self.operations.num_moves
+    }
+
+    pub fn iter(&mut self) -> PlanningOperationIter<'_> {
+        self.drain_ready()
+    }
+
+    /// Returns an iterator yielding the operations that are currently ready, without collecting
+    /// them first. Only moves whose velocities lookahead has fully resolved are ready, so how often
+    /// this is called doesn't change the results, see [`DEFAULT_DRAIN_INTERVAL`].
+    /// The iterator borrows the planner mutably, so `process_cmd` can't be called until it is
+    /// dropped. Use `PlanningOperationIter::planner` to resolve move kinds while draining.
+    pub fn drain_ready(&mut self) -> PlanningOperationIter<'_> {
         PlanningOperationIter { planner: self }
     }
 
+    /// Diameter of the filament in mm: as configured, otherwise as given by the slicer settings
+    /// seen so far, otherwise 1.75mm
+    pub fn filament_diameter(&self) -> f64 {
+        let limits = &self.toolhead_state.limits;
+        limits
+            .filament_diameter
+            .or(self.detected_filament_diameter)
+            .unwrap_or(DEFAULT_FILAMENT_DIAMETER)
+    }
+
     pub fn move_kind_str<'a>(&'a self, m: &PlanningMove) -> Option<&'a str> {
         m.kind.map(|k| self.kind_tracker.resolve_kind(k))
     }
 
+    /// Kind a move is reported under: its tracked kind, or without one `Travel` for moves that
+    /// don't extrude and `Other` for the rest
+    pub fn move_kind_label<'a>(&'a self, m: &PlanningMove) -> &'a str {
+        self.move_kind_str(m).unwrap_or_else(|| {
+            if m.is_kinematic_move() && !m.is_extrude_move() {
+                "Travel"
+            } else {
+                "Other"
+            }
+        })
+    }
+
+    /// Name of the object a move is part of, if any
+    pub fn move_object_str<'a>(&'a self, m: &PlanningMove) -> Option<&'a str> {
+        let names = &self.toolhead_state.object_names;
+        m.object.map(|i| names[i].as_str())
+    }
+
+    pub fn move_extruder_str<'a>(&'a self, m: &PlanningMove) -> &'a str {
+        &self.toolhead_state.extruder_names[m.extruder]
+    }
+
     pub fn kind_str<'a>(&'a self, kind: &Option<Kind>) -> Option<&'a str> {
         kind.map(|k| self.kind_tracker.resolve_kind(k))
     }
@@ -274,6 +895,20 @@ impl Delay {
     }
 }
 
+/// The constraint that determined a move's top speed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeedLimit {
+    /// The requested feedrate was reached
+    Feedrate,
+    /// Axis or extruder velocity limits are below the requested feedrate
+    VelocityLimits,
+    /// A corner at either end of the move slowed it below the requested feedrate
+    Cornering,
+    /// The move was too short to accelerate to the requested feedrate
+    Acceleration,
+}
+
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum PlanningOperation {
     Delay(Delay),
@@ -303,6 +938,13 @@ pub struct PlanningOperationIter<'a> {
     planner: &'a mut Planner,
 }
 
+impl<'a> PlanningOperationIter<'a> {
+    /// Returns the planner being drained
+    pub fn planner(&self) -> &Planner {
+        self.planner
+    }
+}
+
 impl<'a> Iterator for PlanningOperationIter<'a> {
     type Item = PlanningOperation;
 
@@ -327,6 +969,20 @@ pub struct PlanningMove {
     pub smoothed_dv2: f64,
 
     pub kind: Option<Kind>,
+    pub layer: Option<usize>,
+    /// Index of the extruder active for the move, see `ToolheadState::extruder_names`
+    pub extruder: usize,
+    /// Index of the object the move is part of, see `ToolheadState::object_names`
+    pub object: Option<usize>,
+    /// Height of the layer the move is part of, see `ToolheadState::layer_height`
+    pub layer_height: Option<f64>,
+    /// Time an extrude-only move would take at the toolhead's velocity and acceleration limits,
+    /// rather than those of the extruder
+    pub extruder_unlimited_time: Option<f64>,
+    /// Lowest squared junction speed allowed by the corners at either end of the move
+    pub corner_v2: f64,
+    /// Squared junction speed allowed by the corner at the start of the move
+    pub start_corner_v2: f64,
 
     pub start_v: f64,
     pub cruise_v: f64,
@@ -362,6 +1018,13 @@ impl PlanningMove {
             max_smoothed_v2: 0.0,
             smoothed_dv2: f64::MAX,
             kind: None,
+            layer: toolhead_state.layer,
+            extruder: toolhead_state.extruder_index,
+            object: toolhead_state.object,
+            layer_height: None,
+            extruder_unlimited_time: None,
+            corner_v2: f64::MAX,
+            start_corner_v2: f64::MAX,
 
             start_v: 0.0,
             cruise_v: 0.0,
@@ -380,7 +1043,7 @@ impl PlanningMove {
             end,
             distance,
             rate: (end - start) / distance,
-            requested_velocity: velocity,
+            requested_velocity: toolhead_state.velocity,
             acceleration: toolhead_state.limits.max_acceleration,
             junction_deviation: toolhead_state.limits.junction_deviation,
             max_start_v2: 0.0,
@@ -389,6 +1052,13 @@ impl PlanningMove {
             max_smoothed_v2: 0.0,
             smoothed_dv2: 2.0 * distance * toolhead_state.limits.accel_to_decel,
             kind: None,
+            layer: toolhead_state.layer,
+            extruder: toolhead_state.extruder_index,
+            object: toolhead_state.object,
+            layer_height: None,
+            extruder_unlimited_time: None,
+            corner_v2: f64::MAX,
+            start_corner_v2: f64::MAX,
 
             start_v: 0.0,
             cruise_v: 0.0,
@@ -396,39 +1066,83 @@ impl PlanningMove {
         }
     }
 
-    fn apply_junction(&mut self, previous_move: &PlanningMove, toolhead_state: &ToolheadState) {
+    fn apply_junction(&mut self, previous_move: &mut PlanningMove, toolhead_state: &ToolheadState) {
         if !self.is_kinematic_move() || !previous_move.is_kinematic_move() {
             return;
         }
 
-        let mut junction_cos_theta = -self.rate.xyz().dot(previous_move.rate.xyz());
+        let junction_cos_theta = -self.rate.xyz().dot(previous_move.rate.xyz());
         if junction_cos_theta > 0.999999 {
-            // Move was not at an angle, skip all this
+            // The move reverses direction, so the toolhead has to stop
+            self.set_corner_v2(previous_move, 0.0);
             return;
         }
-        junction_cos_theta = junction_cos_theta.max(-0.999999);
-        let sin_theta_d2 = (0.5 * (1.0 - junction_cos_theta)).sqrt();
-        let r = sin_theta_d2 / (1.0 - sin_theta_d2);
-        let tan_theta_d2 = sin_theta_d2 / (0.5 * (1.0 + junction_cos_theta)).sqrt();
-        let move_centripetal_v2 = 0.5 * self.distance * tan_theta_d2 * self.acceleration;
-        let prev_move_centripetal_v2 =
-            0.5 * previous_move.distance * tan_theta_d2 * previous_move.acceleration;
 
         let extruder_v2 = toolhead_state.extruder_junction_speed_v2(self, previous_move);
-
         self.max_start_v2 = extruder_v2
-            .min(r * self.junction_deviation * self.acceleration)
-            .min(r * previous_move.junction_deviation * previous_move.acceleration)
-            .min(move_centripetal_v2)
-            .min(prev_move_centripetal_v2)
             .min(self.max_cruise_v2)
             .min(previous_move.max_cruise_v2)
             .min(previous_move.max_start_v2 + previous_move.max_dv2);
+
+        // Like Klipper, only limit by junction deviation and centripetal velocity when the moves
+        // are at an angle. Collinear moves don't slow down, even with a `square_corner_velocity`
+        // of 0, which stops at every corner.
+        let sin_theta_d2 = (0.5 * (1.0 - junction_cos_theta)).max(0.0).sqrt();
+        let cos_theta_d2 = (0.5 * (1.0 + junction_cos_theta)).max(0.0).sqrt();
+        let one_minus_sin_theta_d2 = 1.0 - sin_theta_d2;
+        if one_minus_sin_theta_d2 > 0.0 && cos_theta_d2 > 0.0 {
+            let r = sin_theta_d2 / one_minus_sin_theta_d2;
+            let tan_theta_d2 = sin_theta_d2 / cos_theta_d2;
+            let move_centripetal_v2 = 0.5 * self.distance * tan_theta_d2 * self.acceleration;
+            let prev_move_centripetal_v2 =
+                0.5 * previous_move.distance * tan_theta_d2 * previous_move.acceleration;
+            let corner_v2 = (r * self.junction_deviation * self.acceleration)
+                .min(r * previous_move.junction_deviation * previous_move.acceleration)
+                .min(move_centripetal_v2)
+                .min(prev_move_centripetal_v2);
+            self.max_start_v2 = self.max_start_v2.min(corner_v2);
+            self.set_corner_v2(previous_move, corner_v2);
+        }
         self.max_smoothed_v2 = self
             .max_start_v2
             .min(previous_move.max_smoothed_v2 + previous_move.smoothed_dv2);
     }
 
+    /// Records the corner limit of the junction between the previous move and this one
+    fn set_corner_v2(&mut self, previous_move: &mut PlanningMove, corner_v2: f64) {
+        self.corner_v2 = self.corner_v2.min(corner_v2);
+        self.start_corner_v2 = corner_v2;
+        previous_move.corner_v2 = previous_move.corner_v2.min(corner_v2);
+    }
+
+    /// Highest velocity the move reaches once planned. Moves too short to accelerate to their
+    /// cruise velocity never cruise, and peak where acceleration turns into deceleration, which
+    /// is still `cruise_v`.
+    pub fn peak_velocity(&self) -> f64 {
+        self.cruise_v
+    }
+
+    /// Whether the move reaches the velocity the gcode requested, once planned. Moves requesting
+    /// more than the printer's velocity limits allow never do.
+    pub fn reached_requested(&self) -> bool {
+        self.cruise_v >= self.requested_velocity - 1e-6
+    }
+
+    /// What kept the move from going faster, once planned
+    pub fn speed_limit(&self) -> SpeedLimit {
+        let requested_v = self.requested_velocity;
+        let limit_v = self.max_cruise_v2.sqrt();
+        if self.reached_requested() {
+            SpeedLimit::Feedrate
+        } else if self.cruise_v >= limit_v - 1e-6 {
+            SpeedLimit::VelocityLimits
+        } else if self.corner_v2 < requested_v.min(limit_v).powi(2) {
+            SpeedLimit::Cornering
+        } else {
+            SpeedLimit::Acceleration
+        }
+    }
+
     fn set_junction(&mut self, start_v2: f64, cruise_v2: f64, end_v2: f64) {
         self.start_v = start_v2.sqrt();
         self.cruise_v = cruise_v2.sqrt();
@@ -440,7 +1154,7 @@ impl PlanningMove {
     }
 
     pub fn is_extrude_move(&self) -> bool {
-        (self.end.w - self.start.w).abs() >= EPSILON
+        (self.end.w - self.start.w).abs() >= f64::EPSILON
     }
 
     pub fn is_extrude_only_move(&self) -> bool {
@@ -448,7 +1162,7 @@ impl PlanningMove {
     }
 
     pub fn is_zero_distance(&self) -> bool {
-        self.distance.abs() < EPSILON
+        self.distance.abs() < f64::EPSILON
     }
 
     pub fn line_width(&self, filament_radius: f64, layer_height: f64) -> Option<f64> {
@@ -459,6 +1173,7 @@ impl PlanningMove {
         Some(self.rate.w * filament_radius * filament_radius * std::f64::consts::PI / layer_height)
     }
 
+    /// Volumetric flow rate of the move, negative for retracting moves
     pub fn flow_rate(&self, filament_radius: f64) -> Option<f64> {
         if !self.is_extrude_move() {
             return None;
@@ -488,7 +1203,7 @@ impl PlanningMove {
     }
 
     pub fn accel_time(&self) -> f64 {
-        self.accel_distance() / ((self.start_v + self.cruise_v) * 0.5)
+        Self::phase_time(self.accel_distance(), (self.start_v + self.cruise_v) * 0.5)
     }
 
     pub fn cruise_distance(&self) -> f64 {
@@ -496,7 +1211,7 @@ impl PlanningMove {
     }
 
     pub fn cruise_time(&self) -> f64 {
-        self.cruise_distance() / self.cruise_v
+        Self::phase_time(self.cruise_distance(), self.cruise_v)
     }
 
     pub fn decel_distance(&self) -> f64 {
@@ -504,12 +1219,32 @@ impl PlanningMove {
     }
 
     pub fn decel_time(&self) -> f64 {
-        self.decel_distance() / ((self.end_v + self.cruise_v) * 0.5)
+        Self::phase_time(self.decel_distance(), (self.end_v + self.cruise_v) * 0.5)
+    }
+
+    /// Time spent covering `distance` at `average_v`. Degenerate phases, where either the
+    /// distance or the velocity collapses to zero, take no time rather than producing NaN/inf.
+    fn phase_time(distance: f64, average_v: f64) -> f64 {
+        if distance.abs() < f64::EPSILON || average_v.abs() < f64::EPSILON {
+            0.0
+        } else {
+            distance / average_v
+        }
     }
 
     pub fn total_time(&self) -> f64 {
         self.accel_time() + self.cruise_time() + self.decel_time()
     }
+
+    /// Time to cover the move starting and ending at rest, as extrude-only moves do
+    fn rest_to_rest_time(&self, velocity: f64, acceleration: f64) -> f64 {
+        let ramp_distance = velocity * velocity / acceleration;
+        if ramp_distance >= self.distance {
+            2.0 * (self.distance / acceleration).sqrt()
+        } else {
+            self.distance / velocity + velocity / acceleration
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -534,14 +1269,25 @@ impl From<OperationSequenceOperation> for PlanningOperation {
 #[derive(Debug, Default)]
 pub struct OperationSequence {
     ops: VecDeque<OperationSequenceOperation>,
+    // Moves added so far, including those already drained
+    num_moves: usize,
 }
 
 impl OperationSequence {
+    /// Adds a delay, after which moves go in a new move sequence. Like Klipper, which flushes
+    /// its lookahead queue for `G4`, `M109` and anything else that waits, the toolhead comes to
+    /// a full stop before every delay: the last move before it ends at zero velocity and the
+    /// first one after it starts from zero. `instant_corner_velocity` doesn't carry any speed
+    /// across, as it only limits junctions between moves in the same sequence.
     pub(crate) fn add_delay(&mut self, delay: Delay) {
         self.ops.push_back(OperationSequenceOperation::Delay(delay));
     }
 
     pub(crate) fn add_move(&mut self, move_cmd: PlanningMove, toolhead_state: &ToolheadState) {
+        // Moves that don't go anywhere are planned as fills
+        if move_cmd.distance != 0.0 {
+            self.num_moves += 1;
+        }
         if let Some(OperationSequenceOperation::MoveSequence(ms)) = self.ops.back_mut() {
             ms.add_move(move_cmd, toolhead_state);
         } else {
@@ -560,6 +1306,15 @@ impl OperationSequence {
         }
     }
 
+    /// Ends the current move sequence with a fill, so the next move starts from a standstill as
+    /// it would after a delay
+    pub(crate) fn end_move_sequence(&mut self) {
+        if let Some(OperationSequenceOperation::MoveSequence(ms)) = self.ops.back_mut() {
+            ms.flush();
+        }
+        self.ops.push_back(OperationSequenceOperation::Fill);
+    }
+
     pub(crate) fn flush(&mut self) {
         for o in self.ops.iter_mut() {
             if let OperationSequenceOperation::MoveSequence(ms) = o {
@@ -581,6 +1336,7 @@ impl OperationSequence {
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 enum MoveSequenceOperation {
     Move(PlanningMove),
@@ -618,7 +1374,7 @@ impl MoveSequence {
             self.add_fill();
             return;
         }
-        if let Some(prev_move) = self.last_move() {
+        if let Some(prev_move) = self.last_move_mut() {
             move_cmd.apply_junction(prev_move, toolhead_state);
         }
         self.moves.push_back(MoveSequenceOperation::Move(move_cmd));
@@ -628,8 +1384,8 @@ impl MoveSequence {
         self.moves.is_empty()
     }
 
-    fn last_move(&self) -> Option<&PlanningMove> {
-        self.moves.iter().rev().find_map(|o| match o {
+    fn last_move_mut(&mut self) -> Option<&mut PlanningMove> {
+        self.moves.iter_mut().rev().find_map(|o| match o {
             MoveSequenceOperation::Move(m) => Some(m),
             _ => None,
         })
@@ -748,7 +1504,68 @@ pub struct PrinterLimits {
     pub firmware_retraction: Option<FirmwareRetractionOptions>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub mm_per_arc_segment: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_extrude_only_distance: Option<f64>,
     pub move_checkers: Vec<MoveChecker>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extruders: BTreeMap<String, ExtruderLimits>,
+    /// Kinematics of the printer, as in the Klipper `[printer]` section
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kinematics: Option<String>,
+    /// Filament density in g/cm³, used to report filament weight
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filament_density: Option<f64>,
+    /// Filament diameter in mm, used to report flow and filament weight. Taken from the slicer
+    /// settings in the file if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filament_diameter: Option<f64>,
+    /// Constant time in seconds added once per print, calibrated by comparing estimates to real
+    /// prints
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overhead_seconds: Option<f64>,
+    /// Number of points probed by a bed mesh calibration
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bed_mesh_probe_points: Option<usize>,
+    /// Homing settings by lower-case axis name, used to model `G28` with `Planner::model_homing`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub homing: BTreeMap<String, AxisHoming>,
+}
+
+/// Number of points in a bed mesh given by a `probe_count` setting, either `x,y` or a single
+/// count used for both axes
+pub fn parse_probe_count(s: &str) -> Option<usize> {
+    let counts = s
+        .split(',')
+        .map(|c| c.trim().parse::<usize>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    match counts[..] {
+        [n] => Some(n * n),
+        [x, y] => Some(x * y),
+        _ => None,
+    }
+}
+
+/// Limits of a single extruder, applied when it becomes the active tool
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ExtruderLimits {
+    pub max_velocity: f64,
+    pub max_accel: f64,
+    pub instant_corner_velocity: f64,
+}
+
+/// Homing settings of an axis, as in the Klipper `[stepper_x]`, `[stepper_y]` and `[stepper_z]`
+/// sections. Unset values use the Klipper defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AxisHoming {
+    pub position_endstop: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub homing_speed: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub second_homing_speed: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub homing_retract_dist: Option<f64>,
+    #[serde(default)]
+    pub homing_positive_dir: bool,
 }
 
 impl Default for PrinterLimits {
@@ -765,11 +1582,31 @@ impl Default for PrinterLimits {
             move_checkers: vec![],
             firmware_retraction: None,
             mm_per_arc_segment: None,
+            max_extrude_only_distance: None,
+            extruders: BTreeMap::new(),
+            kinematics: None,
+            filament_density: None,
+            filament_diameter: None,
+            overhead_seconds: None,
+            bed_mesh_probe_points: None,
+            homing: BTreeMap::new(),
         }
     }
 }
 
 impl PrinterLimits {
+    /// Whether the kinematics are modeled correctly. Toolhead limits are only checked along the
+    /// cartesian axes, which doesn't match e.g. delta or polar printers.
+    pub fn kinematics_supported(&self) -> bool {
+        match self.kinematics.as_deref() {
+            None => true,
+            Some(k) => matches!(
+                k,
+                "cartesian" | "corexy" | "corexz" | "hybrid_corexy" | "hybrid_corexz"
+            ),
+        }
+    }
+
     pub fn recalculate(&mut self) {
         self.update_junction_deviation();
         self.update_accel_to_decel();
@@ -802,10 +1639,47 @@ impl PrinterLimits {
         self.update_junction_deviation();
     }
 
+    /// Sets the junction deviation, by picking the square corner velocity resulting in it at the
+    /// current acceleration
+    pub fn set_junction_deviation(&mut self, jd: f64) {
+        let scv = (jd * self.max_acceleration / (2.0f64.sqrt() - 1.0)).sqrt();
+        self.set_square_corner_velocity(scv);
+    }
+
     pub fn set_instant_corner_velocity(&mut self, icv: f64) {
         self.instant_corner_velocity = icv;
     }
 
+    /// Restores the limits that can be changed by `SET_VELOCITY_LIMIT` and `M204` to those of
+    /// `config`.
+    pub fn reset_velocity_limits(&mut self, config: &PrinterLimits) {
+        self.max_velocity = config.max_velocity;
+        self.max_acceleration = config.max_acceleration;
+        self.max_accel_to_decel = config.max_accel_to_decel;
+        self.minimum_cruise_ratio = config.minimum_cruise_ratio;
+        self.square_corner_velocity = config.square_corner_velocity;
+        self.recalculate();
+    }
+
+    /// Applies the limits of the named extruder, if known, as with `ACTIVATE_EXTRUDER`
+    pub fn activate_extruder(&mut self, name: &str) {
+        let extruder = match self.extruders.get(name) {
+            Some(e) => *e,
+            None => return,
+        };
+        self.instant_corner_velocity = extruder.instant_corner_velocity;
+        for c in self.move_checkers.iter_mut() {
+            if let MoveChecker::ExtruderLimiter {
+                max_velocity,
+                max_accel,
+            } = c
+            {
+                *max_velocity = extruder.max_velocity;
+                *max_accel = extruder.max_accel;
+            }
+        }
+    }
+
     fn scv_to_jd(scv: f64, acceleration: f64) -> f64 {
         let scv2 = scv * scv;
         scv2 * (2.0f64.sqrt() - 1.0) / acceleration
@@ -817,11 +1691,17 @@ impl PrinterLimits {
     }
 
     fn update_accel_to_decel(&mut self) {
-        self.accel_to_decel = match (self.minimum_cruise_ratio, self.max_accel_to_decel) {
+        let accel_to_decel = match (self.minimum_cruise_ratio, self.max_accel_to_decel) {
             (Some(v), _) => self.max_acceleration * (1.0 - v.clamp(0.0, 1.0)),
             (_, Some(v)) => v.min(self.max_acceleration),
             _ => 50.0f64.min(self.max_acceleration),
-        }
+        };
+        // A zero limit would keep moves from ever accelerating, treat it as disabling smoothing
+        self.accel_to_decel = if accel_to_decel > 0.0 {
+            accel_to_decel
+        } else {
+            self.max_acceleration
+        };
     }
 }
 
@@ -839,6 +1719,87 @@ pub struct ToolheadState {
     pub limits: PrinterLimits,
 
     pub velocity: f64,
+    /// Index of the current layer, counted from slicer layer markers
+    pub layer: Option<usize>,
+    /// Height of the layer being printed, from `;HEIGHT:` markers, or inferred from the Z of
+    /// extruding moves if the file has none
+    pub layer_height: Option<f64>,
+    /// Whether `layer_height` comes from `;HEIGHT:` markers
+    pub layer_height_from_marker: bool,
+    /// Z of the last extruding move, from which layer heights are inferred
+    pub print_z: Option<f64>,
+    /// Offset between machine and gcode coordinates, as set by `G92`
+    pub position_offset: Vec4,
+    /// Offset of where the toolhead is from `position` after moves of excluded objects were
+    /// dropped. The next move starts from where the toolhead is.
+    pub physical_offset: Vec3,
+    /// Name of the active extruder
+    pub extruder: String,
+    /// Names of the extruders activated so far, indexed by `PlanningMove::extruder`
+    pub extruder_names: Vec<String>,
+    /// Index of the active extruder in `extruder_names`
+    pub extruder_index: usize,
+    /// Names of the objects started so far, indexed by `PlanningMove::object`
+    pub object_names: Vec<String>,
+    /// Index of the object being printed in `object_names`, between `EXCLUDE_OBJECT_START` and
+    /// `EXCLUDE_OBJECT_END`
+    pub object: Option<usize>,
+    /// Part cooling fan speed, from 0 to 1
+    pub fan_speed: f64,
+    /// Whether a velocity has been requested, as opposed to still using the default
+    pub speed_set: bool,
+    /// Problems found while planning
+    pub warnings: Vec<PlannerWarning>,
+}
+
+/// Problems in the gcode that don't stop planning, but may make the estimate inaccurate
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlannerWarning {
+    /// A zero or negative feedrate was requested, and ignored
+    InvalidFeedrate(f64),
+    /// A zero or negative acceleration was requested, and ignored
+    InvalidAcceleration(f64),
+    /// A move was made before any feedrate was set, so it used the maximum velocity
+    MoveWithoutFeedrate,
+    /// A slicer feature acceleration exceeded the configured `max_accel`, which was used instead
+    FeatureAccelerationCapped {
+        feature: String,
+        acceleration: f64,
+        limit: f64,
+    },
+    /// An extrude-only move was longer than `max_extrude_only_distance`, so Klipper would refuse it
+    ExtrudeOnlyMoveTooLong { limit: f64 },
+}
+
+impl std::fmt::Display for PlannerWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlannerWarning::InvalidFeedrate(v) => {
+                write!(f, "ignored invalid feedrate F{}", v * 60.0)
+            }
+            PlannerWarning::InvalidAcceleration(v) => {
+                write!(f, "ignored invalid acceleration {}", v)
+            }
+            PlannerWarning::MoveWithoutFeedrate => write!(
+                f,
+                "moves before any feedrate was set used the maximum velocity"
+            ),
+            PlannerWarning::FeatureAccelerationCapped {
+                feature,
+                acceleration,
+                limit,
+            } => write!(
+                f,
+                "slicer acceleration {} for {} exceeds max_accel, using {} instead",
+                acceleration, feature, limit
+            ),
+            PlannerWarning::ExtrudeOnlyMoveTooLong { limit } => write!(
+                f,
+                "extrude-only moves exceed max_extrude_only_distance of {}mm, Klipper would reject them",
+                limit
+            ),
+        }
+    }
 }
 
 impl ToolheadState {
@@ -853,29 +1814,120 @@ impl ToolheadState {
             ],
             velocity: limits.max_velocity,
             limits,
+            layer: None,
+            layer_height: None,
+            layer_height_from_marker: false,
+            print_z: None,
+            position_offset: Vec4::ZERO,
+            physical_offset: Vec3::ZERO,
+            extruder: "extruder".into(),
+            extruder_names: vec!["extruder".into()],
+            extruder_index: 0,
+            object_names: Vec::new(),
+            object: None,
+            fan_speed: 0.0,
+            speed_set: false,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Records a warning, unless the same one was already recorded
+    pub fn add_warning(&mut self, warning: PlannerWarning) {
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
         }
     }
 
+    pub fn activate_extruder(&mut self, name: &str) {
+        self.extruder = name.into();
+        self.extruder_index = match self.extruder_names.iter().position(|n| n == name) {
+            Some(i) => i,
+            None => {
+                self.extruder_names.push(name.into());
+                self.extruder_names.len() - 1
+            }
+        };
+        self.limits.activate_extruder(name);
+    }
+
+    /// Whether coordinates of an axis are absolute or relative. As in Klipper, `G91` makes
+    /// extrusion relative as well, whatever `M82` or `M83` set.
+    pub fn position_mode(&self, axis: usize) -> PositionMode {
+        match self.position_modes {
+            [PositionMode::Relative, ..] if axis == 3 => PositionMode::Relative,
+            modes => modes[axis],
+        }
+    }
+
+    /// Sets the object being printed, `None` outside of objects
+    pub fn enter_object(&mut self, name: Option<&str>) {
+        self.object = name.map(|name| {
+            match self
+                .object_names
+                .iter()
+                .position(|n| n.eq_ignore_ascii_case(name))
+            {
+                Some(i) => i,
+                None => {
+                    self.object_names.push(name.into());
+                    self.object_names.len() - 1
+                }
+            }
+        });
+    }
+
     pub fn perform_move(&mut self, axes: [Option<f64>; 4]) -> PlanningMove {
         let mut new_pos = self.position;
 
         for (axis, v) in axes.iter().enumerate() {
             if let Some(v) = v {
                 new_pos.as_mut()[axis] =
-                    Self::new_element(*v, new_pos.as_mut()[axis], self.position_modes[axis]);
+                    Self::new_element(*v, new_pos.as_mut()[axis], self.position_mode(axis));
             }
         }
 
-        let mut pm = PlanningMove::new(self.position, new_pos, self);
+        let start = self.position + std::mem::take(&mut self.physical_offset).extend(0.0);
+        let mut pm = PlanningMove::new(start, new_pos, self);
+        if pm.is_extrude_only_move() {
+            let velocity = self.velocity.min(self.limits.max_velocity);
+            pm.extruder_unlimited_time =
+                Some(pm.rest_to_rest_time(velocity, self.limits.max_acceleration));
+        }
 
         for c in self.limits.move_checkers.iter() {
             c.check(&mut pm);
         }
 
+        if pm.is_kinematic_move() && pm.delta().w > 0.0 {
+            self.update_print_z(new_pos.z);
+        }
+        pm.layer_height = self.layer_height;
+
         self.position = new_pos;
         pm
     }
 
+    /// Tracks the Z extrusion happens at, inferring the layer height from how much it rose
+    /// unless `;HEIGHT:` markers give it
+    fn update_print_z(&mut self, z: f64) {
+        if self.print_z == Some(z) {
+            return;
+        }
+        let height = z - self.print_z.unwrap_or(0.0);
+        if !self.layer_height_from_marker && height > 0.0 {
+            self.layer_height = Some(height);
+        }
+        self.print_z = Some(z);
+    }
+
+    /// Sets the layer height given by a `;HEIGHT:` marker
+    pub fn set_layer_height(&mut self, height: f64) {
+        if height > 0.0 {
+            self.layer_height = Some(height);
+            self.layer_height_from_marker = true;
+        }
+    }
+
     pub fn perform_relative_move(
         &mut self,
         axes: [Option<f64>; 4],
@@ -896,13 +1948,20 @@ impl ToolheadState {
         }
     }
 
+    /// Sets the requested toolhead velocity. Zero or negative velocities are ignored with a
+    /// warning, keeping the previous velocity.
     pub fn set_speed(&mut self, v: f64) {
         if v <= 0.0 {
-            panic!("Requested toolhead velocity {} <= 0", v);
+            self.add_warning(PlannerWarning::InvalidFeedrate(v));
+            return;
         }
-        self.velocity = v
+        self.velocity = v;
+        self.speed_set = true;
     }
 
+    /// Junction speed limit imposed by the extruder, as in Klipper's `calc_junction`. The
+    /// change in extrusion rate is what matters, so a reversal from retracting to extruding
+    /// is limited by the full swing between the two rates.
     fn extruder_junction_speed_v2(&self, cur_move: &PlanningMove, prev_move: &PlanningMove) -> f64 {
         let diff_r = (cur_move.rate.w - prev_move.rate.w).abs();
         if diff_r > 0.0 {
@@ -952,9 +2011,11 @@ impl MoveChecker {
     }
 
     fn check_extruder(move_cmd: &mut PlanningMove, max_velocity: f64, max_accel: f64) {
-        if !move_cmd.is_extrude_only_move() {
+        if !move_cmd.is_extrude_move() {
             return;
         }
+        // Like Klipper, extruder limits apply to extrude-only moves and to retracting moves,
+        // including wipes and coasting that retract while the toolhead travels
         let e_rate = move_cmd.rate.w;
         if move_cmd.rate.xy() == glam::DVec2::ZERO || e_rate < 0.0 {
             let inv_extrude_r = 1.0 / e_rate.abs();
@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use regex::Regex;
 
 #[derive(Debug, Clone)]
@@ -8,6 +10,7 @@ pub enum SlicerPreset {
     IdeaMaker { version: String },
     Cura { version: Option<String> },
     Simplify3D { version: String },
+    KISSlicer { edition: String },
 }
 
 impl std::fmt::Display for SlicerPreset {
@@ -22,6 +25,7 @@ impl std::fmt::Display for SlicerPreset {
                 version: Some(version),
             } => write!(f, "Cura {}", version),
             SlicerPreset::Simplify3D { version } => write!(f, "Simplify3D {}", version),
+            SlicerPreset::KISSlicer { edition } => write!(f, "KISSlicer {}", edition),
         }
     }
 }
@@ -33,6 +37,7 @@ impl SlicerPreset {
             .or_else(|| Self::try_cura_old(comment))
             .or_else(|| Self::try_cura_new(comment))
             .or_else(|| Self::try_simplify3d(comment))
+            .or_else(|| Self::try_kisslicer(comment))
     }
 
     #[allow(clippy::manual_map)]
@@ -93,4 +98,139 @@ impl SlicerPreset {
             version: c.get(1).unwrap().as_str().into(),
         })
     }
+
+    fn try_kisslicer(comment: &str) -> Option<SlicerPreset> {
+        lazy_static! {
+            static ref RE: Regex = Regex::new(r"^\s*KISSlicer\s-\s(.*)").unwrap();
+        }
+        RE.captures(comment).map(|c| SlicerPreset::KISSlicer {
+            edition: c.get(1).unwrap().as_str().trim().into(),
+        })
+    }
+}
+
+/// Filament diameter from a slicer settings comment: `filament_diameter = 1.75` by PrusaSlicer and
+/// derivatives. For multi-extruder settings the first value is used. Cura's settings block is
+/// read with [`CuraSettings`] instead, as its lines can split a setting.
+pub fn parse_filament_diameter(comment: &str) -> Option<f64> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"filament_diameter\s*=\s*([0-9]*\.?[0-9]+)").unwrap();
+    }
+    let diameter = RE.captures(comment)?.get(1)?.as_str().parse::<f64>().ok()?;
+    Some(diameter).filter(|d| *d > 0.0)
+}
+
+/// Cura's settings block, written at the end of the file as `;SETTING_3 ` comments. Together
+/// they hold a JSON object whose values are profiles in INI format, with escaped newlines. The
+/// chunks are collected as they come, and settings looked up by name in the text so far.
+#[derive(Debug, Clone, Default)]
+pub struct CuraSettings {
+    text: String,
+}
+
+impl CuraSettings {
+    /// Adds a comment, returning whether it was part of the settings block
+    pub fn add_comment(&mut self, comment: &str) -> bool {
+        match comment.strip_prefix("SETTING_3 ") {
+            Some(chunk) => {
+                self.text.push_str(chunk);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Value of a setting, from the first profile in the block that sets it
+    pub fn get(&self, key: &str) -> Option<&str> {
+        // Newlines are escaped twice, as `\\n`, leaving a backslash at the end of each line
+        self.text.split("\\n").find_map(|line| {
+            let (k, v) = line.split_once('=')?;
+            (k.trim() == key).then(|| v.trim().trim_end_matches('\\'))
+        })
+    }
+
+    /// Filament diameter given by `material_diameter`
+    pub fn filament_diameter(&self) -> Option<f64> {
+        let diameter = self.get("material_diameter")?.parse::<f64>().ok()?;
+        Some(diameter).filter(|d| *d > 0.0)
+    }
+}
+
+// Slicer acceleration settings and the `;TYPE:` features they apply to
+const FEATURE_ACCELERATION_SETTINGS: &[(&str, &[&str])] = &[
+    // PrusaSlicer and SuperSlicer
+    ("external_perimeter_acceleration", &["External perimeter"]),
+    (
+        "perimeter_acceleration",
+        &["Perimeter", "Overhang perimeter"],
+    ),
+    ("infill_acceleration", &["Internal infill"]),
+    ("solid_infill_acceleration", &["Solid infill"]),
+    ("top_solid_infill_acceleration", &["Top solid infill"]),
+    ("bridge_acceleration", &["Bridge infill", "Bridge"]),
+    // OrcaSlicer
+    ("outer_wall_acceleration", &["Outer wall"]),
+    ("inner_wall_acceleration", &["Inner wall"]),
+    ("sparse_infill_acceleration", &["Sparse infill"]),
+    (
+        "internal_solid_infill_acceleration",
+        &["Internal solid infill"],
+    ),
+    ("top_surface_acceleration", &["Top surface"]),
+];
+
+/// Per-feature accelerations taken from the slicer settings embedded in the gcode, keyed by the
+/// feature names used in `;TYPE:` markers
+#[derive(Debug, Clone, Default)]
+pub struct FeatureAccelerations {
+    pub features: BTreeMap<String, f64>,
+    /// Acceleration for features without a setting of their own
+    pub default: Option<f64>,
+}
+
+impl FeatureAccelerations {
+    /// Picks up an acceleration setting from a `key = value` settings comment. Returns whether
+    /// the comment was one.
+    ///
+    /// Zero means "use the default acceleration" to the slicers, so it's skipped, as are
+    /// percentages. For multi-extruder settings the first value is used.
+    pub fn parse_setting(&mut self, comment: &str) -> bool {
+        let (key, value) = match comment.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => return false,
+        };
+        let value = match value
+            .split(',')
+            .next()
+            .and_then(|v| v.trim().parse::<f64>().ok())
+        {
+            Some(v) if v > 0.0 => v,
+            _ => return false,
+        };
+        if key == "default_acceleration" {
+            self.default = Some(value);
+            return true;
+        }
+        match FEATURE_ACCELERATION_SETTINGS
+            .iter()
+            .find(|(k, _)| *k == key)
+        {
+            Some((_, features)) => {
+                for feature in features.iter() {
+                    self.features.insert((*feature).into(), value);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty() && self.default.is_none()
+    }
+
+    /// Acceleration intended for the given feature
+    pub fn get(&self, feature: &str) -> Option<f64> {
+        self.features.get(feature.trim()).copied().or(self.default)
+    }
 }
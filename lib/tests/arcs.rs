@@ -0,0 +1,52 @@
+mod common;
+
+use std::f64::consts::PI;
+
+use common::{assert_close, limits, plan};
+use lib_klipper::gcode::parse_gcode;
+use lib_klipper::planner::{Planner, PlanningOperation};
+
+fn total_distance(gcode: &str) -> (f64, usize) {
+    let moves: Vec<_> = plan(Planner::from_limits(limits()), gcode)
+        .into_iter()
+        .filter_map(|o| match o {
+            PlanningOperation::Move(m) => Some(m),
+            _ => None,
+        })
+        .collect();
+    (moves.iter().map(|m| m.distance).sum(), moves.len())
+}
+
+#[test]
+fn full_circle_without_target_returns_to_start() {
+    let (distance, moves) = total_distance("G1 X10 Y10 F6000\nG2 I5 J0\nG1 X20 Y10\n");
+    let (start, _) = total_distance("G1 X10 Y10 F6000\n");
+    // 31 segments of 1mm on a circle of radius 5, as chords of it
+    let chords = 2.0 * 5.0 * 31.0 * (PI / 31.0).sin();
+    assert_close(distance - start - 10.0, chords, 1e-9);
+    assert_close(chords, 2.0 * PI * 5.0, 0.1);
+    assert_eq!(moves, 1 + 31 + 1);
+}
+
+#[test]
+fn xz_arc_distributes_extrusion_and_advances_y() {
+    let mut planner = Planner::from_limits(limits());
+    for line in ["G18", "G1 X10 Y0 Z5 F6000", "G2 X20 Y10 Z5 I5 K0 E10"] {
+        planner.process_cmd(&parse_gcode(line).unwrap());
+    }
+    planner.finalize();
+    let moves: Vec<_> = planner.iter().filter_map(|o| o.get_move()).collect();
+    let arc = &moves[1..];
+
+    // A half circle of 5π mm around Y, with Y as the helical axis advancing 10mm
+    let segments = (5.0 * std::f64::consts::PI).hypot(10.0).floor() as usize;
+    assert_eq!(arc.len(), segments);
+    let e: f64 = arc.iter().map(|m| m.end.w - m.start.w).sum();
+    assert_close(e, 10.0, 1e-9);
+    for (i, m) in arc.iter().enumerate() {
+        assert_close(m.end.y, 10.0 * (i + 1) as f64 / segments as f64, 1e-9);
+        assert_close((m.end.x - 15.0).hypot(m.end.z - 5.0), 5.0, 1e-9);
+    }
+    let end = arc.last().unwrap().end;
+    assert_eq!((end.x, end.y, end.z, end.w), (20.0, 10.0, 5.0, 10.0));
+}
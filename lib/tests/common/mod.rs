@@ -0,0 +1,68 @@
+//! Helpers shared by the integration tests
+
+#![allow(dead_code)]
+
+use lib_klipper::estimation::{EstimationState, Estimator};
+use lib_klipper::gcode::parse_gcode;
+use lib_klipper::planner::{Planner, PlanningOperation, PrinterLimits};
+
+/// Limits the tests' expected times were worked out for: 200mm/s, 1000mm/s² without smoothing,
+/// a 5mm/s square corner velocity and 1mm arc segments. There are no extruder limits, so
+/// extrude-only moves accelerate instantly, as in Klipper without an `[extruder]`.
+pub fn limits() -> PrinterLimits {
+    let mut limits = PrinterLimits {
+        mm_per_arc_segment: Some(1.0),
+        ..Default::default()
+    };
+    limits.set_max_velocity(200.0);
+    limits.set_max_acceleration(1000.0);
+    limits.set_minimum_cruise_ratio(0.0);
+    limits.set_square_corner_velocity(5.0);
+    limits
+}
+
+pub fn estimate(gcode: &str) -> EstimationState {
+    estimate_with(Planner::from_limits(limits()), gcode)
+}
+
+pub fn estimate_with(planner: Planner, gcode: &str) -> EstimationState {
+    let mut estimator = Estimator::new(planner);
+    for line in gcode.lines() {
+        estimator.feed_line(line).expect("fixture should parse");
+    }
+    estimator.finish()
+}
+
+pub fn total_time(state: &EstimationState) -> f64 {
+    state.sequences.iter().map(|s| s.total_time).sum()
+}
+
+/// Plans gcode to the end and returns the planned operations
+pub fn plan(mut planner: Planner, gcode: &str) -> Vec<PlanningOperation> {
+    for line in gcode.lines() {
+        planner.process_cmd(&parse_gcode(line).expect("line should parse"));
+    }
+    planner.finalize();
+    planner.iter().collect()
+}
+
+/// Time spent moving in planned operations, without delays
+pub fn moves_time(ops: &[PlanningOperation]) -> f64 {
+    ops.iter()
+        .filter_map(|o| match o {
+            PlanningOperation::Move(m) => Some(m.total_time()),
+            _ => None,
+        })
+        .sum()
+}
+
+#[track_caller]
+pub fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+    assert!(
+        (actual - expected).abs() <= tolerance,
+        "expected {} ± {}, got {}",
+        expected,
+        tolerance,
+        actual
+    );
+}
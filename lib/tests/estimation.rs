@@ -0,0 +1,111 @@
+mod common;
+
+use std::f64::consts::PI;
+
+use common::{assert_close, estimate, estimate_with, limits, total_time};
+use lib_klipper::estimation::Estimator;
+use lib_klipper::planner::Planner;
+
+#[test]
+fn overhead_is_added_once_per_print() {
+    // The pause splits the print into several sequences
+    let gcode = "G1 X100 F6000\nM600\nG1 X0\nM600\nG1 X100\n";
+    let mut limits = limits();
+    limits.overhead_seconds = Some(30.0);
+    let calibrated = estimate_with(Planner::from_limits(limits), gcode);
+    let plain = estimate(gcode);
+    assert!(plain.sequences.len() > 1);
+    assert_close(total_time(&calibrated), total_time(&plain) + 30.0, 1e-9);
+}
+
+#[test]
+fn filament_usage_per_tool() {
+    let mut limits = limits();
+    limits.filament_density = Some(1.25);
+    let gcode = "\
+M83
+G1 X10 E4 F6000
+G1 E-1
+G1 X20
+G1 E1
+G1 X30 E6
+T1
+G1 X40 E3
+G1 E-1
+";
+    let state = estimate_with(Planner::from_limits(limits), gcode);
+    let seq = &state.sequences[0];
+    // Retractions are undone by the following unretractions, or don't count as used at the end
+    let weight = |length: f64| length * PI * 0.875 * 0.875 / 1000.0 * 1.25;
+    assert_close(seq.total_extrude_distance, 12.0, 1e-9);
+    assert_close(seq.filament_weight, weight(12.0), 1e-9);
+
+    let usage = &seq.extruder_usage;
+    assert_eq!(usage.len(), 2);
+    assert_close(usage["extruder"].length, 10.0, 1e-9);
+    assert_close(usage["extruder"].weight, weight(10.0), 1e-9);
+    assert_close(usage["extruder1"].length, 2.0, 1e-9);
+    assert_close(usage["extruder1"].weight, weight(2.0), 1e-9);
+}
+
+#[test]
+fn short_travels_are_counted() {
+    // Ten 2mm hops between 2mm extrusions, far short of the 40mm needed to reach 200mm/s, then
+    // one long travel that does
+    let mut gcode = String::from("M83\nG1 F12000\n");
+    for i in 0..10 {
+        gcode.push_str(&format!("G1 X{} E1\nG1 X{}\n", i * 4 + 2, i * 4 + 4));
+    }
+    gcode.push_str("G1 X140\n");
+    let state = estimate(&gcode);
+    let seq = &state.sequences[0];
+
+    assert_eq!(seq.num_travel_moves, 11);
+    assert_eq!(seq.num_short_travel_moves, 10);
+    assert_close(seq.total_travel_distance, 120.0, 1e-9);
+    let average = seq.total_travel_distance / seq.total_travel_time;
+    assert!(average > 0.0 && average < 200.0, "{}mm/s", average);
+}
+
+#[test]
+fn drain_interval_does_not_change_results() {
+    // A zigzag of 3000 moves with a pause halfway, so draining every command, every 100 and
+    // only at the end split it differently
+    let mut lines = vec!["M83".to_string(), "G1 F6000".to_string()];
+    for i in 1..=3000 {
+        if i == 1500 {
+            lines.push("M600".to_string());
+        }
+        lines.push(format!("G1 X{} Y{} E0.1", i % 7, i % 2 * 3));
+    }
+    let run = |interval: usize| {
+        let mut estimator = Estimator::new(Planner::from_limits(limits())).drain_interval(interval);
+        for line in lines.iter() {
+            estimator.feed_line(line).unwrap();
+        }
+        estimator.finish()
+    };
+
+    let reference = run(1);
+    for &interval in [100, 10000].iter() {
+        let state = run(interval);
+        assert_eq!(state.sequences.len(), reference.sequences.len());
+        for (seq, expected) in state.sequences.iter().zip(reference.sequences.iter()) {
+            assert_eq!(seq.num_moves, expected.num_moves);
+            assert_close(seq.total_time, expected.total_time, 1e-6);
+        }
+    }
+}
+
+#[test]
+fn retractions_and_sharp_corners_are_counted() {
+    let seq = &estimate(include_str!("fixtures/retraction.gcode")).sequences[0];
+    assert_eq!(seq.num_retractions, 1);
+    // The retraction between the travels stops the toolhead, but isn't a corner
+    assert_eq!(seq.num_sharp_corners, 0);
+
+    // Two reversals, while the square corner keeps 5mm/s
+    let seq = &estimate("G1 X10 F6000\nG1 X0\nG1 X10\nG1 X10 Y10\n").sequences[0];
+    assert_eq!(seq.num_retractions, 0);
+    assert_eq!(seq.num_sharp_corners, 2);
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::{assert_close, estimate, estimate_with, limits, moves_time, plan, total_time};
+use lib_klipper::gcode::parse_gcode;
+use lib_klipper::planner::{MoveChecker, Planner};
+
+#[test]
+fn retracting_while_travelling_is_extruder_limited() {
+    let mut limits = limits();
+    limits.move_checkers.push(MoveChecker::ExtruderLimiter {
+        max_velocity: 50.0,
+        max_accel: 1000.0,
+    });
+    let wipe = |e: &str| {
+        let gcode = format!("M83\nG1 X10 E{} F6000\n", e);
+        moves_time(&plan(Planner::from_limits(limits.clone()), &gcode))
+    };
+
+    // Extruding along at 2mm/mm stays within the toolhead's limits, reaching 100mm/s
+    assert_close(wipe("20"), 0.2, 1e-9);
+    // Retracting at 2mm/mm is limited to 25mm/s and 500mm/s²
+    assert_close(wipe("-20"), 0.05 + 0.35 + 0.05, 1e-9);
+}
+
+#[test]
+fn extrusion_reversal_junction_is_limited_by_the_rate_swing() {
+    let mut limits = limits();
+    limits.set_instant_corner_velocity(1.0);
+    let mut planner = Planner::from_limits(limits);
+    for line in ["M83", "G1 X10 E-1 F6000", "G1 X20 E1"] {
+        planner.process_cmd(&parse_gcode(line).unwrap());
+    }
+    planner.finalize();
+    let moves: Vec<_> = planner.iter().filter_map(|o| o.get_move()).collect();
+
+    // As in Klipper's calc_junction, the rate goes from -0.1 to 0.1mm/mm, a swing of 0.2, so
+    // the junction is limited to 1 / 0.2 = 5mm/s despite the toolhead going straight through
+    assert_close(moves[0].end_v, 5.0, 1e-9);
+    assert_close(moves[1].start_v, 5.0, 1e-9);
+}
+
+#[test]
+fn time_lost_to_extruder_limits_on_a_prime_line() {
+    let prime = "M83\nG1 E100 F3000\n";
+    let unlimited = estimate(prime);
+    assert_eq!(unlimited.sequences[0].total_extruder_limited_time, 0.0);
+
+    let mut limits = limits();
+    limits.move_checkers.push(MoveChecker::ExtruderLimiter {
+        max_velocity: 10.0,
+        max_accel: 1000.0,
+    });
+    let limited = estimate_with(Planner::from_limits(limits), prime);
+    let seq = &limited.sequences[0];
+
+    // 10mm/s takes 100 / 10 + 10 / 1000 = 10.01s, the requested 50mm/s only 100 / 50 + 50 / 1000
+    assert_close(seq.total_extrude_only_time, 10.01, 1e-9);
+    assert_close(seq.total_extruder_limited_time, 10.01 - 2.05, 1e-9);
+}
+
+const PRIME: &str = "M83\nG1 E100 F600\n";
+
+#[test]
+fn long_extrude_only_move_is_planned_whole_and_warned_about() {
+    let unlimited = estimate(PRIME);
+    assert!(unlimited.warnings.is_empty());
+
+    let mut limits = limits();
+    limits.max_extrude_only_distance = Some(50.0);
+    let limited = estimate_with(Planner::from_limits(limits), PRIME);
+
+    // Klipper rejects the move rather than splitting it, so the time is unchanged
+    assert_close(total_time(&limited), total_time(&unlimited), 1e-9);
+    assert_close(total_time(&limited), 0.25 + 10.0, 1e-9);
+    assert_eq!(limited.warnings.len(), 1);
+    assert!(limited.warnings[0].contains("max_extrude_only_distance"));
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use common::{assert_close, limits, moves_time, plan};
+use lib_klipper::planner::{ExtruderLimits, MoveChecker, Planner};
+
+#[test]
+fn each_extruder_has_its_own_limits() {
+    let mut limits = limits();
+    limits.move_checkers.push(MoveChecker::ExtruderLimiter {
+        max_velocity: 50.0,
+        max_accel: 1000.0,
+    });
+    let extruder = |max_velocity, max_accel| ExtruderLimits {
+        max_velocity,
+        max_accel,
+        instant_corner_velocity: 1.0,
+    };
+    limits
+        .extruders
+        .insert("extruder".into(), extruder(50.0, 1000.0));
+    limits
+        .extruders
+        .insert("extruder1".into(), extruder(10.0, 100.0));
+
+    let prime = |tool: &str| {
+        let gcode = format!("M83\n{}\nG1 E20 F6000\n", tool);
+        moves_time(&plan(Planner::from_limits(limits.clone()), &gcode))
+    };
+    // 20mm at 50mm/s with 1000mm/s² for the first extruder, at 10mm/s with 100mm/s² for the
+    // second
+    assert_close(prime("T0"), 0.05 + 0.35 + 0.05, 1e-9);
+    assert_close(prime("T1"), 0.1 + 1.9 + 0.1, 1e-9);
+    assert_close(
+        prime("ACTIVATE_EXTRUDER EXTRUDER=extruder1"),
+        prime("T1"),
+        1e-9,
+    );
+}
@@ -0,0 +1,3 @@
+; Half circle of radius 10 at 10mm/s, segmented every 1mm
+G90
+G2 X20 Y0 I10 J0 F600
@@ -0,0 +1,4 @@
+; Right angle corner: the junction is limited to the 5mm/s square_corner_velocity
+G90
+G1 X100 F6000
+G1 Y100
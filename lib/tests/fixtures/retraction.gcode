@@ -0,0 +1,7 @@
+; Retraction around a travel, with extrude-only moves at 40mm/s
+G90
+M83
+G1 X50 F6000
+G1 E-1 F2400
+G1 X0 F6000
+G1 E1 F2400
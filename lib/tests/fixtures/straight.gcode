@@ -0,0 +1,3 @@
+; Straight line: 5mm ramps at 1000mm/s^2, 90mm cruise at 100mm/s
+G90
+G1 X100 F6000
@@ -0,0 +1,108 @@
+use lib_klipper::estimation::Estimator;
+use lib_klipper::gcode::{GCodeOperation, GCodeReader};
+use lib_klipper::planner::{Planner, PrinterLimits};
+
+#[test]
+fn every_line_ending_style_parses() {
+    for ending in ["\n", "\r\n", "\r"] {
+        let gcode = ["G1 X10", "; comment", "G1 Y20"].join(ending) + ending;
+        let cmds = GCodeReader::new(gcode.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(cmds.len(), 3, "{:?}", ending);
+        assert!(
+            matches!(cmds[0].op, GCodeOperation::Move { x: Some(x), y: None, .. } if x == 10.0)
+        );
+        assert_eq!(cmds[1].comment.as_deref(), Some(" comment"));
+        assert!(
+            matches!(cmds[2].op, GCodeOperation::Move { x: None, y: Some(y), .. } if y == 20.0)
+        );
+    }
+}
+
+#[test]
+fn byte_order_mark_is_skipped() {
+    let gcode = "\u{feff}G1 X10 F6000\r\n;TYPE:Perimeter\r\nG1 X20\r\n";
+    let cmds = GCodeReader::new(gcode.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .expect("BOM should be skipped");
+    assert!(matches!(cmds[0].op, GCodeOperation::Move { x: Some(x), .. } if x == 10.0));
+
+    // The kind doesn't keep the carriage return either
+    let mut estimator = Estimator::new(Planner::from_limits(PrinterLimits::default()));
+    for cmd in cmds.iter() {
+        estimator.feed(cmd);
+    }
+    let state = estimator.finish();
+    let kinds: Vec<&str> = state.sequences[0]
+        .kind_times
+        .keys()
+        .map(|k| k.as_str())
+        .collect();
+    assert!(kinds.contains(&"Perimeter"), "{:?}", kinds);
+}
+
+#[test]
+fn thumbnail_blocks_are_skipped() {
+    let gcode: &[u8] = b"\
+; thumbnail begin 16x16 24\r\n\
+; iVBORw0KGgoAAAANSUhEUgAAABAAAAAQ\r\n\
+; \xff\xfe not even UTF-8\r\n\
+; thumbnail end\r\n\
+; after\r\n\
+G1 X10\r\n";
+    let cmds = GCodeReader::new(gcode)
+        .collect::<Result<Vec<_>, _>>()
+        .expect("thumbnail lines should read without errors");
+
+    // One command per line, keeping line numbers in step with the file
+    assert_eq!(cmds.len(), 6);
+    for cmd in &cmds[..4] {
+        assert_eq!(cmd.op, GCodeOperation::Nop);
+        assert_eq!(cmd.comment, None);
+    }
+    assert_eq!(cmds[4].comment.as_deref(), Some(" after"));
+    assert!(matches!(cmds[5].op, GCodeOperation::Move { .. }));
+}
+
+#[test]
+fn unterminated_thumbnail_ends_at_the_first_command() {
+    let gcode = "G1 X10 F6000\n; thumbnail begin 16x16 10\n; abc\nG1 X100\nG1 X200\n";
+    let mut rdr = GCodeReader::new(gcode.as_bytes());
+    let cmds = rdr
+        .by_ref()
+        .collect::<Result<Vec<_>, _>>()
+        .expect("thumbnail lines should read without errors");
+
+    let moves: Vec<_> = cmds
+        .iter()
+        .filter_map(|cmd| match cmd.op {
+            GCodeOperation::Move { x, .. } => x,
+            _ => None,
+        })
+        .collect();
+    assert_eq!(moves, vec![10.0, 100.0, 200.0]);
+    assert_eq!(rdr.unterminated_thumbnail_line(), Some(2));
+}
+
+fn read(gcode: &str, decimal_comma: bool) -> (Vec<GCodeOperation>, Option<usize>) {
+    let mut rdr = GCodeReader::new(gcode.as_bytes()).decimal_comma(decimal_comma);
+    let ops = rdr.by_ref().map(|cmd| cmd.unwrap().op).collect();
+    (ops, rdr.comma_decimal_line())
+}
+
+#[test]
+fn comma_decimals_only_apply_to_numeric_params() {
+    let gcode = "M117 Layer 1,2\nM204 S1000,5\nG1 X10,5 Y2 ; go 1,5\n";
+    let (plain, line) = read(gcode, false);
+    assert_eq!(line, Some(2));
+    assert!(matches!(plain[2], GCodeOperation::Move { x: None, .. }));
+
+    let (ops, _) = read(gcode, true);
+    assert_eq!(ops[0], plain[0]);
+    assert_eq!(ops[1].to_string(), "M204 S1000.5");
+    assert!(
+        matches!(ops[2], GCodeOperation::Move { x: Some(x), y: Some(y), .. } if x == 10.5 && y == 2.0)
+    );
+    assert_eq!(read("M117 Layer 1,2\n", false).1, None);
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use std::collections::BTreeSet;
+
+use common::{assert_close, estimate, limits};
+use lib_klipper::estimation::MoveKind;
+use lib_klipper::gcode::parse_gcode;
+use lib_klipper::planner::{KindSource, Planner, PlanningOperation};
+
+const STRAY_COMMENT: &str = "\
+;TYPE:Perimeter
+G1 X10 F6000
+G1 X20 ; wipe to the right
+";
+
+fn kinds(source: KindSource) -> BTreeSet<String> {
+    let mut planner = Planner::from_limits(limits());
+    planner.kind_tracker.source = source;
+    for line in STRAY_COMMENT.lines() {
+        planner.process_cmd(&parse_gcode(line).expect("line should parse"));
+    }
+    planner.finalize();
+    let kinds: Vec<_> = planner
+        .iter()
+        .filter_map(|o| match o {
+            PlanningOperation::Move(m) => m.kind,
+            _ => None,
+        })
+        .collect();
+    kinds
+        .into_iter()
+        .map(|k| planner.kind_tracker.resolve_kind(k).to_string())
+        .collect()
+}
+
+#[test]
+fn stray_move_comments_are_not_kinds_by_default() {
+    let expected: BTreeSet<String> = vec!["Perimeter".to_string()].into_iter().collect();
+    assert_eq!(kinds(KindSource::TypeOnly), expected);
+    assert!(kinds(KindSource::Comment).contains("wipe to the right"));
+    assert!(kinds(KindSource::None).iter().all(|k| k != "Perimeter"));
+}
+
+#[test]
+fn slicer_kinds_map_to_canonical_kinds() {
+    let cases = vec![
+        // PrusaSlicer and SuperSlicer
+        ("External perimeter", MoveKind::ExternalPerimeter),
+        ("Overhang perimeter", MoveKind::Perimeter),
+        ("Internal infill", MoveKind::Infill),
+        ("Top solid infill", MoveKind::SolidInfill),
+        ("Bridge infill", MoveKind::Bridge),
+        ("Support material interface", MoveKind::Support),
+        ("Skirt/Brim", MoveKind::Skirt),
+        // Cura
+        ("WALL-OUTER", MoveKind::ExternalPerimeter),
+        ("WALL-INNER", MoveKind::Perimeter),
+        ("FILL", MoveKind::Infill),
+        ("SKIN", MoveKind::SolidInfill),
+        ("SUPPORT-INTERFACE", MoveKind::Support),
+        ("SKIRT", MoveKind::Skirt),
+        // OrcaSlicer and Bambu Studio
+        ("Outer wall", MoveKind::ExternalPerimeter),
+        ("Sparse infill", MoveKind::Infill),
+        ("Bottom surface", MoveKind::SolidInfill),
+        ("Internal Bridge", MoveKind::Bridge),
+        // Simplify3D
+        ("inner perimeter", MoveKind::Perimeter),
+        ("solid layer", MoveKind::SolidInfill),
+        // Planner kinds
+        ("Object travel", MoveKind::Travel),
+        ("Firmware retract", MoveKind::Retract),
+    ];
+    for (name, kind) in cases {
+        assert_eq!(MoveKind::classify(name), kind, "{}", name);
+    }
+    assert_eq!(
+        MoveKind::classify("Prime tower"),
+        MoveKind::Custom("Prime tower".to_string())
+    );
+}
+
+#[test]
+fn feature_times_group_kinds_across_slicers() {
+    let gcode = "\
+;TYPE:External perimeter
+G1 X10 F6000
+;TYPE:WALL-OUTER
+G1 X20
+;TYPE:Prime tower
+G1 X30
+";
+    let seq = &estimate(gcode).sequences[0];
+    let features = &seq.feature_times;
+    assert_eq!(features.len(), 2);
+    assert_close(
+        features[&MoveKind::ExternalPerimeter],
+        seq.kind_times["External perimeter"] + seq.kind_times["WALL-OUTER"],
+        1e-9,
+    );
+    let custom = MoveKind::Custom("Prime tower".to_string());
+    assert_close(features[&custom], seq.kind_times["Prime tower"], 1e-9);
+}
+
+#[test]
+fn unlabeled_travels_are_reported_as_travel() {
+    let seq = &estimate("M83\nG1 X10 E1 F6000\nG1 X20\nG1 E-1\n").sequences[0];
+    let kinds: Vec<&str> = seq.kind_times.keys().map(|k| k.as_str()).collect();
+    // The extrude-only retraction isn't a travel either
+    assert_eq!(kinds, vec!["Other", "Travel"]);
+    assert_close(
+        seq.kind_times["Travel"] + seq.kind_times["Other"],
+        seq.total_time - 0.25,
+        1e-9,
+    );
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use std::collections::BTreeSet;
+
+use common::{assert_close, limits, plan};
+use lib_klipper::planner::{Planner, PlanningOperation};
+use std::f64::consts::PI;
+
+use lib_klipper::gcode::parse_gcode;
+
+/// Three layers of the same move, each preceded by the given marker, with a Z hop in the
+/// second one that Z-based layer detection would count as layers of its own
+fn marker_layers(marker: impl Fn(usize) -> String) -> Vec<usize> {
+    let mut gcode = "G1 F6000\n".to_string();
+    for layer in 0..3 {
+        gcode.push_str(&format!(
+            ";{}\nG1 Z{}\n",
+            marker(layer),
+            0.2 * (layer + 1) as f64
+        ));
+        if layer == 1 {
+            gcode.push_str("G1 Z1\nG1 Z0.4\n");
+        }
+        gcode.push_str("G1 X10 E1\nG1 X0 E2\n");
+    }
+    plan(Planner::from_limits(limits()), &gcode)
+        .into_iter()
+        .filter_map(|o| match o {
+            PlanningOperation::Move(m) => m.layer,
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+#[test]
+fn cura_layer_markers() {
+    assert_eq!(marker_layers(|n| format!("LAYER:{}", n)), vec![0, 1, 2]);
+}
+
+#[test]
+fn prusaslicer_layer_markers() {
+    assert_eq!(marker_layers(|_| "LAYER_CHANGE".to_string()), vec![0, 1, 2]);
+}
+
+#[test]
+fn simplify3d_layer_markers() {
+    assert_eq!(
+        marker_layers(|n| format!(" layer {}, Z = {:.3}", n + 1, 0.2 * (n + 1) as f64)),
+        vec![0, 1, 2]
+    );
+}
+
+#[test]
+fn other_comments_are_not_layer_markers() {
+    assert!(marker_layers(|_| "comment".to_string()).is_empty());
+}
+
+/// Layer heights and line widths of the extruding moves in the given gcode
+fn extrusion_widths(gcode: &str) -> Vec<(f64, f64)> {
+    let mut planner = Planner::from_limits(limits());
+    for line in gcode.lines() {
+        planner.process_cmd(&parse_gcode(line).unwrap());
+    }
+    planner.finalize();
+    planner
+        .iter()
+        .filter_map(|o| o.get_move())
+        .filter_map(|m| {
+            let height = m.layer_height?;
+            Some((height, m.line_width(0.875, height)?))
+        })
+        .collect()
+}
+
+#[test]
+fn line_widths_use_height_markers() {
+    // Adaptive layers whose heights don't match the Z steps, which include a Z hop
+    let gcode = "\
+M83
+G1 F6000
+;HEIGHT:0.3
+G1 Z0.3
+G1 X10 E1
+G1 Z1
+;HEIGHT:0.1
+G1 Z0.4
+G1 X0 E1
+";
+    let widths = extrusion_widths(gcode);
+    // Both lines extrude 0.1mm of filament per mm
+    let width = |height: f64| 0.1 * 0.875 * 0.875 * PI / height;
+    assert_eq!(widths.len(), 2);
+    assert_close(widths[0].0, 0.3, 1e-9);
+    assert_close(widths[0].1, width(0.3), 1e-9);
+    assert_close(widths[1].0, 0.1, 1e-9);
+    assert_close(widths[1].1, width(0.1), 1e-9);
+}
+
+#[test]
+fn line_widths_fall_back_to_z_steps() {
+    let gcode = "M83\nG1 F6000\nG1 Z0.2\nG1 X10 E1\nG1 Z0.5\nG1 X0 E1\n";
+    let widths = extrusion_widths(gcode);
+    assert_eq!(widths.len(), 2);
+    assert_close(widths[0].0, 0.2, 1e-9);
+    assert_close(widths[1].0, 0.3, 1e-9);
+}
@@ -0,0 +1,112 @@
+mod common;
+
+use common::{assert_close, estimate_with, total_time};
+use lib_klipper::gcode::parse_gcode;
+use lib_klipper::planner::{Planner, PrinterLimits};
+use serde_json::json;
+
+const TWO_EXTRUDERS: &str = include_str!("fixtures/two_extruders.json");
+
+/// The `configfile.settings` Klipper requires, extended with the given sections
+fn settings(sections: serde_json::Value) -> serde_json::Value {
+    let mut settings = json!({
+        "printer": {
+            "max_velocity": 200,
+            "max_accel": 1000,
+            "square_corner_velocity": 5
+        },
+        "extruder": {
+            "max_extrude_only_velocity": 50,
+            "max_extrude_only_accel": 1000,
+            "instantaneous_corner_velocity": 1
+        }
+    });
+    for (name, section) in sections.as_object().unwrap() {
+        settings[name] = section.clone();
+    }
+    settings
+}
+
+#[test]
+fn each_extruder_has_its_own_limits() {
+    let limits = PrinterLimits::from_moonraker_json(TWO_EXTRUDERS).unwrap();
+    assert_eq!(limits.extruders.len(), 2);
+    assert_ne!(limits.extruders["extruder"], limits.extruders["extruder1"]);
+
+    let prime = |tool: &str| {
+        let planner = Planner::from_limits(limits.clone());
+        let gcode = format!("M83\n{}\nG1 E20 F6000\n", tool);
+        total_time(&estimate_with(planner, &gcode))
+    };
+    // 20mm at 50mm/s with 1000mm/s² for the first extruder, at 10mm/s with 100mm/s² for the
+    // second, each after the 0.25s startup
+    assert_close(prime("T0"), 0.25 + 0.05 + 0.35 + 0.05, 1e-9);
+    assert_close(prime("T1"), 0.25 + 0.1 + 1.9 + 0.1, 1e-9);
+    assert_close(
+        prime("ACTIVATE_EXTRUDER EXTRUDER=extruder1"),
+        prime("T1"),
+        1e-9,
+    );
+}
+
+#[test]
+fn gcode_arcs_resolution_defaults_to_1mm() {
+    let limits = |sections| PrinterLimits::from_moonraker_settings(&settings(sections)).unwrap();
+    assert_eq!(limits(json!({})).mm_per_arc_segment, None);
+    let default = limits(json!({ "gcode_arcs": {} }));
+    assert_eq!(default.mm_per_arc_segment, Some(1.0));
+    let fine = limits(json!({ "gcode_arcs": { "resolution": 0.1 } }));
+    assert_eq!(fine.mm_per_arc_segment, Some(0.1));
+
+    // A half circle of radius 10 is about 31.4mm long
+    let mut planner = Planner::from_limits(default);
+    for line in ["G1 X0 Y0 F600", "G2 X20 Y0 I10 J0"].iter() {
+        planner.process_cmd(&parse_gcode(line).unwrap());
+    }
+    planner.finalize();
+    assert_eq!(
+        planner.iter().filter(|o| o.get_move().is_some()).count(),
+        31
+    );
+}
+
+#[test]
+fn accel_to_decel_forms() {
+    let limits = |printer: serde_json::Value| {
+        let mut settings = settings(json!({}));
+        for (key, value) in printer.as_object().unwrap() {
+            settings["printer"][key] = value.clone();
+        }
+        PrinterLimits::from_moonraker_json(&settings.to_string()).unwrap()
+    };
+
+    // Klipper before v0.12
+    let old = limits(json!({ "max_accel_to_decel": 500 }));
+    assert_eq!(old.max_accel_to_decel, Some(500.0));
+    assert_eq!(old.minimum_cruise_ratio, None);
+    assert_close(old.accel_to_decel, 500.0, 1e-9);
+
+    let new = limits(json!({ "minimum_cruise_ratio": 0.25 }));
+    assert_eq!(new.minimum_cruise_ratio, Some(0.25));
+    assert_eq!(new.max_accel_to_decel, None);
+    assert_close(new.accel_to_decel, 750.0, 1e-9);
+
+    // Klipper still reports the deprecated setting if configured, the ratio takes precedence
+    let both = limits(json!({ "max_accel_to_decel": 500, "minimum_cruise_ratio": 0.25 }));
+    assert_close(both.accel_to_decel, 750.0, 1e-9);
+}
+
+#[test]
+fn settings_are_read_with_or_without_the_query_response() {
+    let mut settings = settings(json!({}));
+    settings["printer"]["minimum_cruise_ratio"] = json!(0.25);
+    let response = json!({ "result": { "status": { "configfile": { "settings": settings } } } });
+    let bare = PrinterLimits::from_moonraker_json(&settings.to_string()).unwrap();
+    let full = PrinterLimits::from_moonraker_json(&response.to_string()).unwrap();
+    assert_eq!(
+        serde_json::to_value(&bare).unwrap(),
+        serde_json::to_value(&full).unwrap()
+    );
+    assert_eq!(full.max_velocity, 200.0);
+    assert_close(full.accel_to_decel, 750.0, 1e-9);
+}
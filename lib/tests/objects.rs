@@ -0,0 +1,115 @@
+mod common;
+
+use common::{assert_close, estimate, estimate_with, limits, total_time};
+use lib_klipper::planner::Planner;
+
+/// Two passes over object A with a pass over object B in between, which returns to where A
+/// left off so the travel back to A is the same either way.
+const TWO_OBJECTS: &str = "\
+EXCLUDE_OBJECT_START NAME=A
+G1 X50 F6000
+G1 Y50
+EXCLUDE_OBJECT_END NAME=A
+EXCLUDE_OBJECT_START NAME=B
+G1 X100
+G1 X50
+EXCLUDE_OBJECT_END NAME=B
+EXCLUDE_OBJECT_START NAME=A
+G1 Y100
+G1 X0
+EXCLUDE_OBJECT_END NAME=A
+";
+
+#[test]
+fn travel_between_objects_has_its_own_bucket() {
+    let state = estimate(
+        "\
+;TYPE:Perimeter
+EXCLUDE_OBJECT_START NAME=A
+G1 X10 E1 F6000
+EXCLUDE_OBJECT_END NAME=A
+G1 Y50
+EXCLUDE_OBJECT_START NAME=B
+G1 X60 E2
+EXCLUDE_OBJECT_END NAME=B
+",
+    );
+    let kind_times = &state.sequences[0].kind_times;
+    assert!(kind_times["Object travel"] > 0.0);
+    assert!(kind_times["Perimeter"] > 0.0);
+
+    let objects_of = |kind: &str| {
+        state
+            .time_attribution
+            .keys()
+            .filter(|stack| stack.split(';').nth(1) == Some(kind))
+            .map(|stack| stack.split(';').next().unwrap().to_string())
+            .collect::<std::collections::BTreeSet<_>>()
+    };
+    assert_eq!(
+        objects_of("Object travel").into_iter().collect::<Vec<_>>(),
+        vec!["(no object)".to_string()]
+    );
+    assert_eq!(
+        objects_of("Perimeter").into_iter().collect::<Vec<_>>(),
+        vec!["A".to_string(), "B".to_string()]
+    );
+}
+
+#[test]
+fn folded_stacks_add_up_to_the_total_time() {
+    let gcode = format!(
+        ";TYPE:Perimeter\nG1 E1 F6000\n{}G4 P500\nM600\nG1 X10\n",
+        TWO_OBJECTS
+    );
+    let state = estimate(&gcode);
+    let lines: Vec<String> = state.folded_stacks().collect();
+
+    let mut sum = 0;
+    for line in lines.iter() {
+        let (stack, value) = line.rsplit_once(' ').unwrap();
+        assert_eq!(stack.split(';').count(), 3, "{}", line);
+        sum += value.parse::<u64>().unwrap();
+    }
+    assert!(lines.iter().any(|l| l.starts_with("A;Perimeter;Cruise ")));
+    assert!(lines
+        .iter()
+        .any(|l| l.starts_with("(no object);Dwell;Delay ")));
+    // Each line is rounded to the microsecond
+    let tolerance = lines.len() as f64 * 0.5e-6;
+    assert_close(sum as f64 / 1e6, total_time(&state), tolerance);
+}
+
+#[test]
+fn only_object_stops_between_passes() {
+    let mut planner = Planner::from_limits(limits());
+    planner.only_object = Some("A".to_string());
+    let only = total_time(&estimate_with(planner, TWO_OBJECTS));
+
+    // A's passes on their own, coming to a stop in between as a zero length dwell does
+    let expected = total_time(&estimate("G1 X50 F6000\nG1 Y50\nG4 P0\nG1 Y100\nG1 X0\n"));
+    assert_close(only, expected, 1e-9);
+    assert!(only < total_time(&estimate(TWO_OBJECTS)));
+}
+
+#[test]
+fn moves_after_excluded_object_continue_from_its_gcode_position() {
+    let mut planner = Planner::from_limits(limits());
+    planner.excluded_objects.insert("B".to_string());
+    let gcode = "\
+EXCLUDE_OBJECT_START NAME=A
+G1 X10 F6000
+EXCLUDE_OBJECT_END NAME=A
+EXCLUDE_OBJECT_START NAME=B
+G1 X50
+G1 Y50
+EXCLUDE_OBJECT_END NAME=B
+G91
+G1 X10
+";
+    let excluded = total_time(&estimate_with(planner, gcode));
+
+    // The relative move is from where B left off, but the toolhead travels there from A
+    let expected = total_time(&estimate("G1 X10 F6000\nG1 X60 Y50\n"));
+    assert_close(excluded, expected, 1e-9);
+}
@@ -0,0 +1,416 @@
+mod common;
+
+use common::{assert_close, estimate, estimate_with, limits, moves_time, plan, total_time};
+use std::time::Duration;
+
+use lib_klipper::estimation::Estimator;
+use lib_klipper::gcode::parse_gcode;
+use lib_klipper::planner::{
+    parse_probe_count, AxisHoming, Delay, MoveChecker, Planner, PlanningOperation, PrinterLimits,
+    SpeedLimit,
+};
+
+const ZIGZAG: &str = "\
+G1 X10 F6000
+G1 Y10
+G4 P100
+G1 X0 E1
+G1 Y0 E2
+G1 X5 Y5
+";
+
+fn planner() -> Planner {
+    Planner::from_limits(limits())
+}
+
+fn feed(planner: &mut Planner, line: &str) {
+    planner.process_cmd(&parse_gcode(line).expect("line should parse"));
+}
+
+#[test]
+fn incremental_draining_matches_draining_at_the_end() {
+    let mut batch = planner();
+    for line in ZIGZAG.lines() {
+        feed(&mut batch, line);
+    }
+    batch.finalize();
+    let expected: Vec<_> = batch.iter().map(|o| format!("{:?}", o)).collect();
+
+    let mut incremental = planner();
+    let mut drained = Vec::new();
+    for line in ZIGZAG.lines() {
+        feed(&mut incremental, line);
+        drained.extend(incremental.drain_ready().map(|o| format!("{:?}", o)));
+    }
+    incremental.finalize();
+    drained.extend(incremental.drain_ready().map(|o| format!("{:?}", o)));
+
+    assert_eq!(drained, expected);
+}
+
+#[test]
+fn degenerate_moves_take_finite_time() {
+    let mut planner = planner();
+    for line in [
+        "G1 X10 F6000",
+        "G1 X10.0000000001",
+        "G1 X10.0000000001 Y0.0000000001",
+        "G1 X10",
+        "G1 E0.0000000001",
+        "G1 X20",
+    ] {
+        feed(&mut planner, line);
+    }
+    planner.finalize();
+    let moves: Vec<_> = planner.iter().filter_map(|o| o.get_move()).collect();
+    assert!(!moves.is_empty());
+    for m in &moves {
+        for t in [
+            m.accel_time(),
+            m.cruise_time(),
+            m.decel_time(),
+            m.total_time(),
+        ] {
+            assert!(t.is_finite() && t >= 0.0, "{:?} has time {}", m, t);
+        }
+    }
+    // The micro moves barely add to the two 10mm moves, which take 0.2s each
+    let total: f64 = moves.iter().map(|m| m.total_time()).sum();
+    assert!(total > 0.4 && total < 0.5, "total {}", total);
+}
+
+#[test]
+fn velocity_limit_reset_restores_configured_accel() {
+    let time = |gcode: &str| moves_time(&plan(planner(), gcode));
+    let overridden = "SET_VELOCITY_LIMIT ACCEL=100\nG1 X100 F6000\n";
+    assert_close(time(overridden), 2.0, 1e-9);
+
+    let reset = "SET_VELOCITY_LIMIT ACCEL=100\nSET_VELOCITY_LIMIT RESET=1\nG1 X100 F6000\n";
+    assert_close(time(reset), 1.1, 1e-9);
+}
+
+#[test]
+fn m0_is_an_indeterminate_pause() {
+    let mut planner = planner();
+    planner.pause_time = Duration::from_secs(60);
+    for line in ["G1 X10 F6000", "M0", "G1 X0"] {
+        feed(&mut planner, line);
+    }
+    planner.finalize();
+    let ops: Vec<_> = planner.iter().collect();
+    let pauses: Vec<_> = ops
+        .into_iter()
+        .filter_map(|o| match o {
+            PlanningOperation::Delay(Delay::Indeterminate(t, k)) => {
+                Some((t, planner.kind_str(&k)?.to_string()))
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(pauses, vec![(Duration::from_secs(60), "Pause".to_string())]);
+
+    let mut estimator = Estimator::new(Planner::from_limits(limits()));
+    for line in ["G1 X10 F6000", "M0", "G1 X0"] {
+        estimator.feed_line(line).unwrap();
+    }
+    let state = estimator.finish();
+    let pauses: usize = state.sequences.iter().map(|s| s.num_pauses).sum();
+    assert_eq!(pauses, 1);
+}
+
+#[test]
+fn fan_commands_are_time_neutral_and_tracked() {
+    let plan = |lines: &[&str]| {
+        let mut planner = planner();
+        for line in lines {
+            feed(&mut planner, line);
+        }
+        planner.finalize();
+        let fan_speed = planner.toolhead_state.fan_speed;
+        let ops: Vec<_> = planner
+            .iter()
+            .filter(|o| o.is_move())
+            .map(|o| format!("{:?}", o))
+            .collect();
+        (ops, fan_speed)
+    };
+    let (plain, _) = plan(&["G1 X10 F6000", "G1 Y10", "G1 X0"]);
+    let (fans, fan_speed) = plan(&[
+        "M106 S255",
+        "G1 X10 F6000",
+        "M107",
+        "G1 Y10",
+        "M106 S127.5",
+        "G1 X0",
+    ]);
+    assert_eq!(fans, plain);
+    assert_close(fan_speed, 0.5, 1e-9);
+}
+
+#[test]
+fn zero_feedrate_is_ignored_with_a_warning() {
+    let zero = estimate("G1 X10 F6000\nG1 X20 F0\nG1 X30\n");
+    let plain = estimate("G1 X10 F6000\nG1 X20\nG1 X30\n");
+    assert_close(total_time(&zero), total_time(&plain), 1e-9);
+    assert_eq!(
+        zero.warnings,
+        vec!["ignored invalid feedrate F0".to_string()]
+    );
+    assert!(plain.warnings.is_empty());
+}
+
+#[test]
+fn zero_acceleration_is_ignored_with_a_warning() {
+    let zero = estimate("G1 X10 F6000\nM204 S0\nG1 X20\nSET_VELOCITY_LIMIT ACCEL=-5\nG1 X30\n");
+    let plain = estimate("G1 X10 F6000\nG1 X20\nG1 X30\n");
+    assert!(total_time(&zero).is_finite());
+    assert_close(total_time(&zero), total_time(&plain), 1e-9);
+    assert_eq!(
+        zero.warnings,
+        vec![
+            "ignored invalid acceleration 0".to_string(),
+            "ignored invalid acceleration -5".to_string()
+        ]
+    );
+}
+
+#[test]
+fn moves_before_any_feedrate_are_warned_about() {
+    let state = estimate("G1 X10\nG1 X20 F6000\n");
+    assert_eq!(state.warnings.len(), 1);
+    assert!(state.warnings[0].contains("before any feedrate was set"));
+}
+
+#[test]
+fn zero_accel_to_decel_disables_smoothing() {
+    let time = |f: &dyn Fn(&mut PrinterLimits)| {
+        let mut limits = limits();
+        f(&mut limits);
+        total_time(&estimate_with(Planner::from_limits(limits), ZIGZAG))
+    };
+    let unsmoothed = time(&|l| l.set_max_accel_to_decel(1000.0));
+    assert_close(time(&|l| l.set_max_accel_to_decel(0.0)), unsmoothed, 1e-9);
+    assert_close(time(&|l| l.set_minimum_cruise_ratio(1.0)), unsmoothed, 1e-9);
+    // Smoothing does slow the moves down otherwise
+    assert!(time(&|l| l.set_max_accel_to_decel(100.0)) > unsmoothed);
+}
+
+#[test]
+fn m205_sets_the_junction_deviation() {
+    let mut planner = planner();
+    let jd = |scv: f64| scv * scv * (2f64.sqrt() - 1.0) / 1000.0;
+    assert_close(
+        planner.toolhead_state.limits.junction_deviation,
+        jd(5.0),
+        1e-12,
+    );
+
+    feed(&mut planner, "G1 X10 F6000");
+    feed(&mut planner, "M205 S10");
+    assert_close(
+        planner.toolhead_state.limits.square_corner_velocity,
+        10.0,
+        1e-12,
+    );
+    assert_close(
+        planner.toolhead_state.limits.junction_deviation,
+        jd(10.0),
+        1e-12,
+    );
+
+    feed(&mut planner, "M205 X0.02 Y0.03");
+    assert_close(
+        planner.toolhead_state.limits.junction_deviation,
+        0.02,
+        1e-12,
+    );
+}
+
+#[test]
+fn restoring_gcode_state_undoes_speed_changes() {
+    let time = |gcode: &str| total_time(&estimate(gcode));
+    let plain = time("G1 X10 F6000\nG1 X100\n");
+    let scoped = time(
+        "G1 X10 F6000\nSAVE_GCODE_STATE NAME=macro\nG1 F600\nG91\nRESTORE_GCODE_STATE NAME=macro\nG1 X100\n",
+    );
+    assert_close(scoped, plain, 1e-9);
+    // Without restoring, the slower speed and relative positioning stay
+    let leaked = time("G1 X10 F6000\nSAVE_GCODE_STATE NAME=macro\nG1 F600\nG91\nG1 X90\n");
+    assert_close(leaked, time("G1 X10 F6000\nG1 X100 F600\n"), 1e-9);
+}
+
+#[test]
+fn acceleration_changes_apply_per_move_within_a_sequence() {
+    // Like in Klipper, the first move keeps its 1000mm/s², reaching 100mm/s in 0.1s and
+    // cruising for 45mm, while the second decelerates from 100mm/s over all of its 50mm
+    let state = estimate("G1 X50 F6000\nM204 S100\nG1 X100\n");
+    assert_eq!(state.sequences.len(), 1);
+    assert_close(total_time(&state), 0.25 + 0.1 + 0.45 + 1.0, 1e-9);
+}
+
+#[test]
+fn probe_counts() {
+    assert_eq!(parse_probe_count("5,5"), Some(25));
+    assert_eq!(parse_probe_count(" 3 , 4 "), Some(12));
+    assert_eq!(parse_probe_count("7"), Some(49));
+    assert_eq!(parse_probe_count("1,2,3"), None);
+    assert_eq!(parse_probe_count("five"), None);
+}
+
+#[test]
+fn bed_mesh_time_is_modeled_per_point() {
+    let mesh = |probe_point_time: Option<f64>, gcode: &str| {
+        let mut limits = limits();
+        limits.bed_mesh_probe_points = Some(25);
+        let mut planner = Planner::from_limits(limits);
+        planner.probe_point_time = probe_point_time.map(Duration::from_secs_f64);
+        total_time(&estimate_with(planner, gcode))
+    };
+    assert_close(mesh(Some(2.0), "BED_MESH_CALIBRATE\n"), 50.0, 1e-9);
+    assert_close(mesh(Some(2.0), "G29\n"), 50.0, 1e-9);
+    assert_close(
+        mesh(Some(2.0), "BED_MESH_CALIBRATE PROBE_COUNT=3,3\n"),
+        18.0,
+        1e-9,
+    );
+    // Without a time per point it's indeterminate
+    assert_close(mesh(None, "BED_MESH_CALIBRATE\n"), 0.1, 1e-9);
+}
+
+#[test]
+fn zero_square_corner_velocity_stops_at_corners_only() {
+    let mut limits = limits();
+    limits.set_square_corner_velocity(0.0);
+    let mut planner = Planner::from_limits(limits);
+    for line in ["G1 X10 F6000", "G1 X20", "G1 X20 Y10"].iter() {
+        feed(&mut planner, line);
+    }
+    planner.finalize();
+    let moves: Vec<_> = planner.iter().filter_map(|o| o.get_move()).collect();
+
+    // Collinear moves keep their speed, the 90° corner comes to a full stop
+    assert_close(moves[0].end_v, moves[1].start_v, 1e-9);
+    assert!(moves[1].start_v > 50.0, "{}", moves[1].start_v);
+    assert_eq!(moves[1].end_v, 0.0);
+    assert_eq!(moves[2].start_v, 0.0);
+    assert!(moves.iter().all(|m| m.total_time().is_finite()));
+}
+
+fn speed_limits(mut planner: Planner, gcode: &str) -> Vec<SpeedLimit> {
+    for line in gcode.lines() {
+        feed(&mut planner, line);
+    }
+    planner.finalize();
+    planner
+        .iter()
+        .filter_map(|o| o.get_move().map(|m| m.speed_limit()))
+        .collect()
+}
+
+#[test]
+fn moves_are_classified_by_what_limited_their_speed() {
+    // A long straight move reaches its feedrate, the short zigzag after it is slowed by its
+    // sharp corners
+    let gcode = "G1 X100 F6000\nG1 X101 Y1\nG1 X102 Y0\nG1 X103 Y1\n";
+    let zigzag = speed_limits(planner(), gcode);
+    assert_eq!(zigzag.len(), 4);
+    assert_eq!(zigzag[0], SpeedLimit::Feedrate);
+    assert!(zigzag[1..].iter().all(|l| *l == SpeedLimit::Cornering));
+
+    // A lone 1mm move starts and ends at rest, only reaching 31.6mm/s
+    assert_eq!(
+        speed_limits(planner(), "G1 X1 F6000\n"),
+        vec![SpeedLimit::Acceleration]
+    );
+
+    // Retracting 2mm per mm while moving is limited to 25mm/s by the extruder
+    let mut limits = limits();
+    limits.move_checkers.push(MoveChecker::ExtruderLimiter {
+        max_velocity: 50.0,
+        max_accel: 1000.0,
+    });
+    assert_eq!(
+        speed_limits(Planner::from_limits(limits), "M83\nG1 X10 E-20 F6000\n"),
+        vec![SpeedLimit::VelocityLimits]
+    );
+}
+
+#[test]
+fn modeled_homing_time_scales_with_the_distance_to_the_endstop() {
+    let mut limits = limits();
+    limits.homing.insert(
+        "x".to_string(),
+        AxisHoming {
+            position_endstop: 0.0,
+            homing_speed: Some(50.0),
+            second_homing_speed: None,
+            homing_retract_dist: Some(0.0),
+            homing_positive_dir: false,
+        },
+    );
+    let homing_time = |x: f64| {
+        let mut planner = Planner::from_limits(limits.clone());
+        planner.model_homing = true;
+        let state = estimate_with(planner, &format!("G1 X{} F6000\nG28 X\n", x));
+        state.sequences[0].kind_times["Homing"]
+    };
+
+    // Reversing from the travel, so the homing move starts and ends at rest
+    assert_close(homing_time(100.0), 100.0 / 50.0 + 50.0 / 1000.0, 1e-9);
+    assert_close(homing_time(200.0), 200.0 / 50.0 + 50.0 / 1000.0, 1e-9);
+
+    // Without modeling it's indeterminate time instead
+    let state = estimate_with(Planner::from_limits(limits), "G1 X100 F6000\nG28 X\n");
+    assert!(!state.sequences[0].kind_times.contains_key("Homing"));
+}
+
+#[test]
+fn moves_start_from_rest_after_delays() {
+    let first_start_v = |delay: &str| {
+        let mut planner = planner();
+        for line in ["M83", "G1 X10 E1 F6000", delay, "G1 X20 E1"].iter() {
+            feed(&mut planner, line);
+        }
+        planner.finalize();
+        let moves: Vec<_> = planner.iter().filter_map(|o| o.get_move()).collect();
+        (moves[0].end_v, moves[1].start_v)
+    };
+
+    // Without a delay the collinear moves run through at full speed
+    let (end_v, start_v) = first_start_v("; no delay");
+    assert_close(end_v, 100.0, 1e-9);
+    assert_close(start_v, 100.0, 1e-9);
+    for delay in ["G4 P100", "M109 S200", "M600", "M0"].iter() {
+        assert_eq!(first_start_v(delay), (0.0, 0.0), "{}", delay);
+    }
+}
+
+#[test]
+fn short_moves_dont_reach_the_requested_velocity() {
+    let mut planner = planner();
+    for line in ["G1 X1 F6000", "G1 X-99"].iter() {
+        feed(&mut planner, line);
+    }
+    planner.finalize();
+    let moves: Vec<_> = planner.iter().filter_map(|o| o.get_move()).collect();
+
+    // 1mm from rest to rest peaks at sqrt(1000 * 1mm), the 100mm move back reaches 100mm/s
+    assert!(!moves[0].reached_requested());
+    assert_close(moves[0].peak_velocity(), 1000f64.sqrt(), 1e-9);
+    assert!(moves[1].reached_requested());
+    assert_close(moves[1].peak_velocity(), 100.0, 1e-9);
+}
+
+#[test]
+fn feedrates_above_the_velocity_limit_arent_reached() {
+    let mut planner = planner();
+    feed(&mut planner, "G1 X100 F30000");
+    planner.finalize();
+    let moves: Vec<_> = planner.iter().filter_map(|o| o.get_move()).collect();
+
+    // The move is capped at max_velocity, but still asked for 500mm/s
+    assert_close(moves[0].requested_velocity, 500.0, 1e-9);
+    assert_close(moves[0].peak_velocity(), 200.0, 1e-9);
+    assert!(!moves[0].reached_requested());
+    assert_eq!(moves[0].speed_limit(), SpeedLimit::VelocityLimits);
+}
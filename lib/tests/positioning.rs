@@ -0,0 +1,124 @@
+mod common;
+
+use common::{assert_close, estimate, limits, plan, total_time};
+use lib_klipper::gcode::{parse_gcode, GCodeOperation};
+use lib_klipper::planner::{Planner, PlanningOperation};
+
+fn total_distance(gcode: &str) -> f64 {
+    plan(Planner::from_limits(limits()), gcode)
+        .iter()
+        .filter_map(|o| match o {
+            PlanningOperation::Move(m) => Some(m.distance),
+            _ => None,
+        })
+        .sum()
+}
+
+#[test]
+fn g92_1_resets_offsets_rather_than_setting_them() {
+    let op = parse_gcode("G92.1").unwrap().op;
+    assert!(matches!(
+        op,
+        GCodeOperation::Traditional {
+            letter: 'G',
+            code: 92,
+            minor: Some(1),
+            ..
+        }
+    ));
+
+    // After the reset the toolhead is back at X10 in gcode coordinates too
+    assert_close(
+        total_distance("G1 X10 F6000\nG92 X0\nG92.1\nG1 X10\n"),
+        10.0,
+        1e-9,
+    );
+    assert_close(
+        total_distance("G1 X10 F6000\nG92 X0\nG92\nG1 X10\n"),
+        20.0,
+        1e-9,
+    );
+}
+
+#[test]
+fn extrusion_mode_headers_pick_the_extrusion_mode() {
+    let moves = "G1 X10 E1 F6000\nG1 X20 E1\nG1 X30 E1\n";
+    let extruded = |header: &str| {
+        let gcode = format!("{}\n{}", header, moves);
+        estimate(&gcode).sequences[0].total_extrude_distance
+    };
+    assert_close(extruded("; absolute extrusion"), 1.0, 1e-9);
+    assert_close(extruded("; use_relative_e_distances = 0"), 1.0, 1e-9);
+    assert_close(
+        extruded("; use_relative_e_distances = 0\n; relative extrusion"),
+        3.0,
+        1e-9,
+    );
+    // An explicit mode command wins over headers
+    assert_close(extruded("M83\n; absolute extrusion"), 3.0, 1e-9);
+}
+
+#[test]
+fn relative_coordinates_follow_g90_and_g91() {
+    let relative = estimate("G91\nG1 X50 F6000\nG1 X50\nG90\nG1 X0\n");
+    let absolute = estimate("G1 X50 F6000\nG1 X100\nG1 X0\n");
+    assert_close(total_time(&relative), total_time(&absolute), 1e-9);
+}
+
+#[test]
+fn g91_makes_extrusion_relative() {
+    let gcode = "M82\nG1 X10 E1 F6000\nG91\nG1 E-1 F2400\nG90\nG1 X20 E1\n";
+    let state = estimate(gcode);
+    let relative = estimate("M83\nG1 X10 E1 F6000\nG1 E-1 F2400\nG1 X20 E1\n");
+    assert_close(total_time(&state), total_time(&relative), 1e-9);
+    assert_close(state.sequences[0].total_extrude_distance, 1.0, 1e-9);
+}
+
+#[test]
+fn set_kinematic_position_moves_without_moving() {
+    let distance = |gcode: &str| estimate(gcode).sequences[0].total_distance;
+    // The toolhead thinks it's at X50 afterwards, so the move is 50mm long rather than 90mm
+    assert_close(
+        distance("G1 X10 F6000\nSET_KINEMATIC_POSITION X=50 Y=0\nG1 X100\n"),
+        10.0 + 50.0,
+        1e-9,
+    );
+    // Only the given axes change
+    assert_close(
+        distance("G1 X10 Y10 F6000\nSET_KINEMATIC_POSITION X=0\nG1 X10 Y10\n"),
+        10.0f64.hypot(10.0) + 10.0,
+        1e-9,
+    );
+}
+
+#[test]
+fn g92_e_resets_dont_add_extrusion() {
+    let gcode = "\
+M82
+G1 X10 E1 F6000
+G1 X20 E2
+G92 E0
+G1 X30 E1
+G1 X40 E2
+G92 E0
+G1 X50 E1
+";
+    let seq = &estimate(gcode).sequences[0];
+    assert_close(seq.total_extrude_distance, 5.0, 1e-9);
+    assert_close(seq.total_deposited_distance, 5.0, 1e-9);
+    assert_eq!(seq.num_retractions, 0);
+}
+
+#[test]
+fn tiny_extrusions_count_at_large_absolute_e() {
+    let mut gcode = "M82\nG92 E100000\nG1 F6000\n".to_string();
+    for i in 1..=100 {
+        gcode.push_str(&format!(
+            "G1 X{} E{:.3}\n",
+            i,
+            100000.0 + f64::from(i) * 0.001
+        ));
+    }
+    let seq = &estimate(&gcode).sequences[0];
+    assert_close(seq.total_extrude_distance, 0.1, 1e-6);
+}
@@ -0,0 +1,59 @@
+//! Estimates of small fixtures against hand-computed totals, to catch accuracy regressions. Each
+//! total includes the 0.25s Klipper waits before starting a move sequence.
+
+mod common;
+
+use common::{assert_close, estimate, total_time};
+
+fn fixture_time(gcode: &str) -> f64 {
+    total_time(&estimate(gcode))
+}
+
+#[test]
+fn straight_line() {
+    // 2 × 0.1s ramps + 90mm at 100mm/s
+    let expected = 0.25 + 0.2 + 0.9;
+    assert_close(
+        fixture_time(include_str!("fixtures/straight.gcode")),
+        expected,
+        1e-6,
+    );
+}
+
+#[test]
+fn right_angle_corner() {
+    // Each leg ramps up to 100mm/s in 0.1s, down to 5mm/s in 0.095s, and cruises the rest
+    let cruise = (100.0 - 5.0 - (100.0f64.powi(2) - 5.0f64.powi(2)) / 2000.0) / 100.0;
+    let expected = 0.25 + 2.0 * (0.1 + cruise + 0.095);
+    assert_close(
+        fixture_time(include_str!("fixtures/corner.gcode")),
+        expected,
+        1e-6,
+    );
+}
+
+#[test]
+fn half_circle_arc() {
+    // 31 chords of the half circle. Corners between the chords are shallow enough that the arc
+    // is taken at the full 10mm/s, with 0.01s ramps at either end.
+    let chords = 31.0 * 20.0 * (std::f64::consts::PI / 62.0).sin();
+    let expected = 0.25 + 0.02 + (chords - 0.1) / 10.0;
+    assert_close(
+        fixture_time(include_str!("fixtures/arc.gcode")),
+        expected,
+        1e-6,
+    );
+}
+
+#[test]
+fn retraction() {
+    // Two 50mm travels, and a 1mm retraction and unretraction at 40mm/s
+    let travel = 0.2 + 0.4;
+    let extrude_only = 1.0 / 40.0;
+    let expected = 0.25 + 2.0 * (travel + extrude_only);
+    assert_close(
+        fixture_time(include_str!("fixtures/retraction.gcode")),
+        expected,
+        1e-6,
+    );
+}
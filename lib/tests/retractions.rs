@@ -0,0 +1,77 @@
+mod common;
+
+use common::{assert_close, estimate, estimate_with, limits};
+use lib_klipper::firmware_retraction::FirmwareRetractionOptions;
+use lib_klipper::gcode::parse_gcode;
+use lib_klipper::planner::Planner;
+
+#[test]
+fn retraction_uses_the_feedrate_on_its_line() {
+    let mut planner = Planner::from_limits(limits());
+    for line in ["M83", "G1 X10 F6000", "G1 E-10 F2400"].iter() {
+        planner.process_cmd(&parse_gcode(line).unwrap());
+    }
+    planner.finalize();
+    let moves: Vec<_> = planner.iter().filter_map(|o| o.get_move()).collect();
+
+    // 40mm/s, rather than the 100mm/s of the travel before it. Without extruder limits the
+    // retraction accelerates instantly.
+    let retraction = &moves[1];
+    assert_close(retraction.max_cruise_v2, 40.0 * 40.0, 1e-9);
+    assert_close(retraction.cruise_v, 40.0, 1e-9);
+    assert_close(retraction.total_time(), 10.0 / 40.0, 1e-9);
+}
+
+#[test]
+fn hopped_retracted_travels_are_counted_as_maneuvers() {
+    let mut gcode = "M83\n".to_string();
+    for i in 0..2 {
+        gcode.push_str(&format!(
+            "G1 X{} E1 F6000\nG1 E-1 F2400\nG1 Z0.4 F6000\nG1 X{}\nG1 Z0\nG1 E1 F2400\n",
+            60 * i + 10,
+            60 * i + 50
+        ));
+    }
+    gcode.push_str("G1 X120 E1 F6000\n");
+    let seq = &estimate(&gcode).sequences[0];
+    let maneuvers = &seq.travel_maneuvers;
+
+    assert_eq!(maneuvers.count, 2);
+    // Travels and hops don't extrude, so they're all travel time, to which the retractions add
+    // 1mm at 40mm/s each way
+    assert_close(maneuvers.time, seq.total_travel_time + 4.0 / 40.0, 1e-9);
+    // The overhead is all but the XY travels: the retractions and the Z hops around them
+    assert_close(maneuvers.overhead_time, seq.total_retraction_time, 1e-9);
+    assert!(maneuvers.overhead_time > 4.0 / 40.0);
+}
+
+#[test]
+fn slicer_and_firmware_retractions_are_classified_alike() {
+    let slicer = estimate(
+        "M83\nG1 X10 E1 F6000\nG1 E-1 F2400\nG1 Z0.4 F6000\nG1 X50\nG1 Z0\nG1 E1 F2400\nG1 X60 E1 F6000\n",
+    );
+    let mut limits = limits();
+    limits.firmware_retraction = Some(FirmwareRetractionOptions {
+        retract_length: 1.0,
+        unretract_extra_length: 0.0,
+        unretract_speed: 40.0,
+        retract_speed: 40.0,
+        lift_z: 0.4,
+    });
+    let firmware = estimate_with(
+        Planner::from_limits(limits),
+        "M83\nG1 X10 E1 F6000\nG10\nG1 X50\nG11\nG1 X60 E1\n",
+    );
+
+    let (slicer, firmware) = (&slicer.sequences[0], &firmware.sequences[0]);
+    assert_close(slicer.total_time, firmware.total_time, 1e-9);
+    assert_eq!(slicer.num_retractions, 1);
+    assert_eq!(firmware.num_retractions, 1);
+    // Retraction and unretraction take 1/40s each, on top of which come both Z hop moves
+    assert!(slicer.total_retraction_time > 0.05 + 0.05);
+    assert_close(
+        slicer.total_retraction_time,
+        firmware.total_retraction_time,
+        1e-9,
+    );
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use common::{assert_close, estimate, estimate_with, limits, total_time};
+use lib_klipper::estimation::filament_weight;
+use lib_klipper::planner::Planner;
+use lib_klipper::slicer::{
+    parse_filament_diameter, CuraSettings, FeatureAccelerations, SlicerPreset,
+};
+
+fn detect(comment: &str) -> Option<String> {
+    SlicerPreset::determine(comment).map(|s| s.to_string())
+}
+
+#[test]
+fn kisslicer_is_detected() {
+    assert_eq!(detect(" KISSlicer - PRO").as_deref(), Some("KISSlicer PRO"));
+    assert_eq!(detect(" printed with KISSlicer"), None);
+}
+
+#[test]
+fn feature_accelerations_are_applied_and_capped() {
+    let mut accels = FeatureAccelerations::default();
+    for comment in [
+        " external_perimeter_acceleration = 500",
+        " perimeter_acceleration = 5000",
+        " infill_acceleration = 0",
+        " default_acceleration = 800",
+        " bridge_speed = 30",
+    ] {
+        accels.parse_setting(comment);
+    }
+    assert_eq!(accels.get("External perimeter"), Some(500.0));
+    assert_eq!(accels.get("Overhang perimeter"), Some(5000.0));
+    // Zero means the default acceleration
+    assert_eq!(accels.get("Internal infill"), Some(800.0));
+
+    let feature_time = |feature: &str| {
+        let mut planner = Planner::from_limits(limits());
+        planner.feature_accelerations = Some(accels.clone());
+        let gcode = format!(";TYPE:{}\nG1 X100 F6000\n", feature);
+        estimate_with(planner, &gcode)
+    };
+    let external = feature_time("External perimeter");
+    assert_close(total_time(&external), 0.25 + 0.2 + 0.8 + 0.2, 1e-9);
+    assert!(external.warnings.is_empty());
+    // Capped to the configured 1000mm/s²
+    let perimeter = feature_time("Perimeter");
+    assert_close(total_time(&perimeter), 0.25 + 1.1, 1e-9);
+    assert_eq!(perimeter.warnings.len(), 1);
+}
+
+#[test]
+fn filament_diameter_settings() {
+    assert_eq!(
+        parse_filament_diameter(" filament_diameter = 2.85"),
+        Some(2.85)
+    );
+    assert_eq!(
+        parse_filament_diameter(" filament_diameter = 1.75,1.75"),
+        Some(1.75)
+    );
+    assert_eq!(parse_filament_diameter(" filament_diameter = 0"), None);
+    assert_eq!(parse_filament_diameter(" filament_type = PLA"), None);
+}
+
+#[test]
+fn detected_filament_diameter_sets_flow_and_weight() {
+    // PrusaSlicer writes its settings at the end of the file
+    let gcode = "M83\nG1 X100 E10 F6000\n; filament_diameter = 2.85\n";
+    let default = estimate("M83\nG1 X100 E10 F6000\n");
+    let detected = estimate(gcode);
+    assert_close(default.filament_diameter, 1.75, 1e-12);
+    assert_close(detected.filament_diameter, 2.85, 1e-12);
+
+    let (default, seq) = (&default.sequences[0], &detected.sequences[0]);
+    let area_ratio = (2.85f64 / 1.75).powi(2);
+    assert_close(
+        seq.max_flow.unwrap(),
+        default.max_flow.unwrap() * area_ratio,
+        1e-9,
+    );
+    assert_close(seq.filament_weight, filament_weight(10.0, 2.85, 1.24), 1e-9);
+
+    // A configured diameter wins over the detected one
+    let mut limits = limits();
+    limits.filament_diameter = Some(1.75);
+    let configured = estimate_with(Planner::from_limits(limits), gcode);
+    assert_close(configured.filament_diameter, 1.75, 1e-12);
+    assert_close(
+        configured.sequences[0].max_flow.unwrap(),
+        default.max_flow.unwrap(),
+        1e-9,
+    );
+}
+
+/// A Cura settings block setting the given material diameter, split into `;SETTING_3 ` lines
+/// of 60 characters as Cura does, which splits the setting
+fn cura_settings_block(diameter: &str) -> String {
+    let settings = concat!(
+        r#"{"global_quality": "[general]\\nversion = 4\\nname = Standard\\n\\n"#,
+        r#"[values]\\nadhesion_type = skirt\\n\\n", "extruder_quality": ["#,
+        r#""[general]\\nversion = 4\\n\\n[values]\\nmaterial_diameter = DIAMETER\\n"#,
+        r#"speed_print = 60\\n\\n"]}"#,
+    )
+    .replace("DIAMETER", diameter);
+    let chars: Vec<char> = settings.chars().collect();
+    chars
+        .chunks(60)
+        .map(|c| format!(";SETTING_3 {}\n", c.iter().collect::<String>()))
+        .collect()
+}
+
+#[test]
+fn cura_settings_block_values() {
+    let mut settings = CuraSettings::default();
+    for line in cura_settings_block("2.85").lines() {
+        assert!(settings.add_comment(line.trim_start_matches(';')));
+    }
+    assert!(!settings.add_comment("TIME:100"));
+    assert_eq!(settings.get("adhesion_type"), Some("skirt"));
+    assert_eq!(settings.get("speed_print"), Some("60"));
+    assert_eq!(settings.filament_diameter(), Some(2.85));
+}
+
+#[test]
+fn cura_filament_diameter_sets_flow() {
+    let gcode = "M83\nG1 X100 E10 F6000\n";
+    let default = estimate(gcode);
+    let cura = estimate(&format!("{}{}", gcode, cura_settings_block("2.85")));
+    assert_close(cura.filament_diameter, 2.85, 1e-12);
+    assert_close(
+        cura.sequences[0].max_flow.unwrap(),
+        default.sequences[0].max_flow.unwrap() * (2.85f64 / 1.75).powi(2),
+        1e-9,
+    );
+}
@@ -0,0 +1,49 @@
+//! Wall clock times, as used by `estimate --start-at`. Times are kept as seconds since the Unix
+//! epoch and shown in the system's local time zone.
+
+use chrono::{DateTime, Local, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+
+/// Formats accepted for the date and time, without an offset
+const FORMATS: [&str; 2] = ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%dT%H:%M"];
+
+/// Parses `now`, or a date and time like `2024-01-01T12:00`, `2024-01-01 12:00:30` or
+/// `2024-01-01T12:00+02:00`, into seconds since the Unix epoch. Times without an offset are in
+/// local time.
+pub fn parse_timestamp(s: &str) -> Result<f64, String> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("now") {
+        return Ok(seconds(Utc::now()));
+    }
+
+    let invalid = || format!("invalid time {s:?}, expected e.g. `now` or `2024-01-01T12:00`");
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Ok(seconds(t));
+    }
+    let s = s.replacen(' ', "T", 1);
+    for format in FORMATS {
+        if let Ok(t) = DateTime::parse_from_str(&s, &format!("{format}%#z")) {
+            return Ok(seconds(t));
+        }
+        if let Ok(t) = NaiveDateTime::parse_from_str(&s, format) {
+            // Within the hour skipped by a daylight saving change there's no such local time
+            let t = Local
+                .from_local_datetime(&t)
+                .earliest()
+                .ok_or_else(invalid)?;
+            return Ok(seconds(t));
+        }
+    }
+    Err(invalid())
+}
+
+/// Formats seconds since the Unix epoch as local time, e.g. `2024-01-01T12:00:00+01:00`
+pub fn format_timestamp(t: f64) -> String {
+    match Local.timestamp_opt(t.round() as i64, 0).earliest() {
+        Some(t) => t.to_rfc3339_opts(SecondsFormat::Secs, true),
+        None => format!("{t:.0}"),
+    }
+}
+
+fn seconds<Tz: TimeZone>(t: DateTime<Tz>) -> f64 {
+    t.timestamp() as f64 + f64::from(t.timestamp_subsec_nanos()) / 1e9
+}
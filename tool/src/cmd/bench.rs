@@ -0,0 +1,55 @@
+use std::io::BufReader;
+use std::time::Instant;
+
+use clap::Parser;
+
+use crate::{open_input, Opts};
+
+#[derive(Parser, Debug)]
+pub struct BenchCmd {
+    input: String,
+}
+
+impl BenchCmd {
+    pub fn run(&self, opts: &Opts) {
+        let src = open_input(&self.input);
+        let mut rdr = opts.gcode_reader(BufReader::new(src));
+
+        // Parse everything up front, so parsing and planning can be timed separately
+        let start = Instant::now();
+        let cmds = rdr
+            .by_ref()
+            .filter_map(|cmd| opts.read_command(&self.input, cmd))
+            .collect::<Vec<_>>();
+        let parse_time = start.elapsed().as_secs_f64();
+        opts.check_skipped_lines(&self.input, &rdr);
+        opts.check_thumbnails(&self.input, &rdr);
+
+        let mut planner = opts.make_planner();
+        let mut moves = 0;
+        let start = Instant::now();
+        for (i, cmd) in cmds.iter().enumerate() {
+            planner.process_cmd(cmd);
+            if opts.should_drain(i) {
+                moves += planner.drain_ready().filter(|o| o.is_move()).count();
+            }
+        }
+        planner.finalize();
+        moves += planner.drain_ready().filter(|o| o.is_move()).count();
+        let plan_time = start.elapsed().as_secs_f64();
+
+        println!("Lines:      {}", cmds.len());
+        println!("Moves:      {}", moves);
+        println!(
+            "Parsing:    {:.3}s ({:.0} lines/s)",
+            parse_time,
+            cmds.len() as f64 / parse_time
+        );
+        println!(
+            "Planning:   {:.3}s ({:.0} moves/s)",
+            plan_time,
+            moves as f64 / plan_time
+        );
+        println!("Total:      {:.3}s", parse_time + plan_time);
+    }
+}
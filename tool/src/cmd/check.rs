@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use crate::cmd::post_process::{compare_with_slicer, PostProcessCmd, SlicerOverride};
+use crate::{exit_with_error, Opts};
+
+#[derive(Parser, Debug)]
+pub struct CheckCmd {
+    #[clap(parse(try_from_str))]
+    filename: PathBuf,
+    /// Largest accepted difference from the slicer's estimate, either relative like `5%` or in
+    /// seconds like `30`
+    #[clap(long, default_value = "5%", parse(try_from_str = parse_tolerance))]
+    tolerance: Tolerance,
+    /// Use the given slicer profile to find the embedded estimate instead of detecting it
+    #[clap(arg_enum, long)]
+    slicer: Option<SlicerOverride>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Tolerance {
+    Percent(f64),
+    Seconds(f64),
+}
+
+fn parse_tolerance(s: &str) -> Result<Tolerance, String> {
+    let (value, percent) = match s.trim().strip_suffix('%') {
+        Some(v) => (v, true),
+        None => (s.trim().trim_end_matches('s'), false),
+    };
+    let value = value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite() && *v >= 0.0)
+        .ok_or_else(|| format!("invalid tolerance {s:?}, expected e.g. 5% or 30"))?;
+    Ok(if percent {
+        Tolerance::Percent(value)
+    } else {
+        Tolerance::Seconds(value)
+    })
+}
+
+impl Tolerance {
+    fn allows(&self, total_time: f64, slicer_estimate: f64) -> bool {
+        let delta = (total_time - slicer_estimate).abs();
+        match *self {
+            Tolerance::Percent(p) => delta <= slicer_estimate.abs() * p / 100.0,
+            Tolerance::Seconds(s) => delta <= s,
+        }
+    }
+}
+
+impl CheckCmd {
+    pub fn run(&self, opts: &Opts) {
+        let result = compare_with_slicer(opts, &self.filename, self.slicer)
+            .unwrap_or_else(|e| exit_with_error(format!("{}: {e}", self.filename.display())));
+        let slicer_estimate = result.slicer_estimate.unwrap_or_else(|| {
+            exit_with_error(format!(
+                "no slicer estimate found in {}",
+                self.filename.display()
+            ))
+        });
+
+        if let Some(slicer) = result.slicer.as_ref() {
+            println!("Detected slicer:            {}", slicer);
+        }
+        PostProcessCmd::print_comparison(Some(slicer_estimate), result.total_time);
+        if !self.tolerance.allows(result.total_time, slicer_estimate) {
+            println!("Check failed: difference exceeds tolerance");
+            std::process::exit(1);
+        }
+        println!("Check passed");
+    }
+}
@@ -1,12 +1,28 @@
 use clap::Parser;
 
-use crate::Opts;
+use crate::{exit_with_error, Opts};
 
 #[derive(Parser, Debug)]
-pub struct DumpConfigCmd;
+pub struct DumpConfigCmd {
+    /// List each field with where its value came from: the default, Moonraker, the config file,
+    /// or a `-c` override
+    #[clap(long)]
+    explain: bool,
+}
 
 impl DumpConfigCmd {
     pub fn run(&self, opts: &Opts) {
-        let _ = serde_json::to_writer_pretty(std::io::stdout(), &opts.printer_limits());
+        if !self.explain {
+            let _ = serde_json::to_writer_pretty(std::io::stdout(), &opts.printer_limits());
+            return;
+        }
+
+        let provenance = opts
+            .config_provenance()
+            .unwrap_or_else(|e| exit_with_error(format!("failed to load configuration: {e}")));
+        let limits = serde_json::to_value(opts.printer_limits()).expect("Serialization error");
+        for (field, source) in provenance {
+            println!("{field} = {} ({source})", limits[&field]);
+        }
     }
 }
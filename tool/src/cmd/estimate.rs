@@ -1,35 +1,49 @@
 use std::collections::BTreeMap;
-use std::f64::EPSILON;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
 
-use lib_klipper::gcode::GCodeReader;
-use lib_klipper::glam::{DVec2, Vec4Swizzles};
-use lib_klipper::planner::{Delay, Planner, PlanningMove, PlanningOperation};
+use lib_klipper::estimation::{
+    filament_cross_section, filament_weight, EstimationState, Estimator, PhaseTimings,
+    UnderrunModel, DEFAULT_FILAMENT_DENSITY,
+};
+use lib_klipper::gcode::{parse_gcode, read_line, GCodeReadError};
+use lib_klipper::glam::{DVec2, DVec4, Vec4Swizzles};
+use lib_klipper::planner::{Planner, PrinterLimits};
+use lib_klipper::slicer::FeatureAccelerations;
 
 use clap::Parser;
 use ordered_float::NotNan;
-use serde::{ser::SerializeSeq, Serialize, Serializer};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-use crate::Opts;
+use crate::clock::{format_timestamp, parse_timestamp};
+use crate::{exit_with_error, exit_with_io_error, open_input, try_open_input, LimitError, Opts};
 
-fn format_time(mut seconds: f64) -> String {
+fn format_time(mut seconds: f64, precision: usize) -> String {
     let mut parts = Vec::new();
 
-    if seconds > 86400.0 {
+    // Round up front, so e.g. 59.9999s carries over to 1m rather than printing as 60.000s
+    let factor = 10f64.powi(precision as i32);
+    seconds = (seconds * factor).round() / factor;
+
+    if seconds >= 86400.0 {
         parts.push(format!("{}d", (seconds / 86400.0).floor()));
         seconds %= 86400.0;
     }
-    if seconds > 3600.0 {
+    if seconds >= 3600.0 {
         parts.push(format!("{}h", (seconds / 3600.0).floor()));
         seconds %= 3600.0;
     }
-    if seconds > 60.0 {
+    if seconds >= 60.0 {
         parts.push(format!("{}m", (seconds / 60.0).floor()));
         seconds %= 60.0;
     }
     if seconds > 0.0 {
-        parts.push(format!("{:.3}s", seconds));
+        parts.push(format!("{:.precision$}s", seconds));
     }
 
     if parts.is_empty() {
@@ -39,333 +53,1076 @@ fn format_time(mut seconds: f64) -> String {
     parts.join("")
 }
 
+/// How durations are written in human readable output
+#[derive(clap::ArgEnum, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TimeFormat {
+    /// E.g. `1h2m3.000s`
+    Compact,
+    /// Hours, minutes and seconds, e.g. `01:02:03.000`
+    Clock,
+    /// ISO 8601 duration, e.g. `PT1H2M3S`
+    Iso8601,
+    /// Plain seconds, e.g. `3723.000`
+    Seconds,
+}
+
+impl TimeFormat {
+    fn format(self, seconds: f64, precision: usize) -> String {
+        // Round up front, so e.g. 59.9999s carries over to the next minute
+        let factor = 10f64.powi(precision as i32);
+        let rounded = (seconds * factor).round() / factor;
+        let hours = (rounded / 3600.0).floor();
+        let minutes = (rounded % 3600.0 / 60.0).floor();
+        let secs = rounded % 60.0;
+        match self {
+            TimeFormat::Compact => format_time(seconds, precision),
+            TimeFormat::Clock => {
+                let width = if precision > 0 { precision + 3 } else { 2 };
+                format!("{hours:02}:{minutes:02}:{secs:0width$.precision$}")
+            }
+            TimeFormat::Iso8601 => {
+                let mut s = "PT".to_string();
+                if hours > 0.0 {
+                    s.push_str(&format!("{hours}H"));
+                }
+                if minutes > 0.0 {
+                    s.push_str(&format!("{minutes}M"));
+                }
+                if secs > 0.0 || rounded == 0.0 {
+                    let secs = format!("{secs:.precision$}");
+                    let secs = if secs.contains('.') {
+                        secs.trim_end_matches('0').trim_end_matches('.')
+                    } else {
+                        &secs
+                    };
+                    s.push_str(&format!("{secs}S"));
+                }
+                s
+            }
+            TimeFormat::Seconds => format!("{rounded:.precision$}"),
+        }
+    }
+}
+
+/// Net filament retraction over a run beyond which the extrusion mode is likely wrong
+const MAX_NET_RETRACTION: f64 = 100.0;
+
+/// How long a watched file's size must stay the same before it's estimated
+const WATCH_SETTLE_TIME: Duration = Duration::from_secs(1);
+
+#[derive(clap::ArgEnum, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum InputFormat {
+    Gcode,
+    /// A JSON array of moves
+    Json,
+}
+
+/// A move given as JSON with `--input-format json`, e.g.
+/// `{"start": [0, 0, 0, 0], "end": [10, 0, 0, 0.5], "feedrate": 100}`. Positions are
+/// `[x, y, z, e]` in mm and the feedrate is in mm/s. Without a start, the move continues from
+/// the end of the previous one.
+#[derive(Debug, Deserialize)]
+struct JsonMove {
+    start: Option<[f64; 4]>,
+    end: [f64; 4],
+    feedrate: f64,
+}
+
 #[derive(clap::ArgEnum, Debug, Clone, Copy, Eq, PartialEq)]
 pub enum OutputFormat {
     Human,
     Json,
+    /// Metadata in the shape read by Moonraker based UIs like Mainsail and Fluidd
+    UiJson,
+    /// Time attributed to object, kind and phase as folded stacks for flamegraph tools, in
+    /// microseconds
+    Folded,
+}
+
+/// File metadata as reported by Moonraker, and shown by Mainsail and Fluidd
+#[derive(Debug, Serialize)]
+struct UiMetadata {
+    estimated_time: f64,
+    /// Filament used in mm
+    filament_total: f64,
+    /// Filament used in g
+    filament_weight_total: f64,
+    layer_count: usize,
+    object_height: f64,
+    object_count: usize,
+}
+
+impl UiMetadata {
+    fn new(state: &EstimationState, filament_density: f64) -> UiMetadata {
+        let seqs = state.sequences.iter();
+        let filament_total = seqs.clone().map(|s| s.total_extrude_distance).sum::<f64>();
+        let layer_count = seqs
+            .clone()
+            .map(|s| match s.marker_layer_times.len() {
+                0 => s.layer_times.len(),
+                n => n,
+            })
+            .sum();
+        let object_height = seqs
+            .clone()
+            .filter_map(|s| s.layer_times.keys().next_back())
+            .fold(0.0f64, |a, z| a.max(z.into_inner()));
+        UiMetadata {
+            estimated_time: seqs.map(|s| s.total_time).sum(),
+            filament_total,
+            filament_weight_total: filament_weight(
+                filament_total,
+                state.filament_diameter,
+                filament_density,
+            ),
+            layer_count,
+            object_height,
+            object_count: state.objects.len(),
+        }
+    }
 }
 
 #[derive(Parser, Debug)]
 pub struct EstimateCmd {
-    input: String,
+    #[clap(required_unless_present = "watch")]
+    input: Vec<String>,
+    /// Keep running, estimating each `.gcode` file written to the given directory
+    #[clap(long, conflicts_with = "input")]
+    watch: Option<PathBuf>,
     #[clap(arg_enum, long, short, default_value_t = OutputFormat::Human)]
     format: OutputFormat,
+    /// Format of the inputs: gcode, or a JSON array of moves like
+    /// `[{"start": [0, 0, 0, 0], "end": [10, 0, 0, 0.5], "feedrate": 100}]` with positions as
+    /// `[x, y, z, e]` and feedrates in mm/s. The start can be left out to continue from the
+    /// previous move.
+    #[clap(arg_enum, long, default_value_t = InputFormat::Gcode)]
+    input_format: InputFormat,
     #[clap(long)]
     omit_move_kinds: bool,
+    /// Group the move kind distribution by canonical kind, e.g. `External perimeter` for both
+    /// PrusaSlicer's `External perimeter` and Cura's `WALL-OUTER`
+    #[clap(long)]
+    canonical_kinds: bool,
     #[clap(long)]
     omit_layer_times: bool,
+    /// Show the local time each layer is expected to finish at, for a print started at the given
+    /// time, either `now` or e.g. `2024-01-01T12:00`
+    #[clap(long, parse(try_from_str = parse_timestamp))]
+    start_at: Option<f64>,
+    /// Model stalls caused by the host not keeping up with short moves
+    #[clap(long)]
+    model_underruns: bool,
+    /// Number of moves per second the host can process, used with --model-underruns
+    #[clap(long, default_value_t = 1000.0)]
+    underrun_move_rate: f64,
+    /// Number of moves that can be queued ahead of the toolhead, used with --model-underruns
+    #[clap(long, default_value_t = 100)]
+    underrun_buffer_size: usize,
+    /// Number of decimals for reported times. Also rounds times in JSON output
+    #[clap(long)]
+    time_precision: Option<usize>,
+    /// How durations are written in human readable output
+    #[clap(arg_enum, long, default_value_t = TimeFormat::Compact)]
+    time_format: TimeFormat,
+    /// Estimate once for each of the given values of a numeric setting, e.g.
+    /// `max_acceleration=1000,2000,3000`, and show the resulting total times
+    #[clap(long)]
+    sweep: Option<String>,
+    /// Also write the result as JSON to `<input>.estimate.json`
+    #[clap(long)]
+    write_sidecar: bool,
+    /// Gcode run before each input, either a filename or inline gcode with lines separated by `\n`
+    #[clap(long)]
+    start_gcode: Option<String>,
+    /// Gcode run after each input, either a filename or inline gcode with lines separated by `\n`
+    #[clap(long)]
+    end_gcode: Option<String>,
+    /// Estimate as if the named object was canceled with `EXCLUDE_OBJECT`, can be repeated
+    #[clap(long)]
+    exclude_object: Vec<String>,
+    /// Only time the moves of the named object, e.g. to quote single parts of a plate
+    #[clap(long)]
+    only_object: Option<String>,
+    /// Only show the given number of slowest layers, with layer time statistics, instead of
+    /// all layer times
+    #[clap(long)]
+    top_layers: Option<usize>,
+    /// Also report settings found in the file that don't affect the estimate
+    #[clap(long, short)]
+    verbose: bool,
+    /// Apply the per-feature accelerations from the slicer settings in the file at each `;TYPE:`
+    /// marker, capped to the configured max_accel
+    #[clap(long)]
+    slicer_accel: bool,
+    /// Write JSON output without whitespace
+    #[clap(long)]
+    json_compact: bool,
+    /// Report the time spent parsing, planning, running lookahead and writing output to stderr.
+    /// With multiple files the times are summed over all of them.
+    #[clap(long)]
+    profile: bool,
 }
 
-#[derive(Debug, Clone, PartialEq, Default, Serialize)]
-struct EstimationState {
-    sequences: Vec<EstimationSequence>,
+/// Why an input couldn't be estimated
+#[derive(Error, Debug)]
+enum EstimateError {
+    #[error(transparent)]
+    Limit(#[from] LimitError),
+    #[error(transparent)]
+    Read(#[from] GCodeReadError),
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error("invalid move list: {0}")]
+    InvalidMoves(#[source] serde_json::Error),
 }
 
-#[derive(Debug, Clone, PartialEq, Default, Serialize)]
-struct EstimationSequence {
-    total_time: f64,
-    total_distance: f64,
-    total_extrude_distance: f64,
-    max_flow: Option<f64>,
-    max_speed: Option<f64>,
-    num_moves: usize,
-    total_z_time: f64,
-    total_output_time: f64,
-    total_travel_time: f64,
-    total_extrude_only_time: f64,
-    phase_times: EstimationPhaseTimes,
-    kind_times: BTreeMap<String, f64>,
-    #[serde(serialize_with = "serialize_layer_times")]
-    layer_times: BTreeMap<NotNan<f64>, f64>,
+/// Gcode run before and after each input, from `--start-gcode` and `--end-gcode`
+#[derive(Debug, Default)]
+struct SurroundingGcode {
+    start: String,
+    end: String,
 }
 
-#[derive(Debug, Clone, PartialEq, Default, Serialize)]
-struct EstimationPhaseTimes {
-    acceleration: f64,
-    cruise: f64,
-    deceleration: f64,
+/// Loads gcode passed as an option, either from a file or given inline. Exits if it names a file
+/// that can't be read, or looks like a path to a file that doesn't exist.
+fn load_gcode_arg(option: &str, arg: &str) -> String {
+    let mut gcode = if Path::new(arg).is_file() {
+        std::fs::read_to_string(arg)
+            .unwrap_or_else(|e| exit_with_error(format!("{option} {arg}: {e}")))
+    } else if looks_like_path(arg) {
+        exit_with_error(format!("{option} {arg}: no such file"))
+    } else {
+        let gcode = arg.replace("\\n", "\n");
+        let has_commands = gcode
+            .lines()
+            .any(|line| parse_gcode(line).map_or(true, |cmd| !cmd.op.is_nop()));
+        if !has_commands {
+            eprintln!("Warning: {option} {arg:?} is neither a file nor any gcode commands");
+        }
+        gcode
+    };
+    // Keep the surrounding file from continuing on the last line
+    gcode.insert(0, '\n');
+    gcode.push('\n');
+    gcode
 }
 
-fn serialize_layer_times<S: Serializer>(
-    lts: &BTreeMap<NotNan<f64>, f64>,
-    serializer: S,
-) -> Result<S::Ok, S::Error> {
-    let mut seq = serializer.serialize_seq(Some(lts.len()))?;
+/// Whether inline gcode is more likely a mistyped path, being a single word with a directory
+/// separator or a file extension, e.g. `start.gcode`
+fn looks_like_path(arg: &str) -> bool {
+    if arg.contains("\\n") || arg.contains(char::is_whitespace) {
+        return false;
+    }
+    let has_extension = Path::new(arg)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map_or(false, |e| e.chars().all(|c| c.is_ascii_alphabetic()));
+    arg.contains(['/', '\\']) || has_extension
+}
 
-    for (z, t) in lts {
-        seq.serialize_element(&[z, t])?;
+/// Collects the per-feature accelerations from the slicer settings comments in a file. These are
+/// usually at the end of the file, so it's read ahead of the estimate.
+fn load_feature_accelerations(filename: &str) -> std::io::Result<FeatureAccelerations> {
+    let mut rdr = BufReader::new(File::open(filename)?);
+    let mut accels = FeatureAccelerations::default();
+    let mut buf = Vec::new();
+    while read_line(&mut rdr, &mut buf)? > 0 {
+        let line = String::from_utf8_lossy(&buf);
+        if let Some(comment) = line.trim_start().strip_prefix(';') {
+            accels.parse_setting(comment);
+        }
+        buf.clear();
     }
+    Ok(accels)
+}
 
-    seq.end()
+fn round_json_number(value: &mut serde_json::Value, precision: usize) {
+    if let Some(v) = value.as_f64() {
+        let factor = 10f64.powi(precision as i32);
+        if let Some(n) = serde_json::Number::from_f64((v * factor).round() / factor) {
+            *value = serde_json::Value::Number(n);
+        }
+    }
 }
 
-impl EstimationState {
-    fn add(&mut self, planner: &Planner, op: &PlanningOperation) {
-        match op {
-            PlanningOperation::Move(m) => self.add_move(planner, m),
-            PlanningOperation::Delay(Delay::Pause(t)) => {
-                let t = t.as_secs_f64();
-                let seq = self.get_cur_seq();
-                seq.total_time += t;
-                let kind = "Dwell";
-                if let Some(kt) = seq.kind_times.get_mut(kind) {
-                    *kt += t;
-                } else {
-                    seq.kind_times.insert(kind.to_string(), t);
+/// Rounds the times in JSON output: fields named `time` or ending in `_time`, and everything in
+/// fields ending in `_times`. Distances, speeds and other numbers are left as they are.
+fn round_json_times(value: &mut serde_json::Value, precision: usize, is_time: bool) {
+    use serde_json::Value;
+    match value {
+        Value::Number(n) if is_time && n.is_f64() => round_json_number(value, precision),
+        Value::Array(a) => a
+            .iter_mut()
+            .for_each(|v| round_json_times(v, precision, is_time)),
+        Value::Object(o) => {
+            for (key, v) in o.iter_mut() {
+                match (key.as_str(), v) {
+                    // Pairs of Z height and time
+                    ("layer_times", Value::Array(layers)) => layers
+                        .iter_mut()
+                        .filter_map(|l| l.get_mut(1))
+                        .for_each(|t| round_json_number(t, precision)),
+                    (key, v) => {
+                        let is_time = is_time
+                            || key == "time"
+                            || key.ends_with("_time")
+                            || key.ends_with("_times");
+                        round_json_times(v, precision, is_time);
+                    }
                 }
             }
-            PlanningOperation::Delay(Delay::Indeterminate(t, k)) => {
-                // If current sequence has moves or there is no sequence, make a new one
-                if self
-                    .sequences
-                    .last()
-                    .map(|s| s.num_moves != 0)
-                    .unwrap_or(true)
-                {
-                    self.sequences.push(EstimationSequence::default());
-                }
-                let seq = self.sequences.last_mut().unwrap();
-                let t = t.as_secs_f64();
-                seq.total_time += t;
-                let kind = planner.kind_str(k).unwrap_or("Other");
-                if let Some(kt) = seq.kind_times.get_mut(kind) {
-                    *kt += t;
-                } else {
-                    seq.kind_times.insert(kind.to_string(), t);
+        }
+        _ => {}
+    }
+}
+
+impl EstimateCmd {
+    /// Warns if extrusion totals suggest the extrusion mode was wrong, e.g. an absolute
+    /// extrusion file without `M82` being read as relative
+    fn check_extrusion(input: &str, state: &EstimationState) {
+        let implausible = state.sequences.iter().any(|seq| {
+            seq.total_extrude_distance < -MAX_NET_RETRACTION
+                || seq.total_extrude_distance > seq.total_distance.max(MAX_NET_RETRACTION)
+        });
+        if implausible {
+            eprintln!(
+                "Warning: extrusion totals for {input} are implausible, the extrusion mode may be \
+                 misdetected. Add M82 or M83 to the start of the file"
+            );
+        }
+    }
+
+    /// Feeds gcode to an estimator, returning the time spent parsing it
+    fn feed_gcode<R: Read>(
+        opts: &Opts,
+        input: &str,
+        estimator: &mut Estimator,
+        src: R,
+    ) -> Result<Duration, EstimateError> {
+        let mut rdr = opts.gcode_reader(BufReader::new(src));
+        let mut parse_time = Duration::ZERO;
+        loop {
+            let start = Instant::now();
+            let Some(cmd) = rdr.next() else { break };
+            parse_time += start.elapsed();
+            if let Some(cmd) = opts.try_read_command(input, cmd)? {
+                estimator.feed(&cmd);
+            }
+            opts.check_limits(estimator.planner())?;
+        }
+        opts.check_decimal_comma(&rdr);
+        opts.check_skipped_lines(input, &rdr);
+        opts.check_thumbnails(input, &rdr);
+        Ok(parse_time)
+    }
+
+    fn estimate(
+        &self,
+        opts: &Opts,
+        input: &str,
+        limits: &PrinterLimits,
+        surrounding: &SurroundingGcode,
+    ) -> Result<EstimationState, EstimateError> {
+        use std::io::Cursor;
+        let json = self.input_format == InputFormat::Json;
+        let mut planner = opts.make_planner_from(limits.clone());
+        planner
+            .excluded_objects
+            .extend(self.exclude_object.iter().map(|o| o.to_uppercase()));
+        planner.only_object = self.only_object.as_ref().map(|o| o.to_uppercase());
+        if self.slicer_accel {
+            if json {
+                eprintln!("Warning: --slicer-accel needs gcode input, ignoring");
+            } else if input == "-" {
+                eprintln!("Warning: --slicer-accel can't read ahead in stdin, ignoring");
+            } else {
+                let accels = load_feature_accelerations(input)?;
+                if accels.is_empty() {
+                    eprintln!("Warning: {input}: no slicer acceleration settings found");
                 }
+                planner.feature_accelerations = Some(accels);
             }
-            _ => {}
         }
+        let mut estimator =
+            Estimator::new(planner)
+                .underrun_model(self.model_underruns.then(|| {
+                    UnderrunModel::new(self.underrun_move_rate, self.underrun_buffer_size)
+                }))
+                .profile(self.profile)
+                .drain_interval(opts.drain_interval);
+
+        // Start and end gcode run through the same planner, sharing toolhead state
+        let mut parse_time = Duration::ZERO;
+        if json {
+            let moves: Vec<JsonMove> = serde_json::from_reader(try_open_input(input)?)
+                .map_err(EstimateError::InvalidMoves)?;
+            let start = Cursor::new(&surrounding.start);
+            parse_time += Self::feed_gcode(opts, input, &mut estimator, start)?;
+            for m in moves {
+                let end = DVec4::from(m.end);
+                estimator.feed_move(m.start.map(DVec4::from), end, m.feedrate);
+                opts.check_limits(estimator.planner())?;
+            }
+            let end = Cursor::new(&surrounding.end);
+            parse_time += Self::feed_gcode(opts, input, &mut estimator, end)?;
+        } else {
+            let src = Cursor::new(&surrounding.start)
+                .chain(try_open_input(input)?)
+                .chain(Cursor::new(&surrounding.end));
+            parse_time += Self::feed_gcode(opts, input, &mut estimator, src)?;
+        }
+
+        let mut state = estimator.finish();
+        if let Some(object) = self.only_object.as_ref() {
+            if state.sequences.iter().all(|s| s.num_moves == 0) {
+                eprintln!("Warning: {input}: no moves found for object {object:?}");
+            }
+        }
+        if let Some(timings) = state.timings.as_mut() {
+            timings.parse += parse_time;
+        }
+        if !self.verbose {
+            state.pressure_advance.clear();
+        }
+        Ok(state)
     }
 
-    fn get_cur_seq(&mut self) -> &mut EstimationSequence {
-        if self.sequences.is_empty() {
-            self.sequences.push(EstimationSequence::default());
+    pub fn run(&self, opts: &Opts) {
+        // Load the config up front, each file gets its own planner from it
+        let _ = opts.printer_limits();
+        let surrounding = self.surrounding_gcode();
+        if let Some(dir) = &self.watch {
+            if self.sweep.is_some() {
+                exit_with_error("--sweep can't be used with --watch");
+            }
+            return self.watch(opts, dir, &surrounding);
+        }
+        if let Some(sweep) = &self.sweep {
+            return self.sweep(opts, sweep, &surrounding);
         }
-        self.sequences.last_mut().unwrap()
+        let limits = opts.printer_limits();
+        let states = self
+            .input
+            .par_iter()
+            .map(|input| {
+                self.estimate(opts, input, limits, &surrounding)
+                    .map_err(|e| format!("{input}: {e}"))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_or_else(|e| exit_with_error(e));
+        self.output(opts, &self.input, &states);
     }
 
-    fn add_move(&mut self, planner: &Planner, m: &PlanningMove) {
-        let seq = self.get_cur_seq();
-        if seq.num_moves == 0 {
-            seq.total_time += 0.25;
+    fn surrounding_gcode(&self) -> SurroundingGcode {
+        let load = |option, arg: &Option<String>| {
+            arg.as_deref()
+                .map(|arg| load_gcode_arg(option, arg))
+                .unwrap_or_default()
+        };
+        SurroundingGcode {
+            start: load("--start-gcode", &self.start_gcode),
+            end: load("--end-gcode", &self.end_gcode),
         }
+    }
 
-        seq.total_time += m.total_time();
-        seq.total_distance += m.distance;
-        seq.total_extrude_distance += m.end.w - m.start.w;
-        seq.num_moves += 1;
-        seq.max_speed = Some(seq.max_speed.unwrap_or(0.0).max(m.cruise_v));
+    /// Estimates each input once per value of a setting, showing the total time of each
+    fn sweep(&self, opts: &Opts, sweep: &str, surrounding: &SurroundingGcode) {
+        let (key, values) = sweep
+            .split_once('=')
+            .and_then(|(key, values)| {
+                let values = values.split(',').map(|v| v.trim().parse::<f64>().ok());
+                Some((key.trim(), values.collect::<Option<Vec<_>>>()?))
+            })
+            .unwrap_or_else(|| {
+                exit_with_error(format!(
+                    "invalid sweep {sweep:?}, expected <setting>=<value>,<value>,..."
+                ))
+            });
+        let limits = values
+            .iter()
+            .map(|&v| Self::limits_with(opts.printer_limits(), key, v))
+            .collect::<Vec<_>>();
 
-        match (m.is_extrude_move(), m.is_kinematic_move()) {
-            (true, true) => {
-                seq.total_output_time += m.total_time();
-                if let Some(flow_rate) = m.flow_rate(1.75 / 2.0) {
-                    seq.max_flow = Some(seq.max_flow.unwrap_or(0.0).max(flow_rate));
+        let times = self
+            .input
+            .par_iter()
+            .map(|input| {
+                limits
+                    .par_iter()
+                    .map(|limits| {
+                        let state = self
+                            .estimate(opts, input, limits, surrounding)
+                            .map_err(|e| format!("{input}: {e}"))?;
+                        Ok(state.sequences.iter().map(|s| s.total_time).sum::<f64>())
+                    })
+                    .collect::<Result<Vec<_>, String>>()
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_or_else(|e| exit_with_error(e));
+
+        let precision = self.time_precision.unwrap_or(3);
+        let value_length = values.iter().map(|v| v.to_string().len()).max();
+        let value_length = value_length.unwrap_or(0).max(key.len());
+        for (i, (input, times)) in self.input.iter().zip(times).enumerate() {
+            if self.label_files(&self.input) {
+                if i > 0 {
+                    println!();
                 }
+                println!("File: {}", input);
+            }
+            println!("{key:value_length$}  Total time");
+            for (value, time) in values.iter().zip(times) {
+                let time = self.time_format.format(time, precision);
+                println!("{:value_length$}  {time}", value.to_string());
             }
-            (true, false) => seq.total_extrude_only_time += m.total_time(),
-            (false, true) => seq.total_travel_time += m.total_time(),
-            _ => {}
         }
+    }
 
-        {
-            let pt = &mut seq.phase_times;
-            pt.acceleration += m.accel_time();
-            pt.cruise += m.cruise_time();
-            pt.deceleration += m.decel_time();
-        }
+    /// Returns the limits with a numeric setting changed, exiting if there's no such setting
+    fn limits_with(limits: &PrinterLimits, key: &str, value: f64) -> PrinterLimits {
+        let mut json = serde_json::to_value(limits).expect("Serialization error");
+        json[key] = serde_json::json!(value);
+        let limits = serde_json::from_value::<PrinterLimits>(json)
+            .ok()
+            .filter(|l| serde_json::to_value(l).ok().and_then(|l| l[key].as_f64()) == Some(value));
+        let mut limits = limits.unwrap_or_else(|| {
+            exit_with_error(format!("{key} isn't a numeric setting that can be swept"))
+        });
+        limits.recalculate();
+        limits
+    }
 
-        let kind = planner.move_kind_str(m).unwrap_or("Other");
-        if let Some(t) = seq.kind_times.get_mut(kind) {
-            *t += m.total_time();
-        } else {
-            seq.kind_times.insert(kind.to_string(), m.total_time());
+    /// Writes the results, followed by the profile if requested
+    fn output(&self, opts: &Opts, inputs: &[String], states: &[EstimationState]) {
+        let start = Instant::now();
+        self.write_output(opts, inputs, states);
+        if self.profile {
+            Self::print_profile(states, start.elapsed());
         }
+    }
 
-        if (m.start.z - m.end.z).abs() < EPSILON {
-            *seq.layer_times
-                .entry(NotNan::new((m.start.z * 1000.0).round() / 1000.0).unwrap())
-                .or_insert(0.0) += m.total_time();
-        } else {
-            seq.total_z_time += m.total_time();
+    fn print_profile(states: &[EstimationState], output: Duration) {
+        let mut total = PhaseTimings::default();
+        for timings in states.iter().filter_map(|s| s.timings.as_ref()) {
+            total.parse += timings.parse;
+            total.plan += timings.plan;
+            total.lookahead += timings.lookahead;
+        }
+        eprintln!("Profile:");
+        for (phase, time) in [
+            ("Parsing", total.parse),
+            ("Planning", total.plan),
+            ("Lookahead", total.lookahead),
+            ("Output", output),
+        ] {
+            eprintln!("  {:<10} {:.3}s", format!("{phase}:"), time.as_secs_f64());
         }
     }
-}
 
-impl EstimateCmd {
-    pub fn run(&self, opts: &Opts) {
-        let src: Box<dyn std::io::Read> = match self.input.as_str() {
-            "-" => Box::new(std::io::stdin()),
-            filename => Box::new(File::open(filename).expect("opening gcode file failed")),
-        };
-        let rdr = GCodeReader::new(BufReader::new(src));
+    /// Estimates `.gcode` files as they're written to a directory. A file is only estimated once
+    /// its size has stayed the same for a while, so partial uploads aren't picked up.
+    fn watch(&self, opts: &Opts, dir: &Path, surrounding: &SurroundingGcode) {
+        use notify::{RecursiveMode, Watcher};
 
-        let mut planner = opts.make_planner();
-        let mut state = EstimationState::default();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)
+            .unwrap_or_else(|e| exit_with_error(format!("creating file watcher failed: {e}")));
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .unwrap_or_else(|e| exit_with_error(format!("{}: {e}", dir.display())));
 
-        for (i, cmd) in rdr.enumerate() {
-            let cmd = cmd.expect("gcode read");
-            planner.process_cmd(&cmd);
+        // Changed files, with their size when last checked
+        let mut pending: BTreeMap<PathBuf, Option<u64>> = BTreeMap::new();
+        let mut last_check = Instant::now();
+        loop {
+            match rx.recv_timeout(WATCH_SETTLE_TIME) {
+                Ok(Ok(event)) if event.kind.is_create() || event.kind.is_modify() => {
+                    for path in event.paths {
+                        if path.extension().map_or(false, |e| e == "gcode") {
+                            pending.insert(path, None);
+                        }
+                    }
+                }
+                Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+                Ok(Err(e)) => eprintln!("Warning: watching {}: {e}", dir.display()),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if last_check.elapsed() < WATCH_SETTLE_TIME {
+                continue;
+            }
+            last_check = Instant::now();
 
-            if i % 1000 == 0 {
-                for o in planner.iter().collect::<Vec<_>>() {
-                    state.add(&planner, &o);
+            let mut ready = vec![];
+            pending.retain(|path, size| {
+                let cur = match std::fs::metadata(path) {
+                    Ok(m) => m.len(),
+                    // Removed or renamed before it settled
+                    Err(_) => return false,
+                };
+                if *size == Some(cur) {
+                    ready.push(path.to_string_lossy().into_owned());
+                    return false;
                 }
+                *size = Some(cur);
+                true
+            });
+            for input in ready {
+                let state = match self.estimate(opts, &input, opts.printer_limits(), surrounding) {
+                    Ok(state) => state,
+                    Err(e) => {
+                        eprintln!("Error: {input}: {e}");
+                        continue;
+                    }
+                };
+                self.output(opts, &[input], &[state]);
+                // Also flushes the output, which JSON doesn't end with a newline
+                println!();
             }
         }
+    }
 
-        planner.finalize();
-        for o in planner.iter().collect::<Vec<_>>() {
-            state.add(&planner, &o);
+    fn write_output(&self, opts: &Opts, inputs: &[String], states: &[EstimationState]) {
+        for (input, state) in inputs.iter().zip(states.iter()) {
+            for warning in state.warnings.iter() {
+                eprintln!("Warning: {input}: {warning}");
+            }
+            Self::check_extrusion(input, state);
+        }
+
+        if self.write_sidecar {
+            for (input, state) in inputs.iter().zip(states.iter()) {
+                if input == "-" {
+                    continue;
+                }
+                let path = PathBuf::from(format!("{input}.estimate.json"));
+                let dst = File::create(&path).unwrap_or_else(exit_with_io_error(&path));
+                let mut wr = BufWriter::new(dst);
+                self.write_json(&mut wr, state)
+                    .map_err(std::io::Error::from)
+                    .and_then(|_| wr.flush())
+                    .unwrap_or_else(exit_with_io_error(&path));
+            }
         }
 
         match self.format {
             OutputFormat::Human => {
-                println!("Sequences:");
-
-                let cross_section = std::f64::consts::PI * (1.75f64 / 2.0).powf(2.0);
-                for (i, seq) in state.sequences.iter().enumerate() {
-                    if i > 0 {
-                        println!();
-                    }
-                    println!(" Run {}:", i);
-                    println!("  Total moves:                 {}", seq.num_moves);
-                    println!("  Total distance:              {:.3}mm", seq.total_distance);
-                    println!(
-                        "  Total extrude distance:      {:.3}mm",
-                        seq.total_extrude_distance
-                    );
-                    println!(
-                        "  Minimal time:                {} ({:.3}s)",
-                        format_time(seq.total_time),
-                        seq.total_time
-                    );
-                    println!(
-                        "  Total print move time:       {} ({:.3}s)",
-                        format_time(seq.total_output_time),
-                        seq.total_output_time
-                    );
-                    println!(
-                        "  Total extrude-only time:     {} ({:.3}s)",
-                        format_time(seq.total_extrude_only_time),
-                        seq.total_extrude_only_time
-                    );
-                    println!(
-                        "  Total travel time:           {} ({:.3}s)",
-                        format_time(seq.total_travel_time),
-                        seq.total_travel_time
-                    );
-                    println!(
-                        "  Average speed:               {:.3} mm/s",
-                        seq.total_distance / seq.total_time
-                    );
-                    println!(
-                        "  Top speed:                   {}",
-                        if let Some(max_speed) = seq.max_speed {
-                            format!("{:.3} mm/s", max_speed)
-                        } else {
-                            "-".to_string()
+                for (i, (input, state)) in inputs.iter().zip(states.iter()).enumerate() {
+                    if self.label_files(inputs) {
+                        if i > 0 {
+                            println!();
                         }
-                    );
-                    println!(
-                        "  Average flow:                {:.3} mm³/s",
-                        seq.total_extrude_distance * cross_section / seq.total_time
-                    );
-                    println!(
-                        "  Maximum flow:                {}",
-                        if let Some(max_flow) = seq.max_flow {
-                            format!("{:.3} mm³/s", max_flow)
+                        println!("File: {}", input);
+                    }
+                    self.print_human(state);
+                }
+            }
+            OutputFormat::Json => self.write_files(inputs, states.iter().collect()),
+            OutputFormat::UiJson => {
+                let density = opts
+                    .printer_limits()
+                    .filament_density
+                    .unwrap_or(DEFAULT_FILAMENT_DENSITY);
+                self.write_files(
+                    inputs,
+                    states
+                        .iter()
+                        .map(|state| UiMetadata::new(state, density))
+                        .collect(),
+                )
+            }
+            OutputFormat::Folded => {
+                for (input, state) in inputs.iter().zip(states.iter()) {
+                    for line in state.folded_stacks() {
+                        if self.label_files(inputs) {
+                            println!("{};{}", input.replace(';', "_"), line);
                         } else {
-                            "-".to_string()
+                            println!("{}", line);
                         }
-                    );
-                    println!(
-                        "  Average flow (output only):  {:.3} mm³/s",
-                        seq.total_extrude_distance * cross_section / seq.total_output_time
-                    );
-                    println!("  Phases:");
-                    println!(
-                        "   Acceleration:               {}",
-                        format_time(seq.phase_times.acceleration)
-                    );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether output is labeled with the file it belongs to
+    fn label_files(&self, inputs: &[String]) -> bool {
+        inputs.len() > 1 || self.watch.is_some()
+    }
+
+    /// Writes one JSON value per input file, as an array tagged with file names if there are
+    /// multiple
+    fn write_files<T: Serialize>(&self, inputs: &[String], mut values: Vec<T>) {
+        #[derive(Serialize)]
+        struct FileEstimation<'a, T> {
+            file: &'a str,
+            #[serde(flatten)]
+            state: T,
+        }
+
+        if self.label_files(inputs) {
+            let files = inputs
+                .iter()
+                .zip(values)
+                .map(|(file, state)| FileEstimation { file, state })
+                .collect::<Vec<_>>();
+            self.write_json(std::io::stdout(), &files)
+        } else {
+            self.write_json(std::io::stdout(), &values.remove(0))
+        }
+        .unwrap_or_else(|e| exit_with_error(format!("writing output failed: {e}")));
+    }
+
+    /// Writes a value as JSON. Keys are in declaration order, and map keys sorted, so the output
+    /// is stable between runs
+    fn write_json<W: Write, T: Serialize>(&self, wr: W, v: &T) -> serde_json::Result<()> {
+        let mut value = serde_json::to_value(v).expect("Serialization error");
+        if let Some(precision) = self.time_precision {
+            round_json_times(&mut value, precision, false);
+        }
+        if self.json_compact {
+            serde_json::to_writer(wr, &value)
+        } else {
+            serde_json::to_writer_pretty(wr, &value)
+        }
+    }
+
+    fn print_human(&self, state: &EstimationState) {
+        let precision = self.time_precision.unwrap_or(3);
+        println!("Sequences:");
+
+        let cross_section = filament_cross_section(state.filament_diameter);
+        for (i, seq) in state.sequences.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+            println!(" Run {}:", i);
+            println!("  Total moves:                 {}", seq.num_moves);
+            if seq.num_pauses > 0 {
+                println!("  Pauses:                      {}", seq.num_pauses);
+            }
+            println!("  Total distance:              {:.3}mm", seq.total_distance);
+            println!(
+                "  Total extrude distance:      {:.3}mm",
+                seq.total_extrude_distance
+            );
+            println!(
+                "  Total deposited distance:    {:.3}mm",
+                seq.total_deposited_distance
+            );
+            println!(
+                "  Filament used:               {:.3}mm ({:.2}g)",
+                seq.total_extrude_distance, seq.filament_weight
+            );
+            for (extruder, usage) in seq.extruder_usage.iter() {
+                println!(
+                    "   {:<27}{:.3}mm ({:.2}g)",
+                    format!("{}:", extruder),
+                    usage.length,
+                    usage.weight
+                );
+            }
+            println!(
+                "  Minimal time:                {} ({:.precision$}s)",
+                self.time_format.format(seq.total_time, precision),
+                seq.total_time
+            );
+            println!(
+                "  Total print move time:       {} ({:.precision$}s)",
+                self.time_format.format(seq.total_output_time, precision),
+                seq.total_output_time
+            );
+            println!(
+                "  Total extrude-only time:     {} ({:.precision$}s)",
+                self.time_format
+                    .format(seq.total_extrude_only_time, precision),
+                seq.total_extrude_only_time
+            );
+            println!(
+                "  Lost to extruder limits:     {} ({:.precision$}s)",
+                self.time_format
+                    .format(seq.total_extruder_limited_time, precision),
+                seq.total_extruder_limited_time
+            );
+            println!(
+                "  Total retraction time:       {} ({:.precision$}s)",
+                self.time_format
+                    .format(seq.total_retraction_time, precision),
+                seq.total_retraction_time
+            );
+            println!("  Retractions:                 {}", seq.num_retractions);
+            println!("  Sharp corners:               {}", seq.num_sharp_corners);
+            println!(
+                "  Total travel time:           {} ({:.precision$}s)",
+                self.time_format.format(seq.total_travel_time, precision),
+                seq.total_travel_time
+            );
+            if self.model_underruns {
+                println!(
+                    "  Total underrun stall time:   {} ({:.precision$}s)",
+                    self.time_format.format(seq.total_underrun_time, precision),
+                    seq.total_underrun_time
+                );
+            }
+            println!(
+                "  Average speed:               {:.3} mm/s",
+                seq.total_distance / seq.total_time
+            );
+            println!(
+                "  Top speed:                   {}",
+                if let Some(max_speed) = seq.max_speed {
+                    format!("{:.3} mm/s", max_speed)
+                } else {
+                    "-".to_string()
+                }
+            );
+            println!(
+                "  Average flow:                {:.3} mm³/s",
+                seq.total_deposited_distance * cross_section / seq.total_time
+            );
+            println!(
+                "  Maximum flow:                {}",
+                if let Some(max_flow) = seq.max_flow {
+                    format!("{:.3} mm³/s", max_flow)
+                } else {
+                    "-".to_string()
+                }
+            );
+            println!(
+                "  Average flow (output only):  {:.3} mm³/s",
+                seq.total_deposited_distance * cross_section / seq.total_output_time
+            );
+            println!("  Phases:");
+            println!(
+                "   Acceleration:               {}",
+                self.time_format
+                    .format(seq.phase_times.acceleration, precision)
+            );
+            println!(
+                "   Cruise:                     {}",
+                self.time_format.format(seq.phase_times.cruise, precision)
+            );
+            println!(
+                "   Deceleration:               {}",
+                self.time_format
+                    .format(seq.phase_times.deceleration, precision)
+            );
+            println!("  Speed limited by:");
+            let st = &seq.speed_limit_times;
+            for (name, t) in [
+                ("Feedrate:", st.feedrate),
+                ("Velocity limits:", st.velocity_limits),
+                ("Acceleration:", st.acceleration),
+                ("Cornering:", st.cornering),
+            ] {
+                println!("   {name:<28}{}", self.time_format.format(t, precision));
+            }
+            println!("  Travel:");
+            println!("   Moves:                      {}", seq.num_travel_moves);
+            println!(
+                "   Too short for full speed:   {}",
+                seq.num_short_travel_moves
+            );
+            println!(
+                "   Distance:                   {:.3}mm",
+                seq.total_travel_distance
+            );
+            println!(
+                "   Time:                       {}",
+                self.time_format.format(seq.total_travel_time, precision)
+            );
+            println!(
+                "   Maneuvers:                  {} ({}, of which {} retraction and Z hop)",
+                seq.travel_maneuvers.count,
+                self.time_format
+                    .format(seq.travel_maneuvers.time, precision),
+                self.time_format
+                    .format(seq.travel_maneuvers.overhead_time, precision)
+            );
+            println!(
+                "   Average speed:              {}",
+                if seq.total_travel_time > 0.0 {
+                    format!(
+                        "{:.3} mm/s",
+                        seq.total_travel_distance / seq.total_travel_time
+                    )
+                } else {
+                    "-".to_string()
+                }
+            );
+
+            let mut kind_times = if self.canonical_kinds {
+                let times = seq.feature_times.iter();
+                times.map(|(k, t)| (k.to_string(), t)).collect::<Vec<_>>()
+            } else {
+                let times = seq.kind_times.iter();
+                times.map(|(k, t)| (k.clone(), t)).collect::<Vec<_>>()
+            };
+            if !self.omit_move_kinds && !kind_times.is_empty() {
+                println!("  Move kind distribution:");
+                kind_times.sort_by_key(|(_, t)| {
+                    NotNan::new(**t).unwrap_or_else(|_| NotNan::new(0.0).unwrap())
+                });
+                let kind_length = kind_times
+                    .iter()
+                    .map(|(_, t)| self.time_format.format(**t, precision).len())
+                    .max()
+                    .unwrap_or(0);
+                for (k, t) in kind_times.iter().rev() {
                     println!(
-                        "   Cruise:                     {}",
-                        format_time(seq.phase_times.cruise)
+                        "   {:kind_length$}     {}",
+                        self.time_format.format(**t, precision),
+                        k
                     );
+                }
+            }
+
+            if !self.omit_move_kinds && !seq.speed_limited_by_planner.is_empty() {
+                println!("  Moves not reaching requested speed:");
+                let limited = seq
+                    .speed_limited_by_planner
+                    .iter()
+                    .map(|(k, l)| {
+                        (
+                            k,
+                            l.count.to_string(),
+                            self.time_format.format(l.time, precision),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                let count_length = limited.iter().map(|(_, c, _)| c.len()).max().unwrap_or(0);
+                let time_length = limited.iter().map(|(_, _, t)| t.len()).max().unwrap_or(0);
+                for (k, c, t) in limited {
+                    println!("   {c:>count_length$} moves  {t:time_length$}     {k}");
+                }
+            }
+
+            // Prefer explicit slicer layer markers, falling back to Z heights
+            let (layer_times, layer_source) = if !seq.marker_layer_times.is_empty() {
+                (
+                    seq.marker_layer_times
+                        .iter()
+                        .map(|(l, t)| (format!("{l}"), *t))
+                        .collect::<Vec<_>>(),
+                    "layer markers",
+                )
+            } else {
+                (
+                    seq.layer_times
+                        .iter()
+                        .map(|(l, t)| (format!("{l:.3}"), *t))
+                        .collect::<Vec<_>>(),
+                    "Z heights",
+                )
+            };
+            println!(
+                "  Layers:                      {} (from {})",
+                layer_times.len(),
+                layer_source
+            );
+            if let Some(start) = self.start_at {
+                let seq_start = start
+                    + state.sequences[..i]
+                        .iter()
+                        .map(|s| s.total_time)
+                        .sum::<f64>();
+                let end_times = if !seq.marker_layer_times.is_empty() {
+                    let times = seq.marker_layer_end_times.iter();
+                    times.map(|(l, t)| (format!("{l}"), *t)).collect::<Vec<_>>()
+                } else {
+                    let times = seq.layer_end_times.iter();
+                    times
+                        .map(|(l, t)| (format!("{l:.3}"), *t))
+                        .collect::<Vec<_>>()
+                };
+                let longest_l = end_times.iter().map(|(l, _)| l.len()).max().unwrap_or(0);
+                println!("  Layer completion:");
+                for (l, t) in end_times {
+                    println!("   {l:>longest_l$}: {}", format_timestamp(seq_start + t));
+                }
+                println!(
+                    "  Finishes at:                 {}",
+                    format_timestamp(seq_start + seq.total_time)
+                );
+            }
+            if self.omit_layer_times || layer_times.is_empty() {
+                continue;
+            }
+            if let Some(n) = self.top_layers {
+                let mut sorted = layer_times.clone();
+                sorted.sort_by(|a, b| b.1.total_cmp(&a.1));
+                let longest_l = sorted
+                    .iter()
+                    .take(n)
+                    .map(|(l, _)| l.len())
+                    .max()
+                    .unwrap_or(0);
+                println!("  Slowest layers:");
+                for (l, t) in sorted.iter().take(n) {
                     println!(
-                        "   Deceleration:               {}",
-                        format_time(seq.phase_times.deceleration)
+                        "   {l:>longest_l$}: {}",
+                        self.time_format.format(*t, precision)
                     );
+                }
+                println!(
+                    "  Layer times: min {}, median {}, max {}",
+                    self.time_format
+                        .format(sorted[sorted.len() - 1].1, precision),
+                    self.time_format
+                        .format(sorted[sorted.len() / 2].1, precision),
+                    self.time_format.format(sorted[0].1, precision),
+                );
+            } else {
+                let layer_times = layer_times
+                    .into_iter()
+                    .map(|(l, t)| (l, self.time_format.format(t, precision)))
+                    .collect::<Vec<_>>();
+                println!("  Layer time distribution:");
+                let longest_z = layer_times.iter().map(|(z, _)| z.len()).max().unwrap_or(0);
+                let longest_t = layer_times.iter().map(|(_, t)| t.len()).max().unwrap_or(0);
+                let colon = ": ";
+                let column = longest_z + longest_t + colon.len();
+                let offset = " ".repeat(3);
+                let spacing = " ".repeat(4);
 
-                    let mut kind_times = seq.kind_times.iter().collect::<Vec<_>>();
-                    if !self.omit_move_kinds && !kind_times.is_empty() {
-                        println!("  Move kind distribution:");
-                        kind_times.sort_by_key(|(_, t)| {
-                            NotNan::new(**t).unwrap_or_else(|_| NotNan::new(0.0).unwrap())
-                        });
-                        let kind_length = kind_times
-                            .iter()
-                            .map(|(_, t)| format_time(**t).len())
-                            .max()
-                            .unwrap_or(0);
-                        for (k, t) in kind_times.iter().rev() {
-                            println!("   {:kind_length$}     {}", format_time(**t), k);
-                        }
-                    }
+                let term_width = term_size::dimensions().map(|(w, _)| w).unwrap_or(0);
+                let available_width = term_width.saturating_sub(offset.len());
 
-                    let layer_times = seq
-                        .layer_times
+                let num_columns =
+                    (available_width.saturating_sub(column) / (column + spacing.len()) + 1).max(1);
+                let chunk_size = layer_times.len() / num_columns
+                    + usize::from(layer_times.len() % num_columns != 0);
+                let columnized = layer_times.chunks(chunk_size).collect::<Vec<_>>();
+                for line in 0.. {
+                    if columnized
                         .iter()
-                        .map(|(l, t)| (format!("{l:.3}"), format_time(*t)))
-                        .collect::<Vec<_>>();
-                    if !self.omit_layer_times && !layer_times.is_empty() {
-                        println!("  Layer time distribution:");
-                        let longest_z = layer_times.iter().map(|(z, _)| z.len()).max().unwrap_or(0);
-                        let longest_t = layer_times.iter().map(|(_, t)| t.len()).max().unwrap_or(0);
-                        let colon = ": ";
-                        let column = longest_z + longest_t + colon.len();
-                        let offset = " ".repeat(3);
-                        let spacing = " ".repeat(4);
-
-                        let term_width = term_size::dimensions().map(|(w, _)| w).unwrap_or(0);
-                        let available_width = (term_width - offset.len()).max(0);
-
-                        let num_columns =
-                            ((available_width - column) / (column + spacing.len()) + 1).max(1);
-                        let chunk_size = layer_times.len() / num_columns
-                            + usize::from(layer_times.len() % num_columns != 0);
-                        let columnized = layer_times.chunks(chunk_size).collect::<Vec<_>>();
-                        for line in 0.. {
-                            if columnized
-                                .iter()
-                                .map(|c| c.len().saturating_sub(line))
-                                .max()
-                                .unwrap_or(0)
-                                == 0
-                            {
-                                break;
-                            }
+                        .map(|c| c.len().saturating_sub(line))
+                        .max()
+                        .unwrap_or(0)
+                        == 0
+                    {
+                        break;
+                    }
 
-                            print!("{offset}");
-                            for i in 0..num_columns {
-                                if let Some((t, l)) =
-                                    columnized.get(i).and_then(|col| col.get(line))
-                                {
-                                    if i > 0 {
-                                        print!("{spacing}");
-                                    }
-                                    print!("{t:>longest_z$}{colon}{l:>longest_t$}");
-                                }
+                    print!("{offset}");
+                    for i in 0..num_columns {
+                        if let Some((t, l)) = columnized.get(i).and_then(|col| col.get(line)) {
+                            if i > 0 {
+                                print!("{spacing}");
                             }
-                            println!();
+                            print!("{t:>longest_z$}{colon}{l:>longest_t$}");
                         }
                     }
+                    println!();
                 }
             }
-            OutputFormat::Json => {
-                serde_json::to_writer_pretty(std::io::stdout(), &state)
-                    .expect("Serialization error");
+        }
+
+        if !state.pressure_advance.is_empty() {
+            println!();
+            println!("Pressure advance (doesn't affect the estimate):");
+            for (extruder, values) in state.pressure_advance.iter() {
+                let values = values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+                println!("  {}: {}", extruder, values.join(", "));
             }
         }
     }
@@ -374,6 +1131,10 @@ impl EstimateCmd {
 #[derive(Parser, Debug)]
 pub struct DumpMovesCmd {
     input: String,
+    /// Number of decimals for positions, distances, velocities, and accelerations. Defaults to 3,
+    /// and 4 for accelerations and squared velocity limits
+    #[clap(long)]
+    output_precision_mm: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -381,11 +1142,25 @@ struct DumpMovesState {
     move_idx: usize,
     ctime: f64,
     ztime: f64,
+    precision: Option<usize>,
+}
+
+fn format_vec4(v: DVec4, precision: usize) -> String {
+    format!(
+        "[{:.p$}, {:.p$}, {:.p$}, {:.p$}]",
+        v.x,
+        v.y,
+        v.z,
+        v.w,
+        p = precision
+    )
 }
 
 impl DumpMovesState {
     fn flush(&mut self, planner: &mut Planner) {
-        for o in planner.iter().collect::<Vec<_>>() {
+        let mut ops = planner.drain_ready();
+        while let Some(o) = ops.next() {
+            let planner = ops.planner();
             let m = match o.get_move() {
                 Some(m) => m,
                 None => continue,
@@ -407,26 +1182,42 @@ impl DumpMovesState {
                 self.ctime + m.total_time(),
                 self.ztime,
             );
+            let p = self.precision.unwrap_or(3);
             println!(
-                "    Path:       {} => {} [{:.3}∠{:.2}]",
-                (m.start * 1000.0).round() / 1000.0,
-                (m.end * 1000.0).round() / 1000.0,
+                "    Path:       {} => {} [{:.p$}∠{:.2}]",
+                format_vec4(m.start, p),
+                format_vec4(m.end, p),
                 m.distance,
                 m.rate.xy().angle_between(DVec2::new(1.0, 0.0)) * 180.0 / std::f64::consts::PI,
             );
-            println!("    Axes {}", (m.rate * 1000.0).round() / 1000.0);
-            println!("    Line width: {:?}", m.line_width(1.75 / 2.0, 0.25),);
-            println!("    Flow rate: {:?}", m.flow_rate(1.75 / 2.0));
-            println!("    Kind: {}", planner.move_kind_str(&m).unwrap_or("Other"));
-            println!("    Acceleration {:.4}", m.acceleration);
-            println!("    Max dv2: {:.4}", m.max_dv2);
-            println!("    Max start_v2: {:.4}", m.max_start_v2);
-            println!("    Max cruise_v2: {:.4}", m.max_cruise_v2);
-            println!("    Max smoothed_v2: {:.4}", m.max_smoothed_v2);
+            println!("    Axes {}", format_vec4(m.rate, p));
+            let filament_radius = planner.filament_diameter() / 2.0;
+            let line_width = m
+                .layer_height
+                .and_then(|h| m.line_width(filament_radius, h));
+            println!("    Line width: {:?}", line_width);
+            println!("    Flow rate: {:?}", m.flow_rate(filament_radius));
+            println!("    Kind: {}", planner.move_kind_label(&m));
+            let pa = self.precision.unwrap_or(4);
+            println!("    Acceleration {:.pa$}", m.acceleration);
+            println!("    Max dv2: {:.pa$}", m.max_dv2);
+            println!("    Max start_v2: {:.pa$}", m.max_start_v2);
+            println!("    Max cruise_v2: {:.pa$}", m.max_cruise_v2);
+            println!("    Max smoothed_v2: {:.pa$}", m.max_smoothed_v2);
             println!(
-                "    Velocity:   {:.3} => {:.3} => {:.3}",
+                "    Velocity:   {:.p$} => {:.p$} => {:.p$}",
                 m.start_v, m.cruise_v, m.end_v
             );
+            println!(
+                "    Peak velocity: {:.p$} of {:.p$} requested{}",
+                m.peak_velocity(),
+                m.requested_velocity,
+                if m.reached_requested() {
+                    ""
+                } else {
+                    ", not reached"
+                }
+            );
             println!(
                 "    Time:       {:.4}+{:.4}+{:.4} = {:.4}",
                 m.accel_time(),
@@ -437,7 +1228,7 @@ impl DumpMovesState {
             self.ctime += m.total_time();
 
             println!(
-                "    Distances:  {:.3}+{:.3}+{:.3} = {:.3}",
+                "    Distances:  {:.p$}+{:.p$}+{:.p$} = {:.p$}",
                 m.accel_distance(),
                 m.cruise_distance(),
                 m.decel_distance(),
@@ -453,28 +1244,76 @@ impl DumpMovesState {
 
 impl DumpMovesCmd {
     pub fn run(&self, opts: &Opts) {
-        let src: Box<dyn std::io::Read> = match self.input.as_str() {
-            "-" => Box::new(std::io::stdin()),
-            filename => Box::new(File::open(filename).expect("opening gcode file failed")),
-        };
-        let rdr = GCodeReader::new(BufReader::new(src));
+        let src = open_input(&self.input);
+        let mut rdr = opts.gcode_reader(BufReader::new(src));
 
         let mut planner = opts.make_planner();
         let mut state = DumpMovesState {
             move_idx: 0,
             ctime: 0.25,
             ztime: 0.0,
+            precision: self.output_precision_mm,
         };
 
-        for (i, cmd) in rdr.enumerate() {
-            let cmd = cmd.expect("gcode read");
+        for (i, cmd) in rdr.by_ref().enumerate() {
+            let cmd = match opts.read_command(&self.input, cmd) {
+                Some(cmd) => cmd,
+                None => continue,
+            };
             planner.process_cmd(&cmd);
+            if let Err(e) = opts.check_limits(&planner) {
+                exit_with_error(format!("{}: {e}", self.input));
+            }
 
-            if i % 1000 == 0 {
+            if opts.should_drain(i) {
                 state.flush(&mut planner);
             }
         }
+        opts.check_decimal_comma(&rdr);
+        opts.check_skipped_lines(&self.input, &rdr);
+        opts.check_thumbnails(&self.input, &rdr);
         planner.finalize();
         state.flush(&mut planner);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_precision_sets_the_decimals() {
+        assert_eq!(format_time(3723.4, 0), "1h2m3s");
+        assert_eq!(format_time(59.6, 0), "1m");
+        assert_eq!(format_time(3723.1234567, 6), "1h2m3.123457s");
+        assert_eq!(format_time(0.0000004, 6), "0s");
+    }
+
+    #[test]
+    fn round_boundaries_carry_over() {
+        assert_eq!(format_time(0.0, 3), "0s");
+        assert_eq!(format_time(60.0, 3), "1m");
+        assert_eq!(format_time(3599.999, 3), "59m59.999s");
+        assert_eq!(format_time(3599.9999, 3), "1h");
+        assert_eq!(format_time(3600.0, 3), "1h");
+        assert_eq!(format_time(86400.0, 3), "1d");
+        assert_eq!(format_time(90061.5, 3), "1d1h1m1.500s");
+    }
+
+    #[test]
+    fn time_formats_render_the_same_duration() {
+        let render = |format: TimeFormat| format.format(3723.5, 3);
+        assert_eq!(render(TimeFormat::Compact), "1h2m3.500s");
+        assert_eq!(render(TimeFormat::Clock), "01:02:03.500");
+        assert_eq!(render(TimeFormat::Iso8601), "PT1H2M3.5S");
+        assert_eq!(render(TimeFormat::Seconds), "3723.500");
+    }
+
+    #[test]
+    fn time_formats_carry_over_and_render_zero() {
+        assert_eq!(TimeFormat::Clock.format(3599.9999, 3), "01:00:00.000");
+        assert_eq!(TimeFormat::Clock.format(3723.4, 0), "01:02:03");
+        assert_eq!(TimeFormat::Iso8601.format(3599.9999, 3), "PT1H");
+        assert_eq!(TimeFormat::Iso8601.format(0.0, 3), "PT0S");
+    }
+}
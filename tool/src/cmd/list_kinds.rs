@@ -0,0 +1,49 @@
+use std::collections::BTreeMap;
+use std::io::BufReader;
+
+use clap::Parser;
+use lib_klipper::planner::{Planner, PlanningOperation};
+
+use crate::{open_input, Opts};
+
+#[derive(Parser, Debug)]
+pub struct ListKindsCmd {
+    input: String,
+}
+
+impl ListKindsCmd {
+    pub fn run(&self, opts: &Opts) {
+        let src = open_input(&self.input);
+        let mut rdr = opts.gcode_reader(BufReader::new(src));
+
+        let mut planner = opts.make_planner();
+        let mut kinds = BTreeMap::new();
+        for (i, cmd) in rdr.by_ref().enumerate() {
+            if let Some(cmd) = opts.read_command(&self.input, cmd) {
+                planner.process_cmd(&cmd);
+            }
+            if opts.should_drain(i) {
+                Self::count(&mut planner, &mut kinds);
+            }
+        }
+        opts.check_skipped_lines(&self.input, &rdr);
+        opts.check_thumbnails(&self.input, &rdr);
+        planner.finalize();
+        Self::count(&mut planner, &mut kinds);
+
+        let longest = kinds.values().map(|n| n.to_string().len()).max();
+        for (kind, n) in kinds.iter() {
+            println!("{n:>0$} {kind}", longest.unwrap_or(0));
+        }
+    }
+
+    fn count(planner: &mut Planner, kinds: &mut BTreeMap<String, usize>) {
+        let mut ops = planner.drain_ready();
+        while let Some(op) = ops.next() {
+            if let PlanningOperation::Move(m) = op {
+                let kind = ops.planner().move_kind_label(&m);
+                *kinds.entry(kind.to_string()).or_insert(0) += 1;
+            }
+        }
+    }
+}
@@ -1,3 +1,6 @@
+pub mod bench;
+pub mod check;
 pub mod dump_config;
 pub mod estimate;
+pub mod list_kinds;
 pub mod post_process;
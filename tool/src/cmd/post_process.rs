@@ -8,17 +8,61 @@ use clap::Parser;
 use regex::Regex;
 
 use lib_klipper::gcode::{
-    parse_gcode, GCodeCommand, GCodeOperation, GCodeReader, GCodeTraditionalParams,
+    parse_gcode, read_line, GCodeCommand, GCodeOperation, GCodeReader, GCodeTraditionalParams,
+    ThumbnailTracker, UTF8_BOM,
 };
 use lib_klipper::planner::{Planner, PlanningOperation};
 use lib_klipper::slicer::SlicerPreset;
 
-use crate::Opts;
+use crate::{exit_with_error, exit_with_io_error, open_file, LimitError, Opts};
 
 #[derive(Parser, Debug)]
 pub struct PostProcessCmd {
     #[clap(parse(try_from_str))]
     filename: PathBuf,
+    /// Insert `M73` progress lines every given number of seconds, for slicers that don't emit them
+    #[clap(long)]
+    inject_m73: Option<f64>,
+    /// Use the given slicer profile instead of detecting it, `none` disables rewriting
+    #[clap(arg_enum, long)]
+    slicer: Option<SlicerOverride>,
+    /// Print the slicer's original estimate next to the new one
+    #[clap(long)]
+    compare: bool,
+    /// Add a comment before each pause (`M600`, `M0`, `M1` or `PAUSE`) with the time from it to
+    /// the next pause, or to the end of the print
+    #[clap(long)]
+    annotate_pauses: bool,
+}
+
+#[derive(clap::ArgEnum, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SlicerOverride {
+    Prusa,
+    Super,
+    Orca,
+    Cura,
+    Ideamaker,
+    Simplify3d,
+    Bambu,
+    Kisslicer,
+    Generic,
+    None,
+}
+
+impl SlicerOverride {
+    fn interceptor(&self) -> Box<dyn GCodeInterceptor> {
+        match self {
+            Self::Prusa | Self::Super | Self::Orca | Self::Bambu => {
+                Box::<PSSSGCodeInterceptor>::default()
+            }
+            Self::Cura => Box::<CuraGCodeInterceptor>::default(),
+            Self::Ideamaker => Box::<IdeaMakerGCodeInterceptor>::default(),
+            Self::Simplify3d => Box::<Simplify3DGCodeInterceptor>::default(),
+            Self::Kisslicer => Box::<KISSlicerGCodeInterceptor>::default(),
+            Self::Generic => Box::<GenericGCodeInterceptor>::default(),
+            Self::None => Box::<NoopGCodeInterceptor>::default(),
+        }
+    }
 }
 
 trait GCodeInterceptor: std::fmt::Debug {
@@ -36,6 +80,32 @@ trait GCodeInterceptor: std::fmt::Debug {
         let _ = result;
         None
     }
+
+    /// The slicer's own time estimate in seconds, if the command holds it
+    fn slicer_estimate(&self, command: &GCodeCommand) -> Option<f64> {
+        let _ = command;
+        None
+    }
+}
+
+/// Parses durations like `1d 2h 3m 4s` or `1 hours 2 minutes 3 sec`, as slicers write them
+fn parse_duration(s: &str) -> Option<f64> {
+    lazy_static! {
+        static ref RE: Regex =
+            Regex::new(r"(\d+(?:\.\d+)?)\s*(days?|d|hours?|h|minutes?|mins?|m|seconds?|secs?|s)\b")
+                .unwrap();
+    }
+    RE.captures_iter(s)
+        .map(|c| {
+            let v = c[1].parse::<f64>().unwrap_or(0.0);
+            v * match &c[2][..1] {
+                "d" => 86400.0,
+                "h" => 3600.0,
+                "m" => 60.0,
+                _ => 1.0,
+            }
+        })
+        .fold(None, |acc, v| Some(acc.unwrap_or(0.0) + v))
 }
 
 #[derive(Debug, Default)]
@@ -48,16 +118,20 @@ struct M73GcodeInterceptor {
     time_buffer: VecDeque<f64>,
 }
 
+fn is_m73(command: &GCodeCommand) -> bool {
+    matches!(
+        command.op,
+        GCodeOperation::Traditional {
+            letter: 'M',
+            code: 73,
+            ..
+        }
+    )
+}
+
 impl GCodeInterceptor for M73GcodeInterceptor {
     fn post_command(&mut self, command: &GCodeCommand, result: &mut PostProcessEstimationResult) {
-        if matches!(
-            command.op,
-            GCodeOperation::Traditional {
-                letter: 'M',
-                code: 73,
-                ..
-            }
-        ) {
+        if is_m73(command) {
             self.time_buffer.push_back(result.total_time);
         }
     }
@@ -67,32 +141,38 @@ impl GCodeInterceptor for M73GcodeInterceptor {
         command: &GCodeCommand,
         result: &PostProcessEstimationResult,
     ) -> Option<GCodeCommand> {
-        if !matches!(
-            command.op,
-            GCodeOperation::Traditional {
-                letter: 'M',
-                code: 73,
-                ..
-            }
-        ) {
+        if !is_m73(command) {
             return None;
         }
         let next = self.time_buffer.pop_front()?;
+        Some(Self::progress_command(next, result.total_time))
+    }
+}
+
+impl M73GcodeInterceptor {
+    fn progress_command(elapsed: f64, total_time: f64) -> GCodeCommand {
+        // Accumulated times can end up slightly past the total, which mustn't turn into a
+        // negative remaining time or more than 100% progress. Files without any time count
+        // as done.
+        let progress = if total_time > 0.0 {
+            (elapsed / total_time * 100.0).clamp(0.0, 100.0)
+        } else {
+            100.0
+        };
+        let remaining = ((total_time - elapsed).max(0.0) / 60.0).round() as u64;
         let params = vec![
-            ('P', format!("{:.3}", (next / result.total_time * 100.0))),
-            (
-                'R',
-                format!("{}", ((result.total_time - next) / 60.0).round()),
-            ),
+            ('P', format!("{:.3}", progress)),
+            ('R', format!("{}", remaining)),
         ];
-        Some(GCodeCommand {
+        GCodeCommand {
             op: GCodeOperation::Traditional {
                 letter: 'M',
                 code: 73,
+                minor: None,
                 params: GCodeTraditionalParams::from_vec(params),
             },
             comment: None,
-        })
+        }
     }
 }
 
@@ -101,6 +181,11 @@ struct PSSSGCodeInterceptor {
     m73_interceptor: M73GcodeInterceptor,
 }
 
+lazy_static! {
+    static ref RE_PSSS_EST_TIME: Regex =
+        Regex::new(r"^ estimated printing time \(.*?\) =").unwrap();
+}
+
 impl PSSSGCodeInterceptor {
     fn format_dhms(mut time: f64) -> String {
         use std::fmt::Write;
@@ -137,17 +222,12 @@ impl GCodeInterceptor for PSSSGCodeInterceptor {
         command: &GCodeCommand,
         result: &PostProcessEstimationResult,
     ) -> Option<GCodeCommand> {
-        lazy_static! {
-            static ref RE_EST_TIME: Regex =
-                Regex::new(r"^ estimated printing time \(.*?\) =").unwrap();
-        }
-
         if let Some(cmd) = self.m73_interceptor.output_process(command, result) {
             return Some(cmd);
         }
 
         if let Some(com) = &command.comment {
-            if let Some(c) = RE_EST_TIME.captures(com) {
+            if let Some(c) = RE_PSSS_EST_TIME.captures(com) {
                 return Some(GCodeCommand {
                     op: GCodeOperation::Nop,
                     comment: Some(format!(
@@ -161,6 +241,12 @@ impl GCodeInterceptor for PSSSGCodeInterceptor {
 
         None
     }
+
+    fn slicer_estimate(&self, command: &GCodeCommand) -> Option<f64> {
+        let com = command.comment.as_ref()?;
+        let m = RE_PSSS_EST_TIME.find(com)?;
+        parse_duration(&com[m.end()..])
+    }
 }
 
 #[derive(Debug, Default)]
@@ -209,6 +295,11 @@ impl GCodeInterceptor for IdeaMakerGCodeInterceptor {
         }
         None
     }
+
+    fn slicer_estimate(&self, command: &GCodeCommand) -> Option<f64> {
+        let com = command.comment.as_ref()?;
+        com.strip_prefix("Print Time: ")?.trim().parse().ok()
+    }
 }
 
 #[derive(Debug, Default)]
@@ -245,13 +336,23 @@ impl GCodeInterceptor for CuraGCodeInterceptor {
                 if let Some(next) = self.time_buffer.pop_front() {
                     return Some(GCodeCommand {
                         op: GCodeOperation::Nop,
-                        comment: Some(format!("TIME_ELAPSED:{:.0}", (next).ceil())),
+                        // Cura emits fractional seconds here, which UIs interpolate between
+                        comment: Some(format!("TIME_ELAPSED:{:.6}", next)),
                     });
                 }
             }
         }
         None
     }
+
+    fn slicer_estimate(&self, command: &GCodeCommand) -> Option<f64> {
+        let com = command.comment.as_ref()?;
+        com.strip_prefix("TIME:")
+            .or_else(|| com.strip_prefix("PRINT.TIME:"))?
+            .trim()
+            .parse()
+            .ok()
+    }
 }
 
 #[derive(Debug, Default)]
@@ -297,6 +398,85 @@ impl GCodeInterceptor for Simplify3DGCodeInterceptor {
         }
         None
     }
+
+    fn slicer_estimate(&self, command: &GCodeCommand) -> Option<f64> {
+        let com = command.comment.as_ref()?;
+        parse_duration(com.strip_prefix("   Build Time: ")?)
+    }
+}
+
+#[derive(Debug, Default)]
+struct KISSlicerGCodeInterceptor {}
+
+impl GCodeInterceptor for KISSlicerGCodeInterceptor {
+    fn output_process(
+        &mut self,
+        command: &GCodeCommand,
+        result: &PostProcessEstimationResult,
+    ) -> Option<GCodeCommand> {
+        if let Some(com) = &command.comment {
+            if com.trim_start().starts_with("Estimated Build Time:") {
+                return Some(GCodeCommand {
+                    op: GCodeOperation::Nop,
+                    comment: Some(format!(
+                        " Estimated Build Time:   {:.2} minutes",
+                        result.total_time / 60.0
+                    )),
+                });
+            }
+        }
+        None
+    }
+
+    fn slicer_estimate(&self, command: &GCodeCommand) -> Option<f64> {
+        let com = command.comment.as_ref()?;
+        parse_duration(com.trim_start().strip_prefix("Estimated Build Time:")?)
+    }
+}
+
+/// Best-effort rewriting for slicers we don't know about. Any comment that looks like
+/// `estimated time: ...` or `print time = ...` has its value replaced, as plain seconds if the
+/// original value was a number, or in `1h 2m 3s` form otherwise.
+#[derive(Debug, Default)]
+struct GenericGCodeInterceptor {}
+
+lazy_static! {
+    static ref RE_GENERIC_TIME: Regex = Regex::new(
+        r"(?i)^(\s*(?:estimated(?:\s+(?:print(?:ing)?|build))?|print(?:ing)?)[\s_]+time\b[^:=]*[:=]\s*)(.*?)\s*$"
+    )
+    .unwrap();
+}
+
+impl GCodeInterceptor for GenericGCodeInterceptor {
+    fn output_process(
+        &mut self,
+        command: &GCodeCommand,
+        result: &PostProcessEstimationResult,
+    ) -> Option<GCodeCommand> {
+        if !command.op.is_nop() {
+            return None;
+        }
+        let c = RE_GENERIC_TIME.captures(command.comment.as_ref()?)?;
+        let value = if c[2].parse::<f64>().is_ok() {
+            format!("{:.0}", result.total_time.ceil())
+        } else {
+            PSSSGCodeInterceptor::format_dhms(result.total_time)
+                .trim_start()
+                .to_string()
+        };
+        Some(GCodeCommand {
+            op: GCodeOperation::Nop,
+            comment: Some(format!("{}{}", &c[1], value)),
+        })
+    }
+
+    fn slicer_estimate(&self, command: &GCodeCommand) -> Option<f64> {
+        if !command.op.is_nop() {
+            return None;
+        }
+        let c = RE_GENERIC_TIME.captures(command.comment.as_ref()?)?;
+        c[2].parse::<f64>().ok().or_else(|| parse_duration(&c[2]))
+    }
 }
 
 fn metadata_processor(preset: &SlicerPreset) -> Box<dyn GCodeInterceptor> {
@@ -307,6 +487,7 @@ fn metadata_processor(preset: &SlicerPreset) -> Box<dyn GCodeInterceptor> {
         SlicerPreset::IdeaMaker { .. } => Box::<IdeaMakerGCodeInterceptor>::default(),
         SlicerPreset::Cura { .. } => Box::<CuraGCodeInterceptor>::default(),
         SlicerPreset::Simplify3D { .. } => Box::<Simplify3DGCodeInterceptor>::default(),
+        SlicerPreset::KISSlicer { .. } => Box::<KISSlicerGCodeInterceptor>::default(),
     }
 }
 
@@ -329,6 +510,12 @@ impl std::default::Default for PostProcessEstimationResult {
 struct PostProcessState {
     result: PostProcessEstimationResult,
     gcode_interceptor: Box<dyn GCodeInterceptor>,
+    // Line numbers after which an `M73` line should be injected, with the elapsed time there
+    m73_injections: VecDeque<(usize, f64)>,
+    // Line numbers of pauses, with the elapsed time they start and end at
+    pauses: Vec<(usize, f64, f64)>,
+    // Line number of the last `M73` in the output, and whether it's injected after that line
+    last_m73: Option<(usize, bool)>,
 }
 
 #[allow(clippy::derivable_impls)]
@@ -336,7 +523,11 @@ impl std::default::Default for PostProcessState {
     fn default() -> Self {
         PostProcessState {
             result: PostProcessEstimationResult::default(),
-            gcode_interceptor: Box::<NoopGCodeInterceptor>::default(),
+            // Replaced once a slicer is detected
+            gcode_interceptor: Box::<GenericGCodeInterceptor>::default(),
+            m73_injections: VecDeque::new(),
+            pauses: Vec::new(),
+            last_m73: None,
         }
     }
 }
@@ -346,40 +537,66 @@ struct EstimateRunner {
     state: PostProcessState,
     planner: Planner,
     // We use this buffer to synchronize planned moves with input moves
-    buffer: VecDeque<(usize, GCodeCommand)>,
+    buffer: VecDeque<(usize, usize, GCodeCommand)>,
+    inject_m73: Option<f64>,
+    next_m73: f64,
+    detect_slicer: bool,
 }
 
 impl EstimateRunner {
-    fn run<T: BufRead>(&mut self, rdr: &mut GCodeReader<T>) {
-        for (n, cmd) in rdr.enumerate() {
-            let cmd = cmd.expect("gcode read");
+    fn run<T: BufRead>(
+        &mut self,
+        opts: &Opts,
+        input: &str,
+        rdr: &mut GCodeReader<T>,
+    ) -> Result<(), LimitError> {
+        for (n, cmd) in rdr.by_ref().enumerate() {
+            let cmd = match opts.read_command(input, cmd) {
+                Some(cmd) => cmd,
+                None => continue,
+            };
 
             // If we don't have a slicer figured out yet, and this is a comment, try
-            if cmd.op.is_nop() && cmd.comment.is_some() && self.state.result.slicer.is_none() {
-                self.state.result.slicer = SlicerPreset::determine(cmd.comment.as_ref().unwrap());
+            if let (true, true, Some(comment), None) = (
+                self.detect_slicer,
+                cmd.op.is_nop(),
+                cmd.comment.as_ref(),
+                self.state.result.slicer.as_ref(),
+            ) {
+                self.state.result.slicer = SlicerPreset::determine(comment);
                 if let Some(preset) = self.state.result.slicer.as_ref() {
                     self.state.gcode_interceptor = metadata_processor(preset);
                 }
             }
 
             let x = self.planner.process_cmd(&cmd);
-            self.buffer.push_back((x, cmd));
+            opts.check_limits(&self.planner)?;
+            self.buffer.push_back((x, n, cmd));
 
-            if n % 1000 == 0 {
+            if opts.should_drain(n) {
                 self.flush();
             }
         }
 
+        opts.check_decimal_comma(rdr);
+        opts.check_skipped_lines(input, rdr);
+        opts.check_thumbnails(input, rdr);
         self.planner.finalize();
         self.flush();
+        Ok(())
     }
 
     fn flush(&mut self) {
         for c in self.planner.iter() {
-            let (n, cmd) = self.buffer.front_mut().unwrap();
+            let (n, line, cmd) = self.buffer.front_mut().unwrap();
             match c {
                 PlanningOperation::Delay(d) => {
-                    self.state.result.total_time += d.duration().as_secs_f64()
+                    let start = self.state.result.total_time;
+                    self.state.result.total_time += d.duration().as_secs_f64();
+                    if is_pause(cmd) {
+                        let end = self.state.result.total_time;
+                        self.state.pauses.push((*line, start, end));
+                    }
                 }
                 PlanningOperation::Move(m) => self.state.result.total_time += m.total_time(),
                 PlanningOperation::Fill => {}
@@ -388,6 +605,17 @@ impl EstimateRunner {
                 .gcode_interceptor
                 .post_command(cmd, &mut self.state.result);
             if *n <= 1 {
+                if is_m73(cmd) {
+                    self.state.last_m73 = Some((*line, false));
+                }
+                if let Some(interval) = self.inject_m73 {
+                    let elapsed = self.state.result.total_time;
+                    if elapsed >= self.next_m73 {
+                        self.state.last_m73 = Some((*line, true));
+                        self.state.m73_injections.push_back((*line, elapsed));
+                        self.next_m73 = (elapsed / interval).floor() * interval + interval;
+                    }
+                }
                 let _ = self.buffer.pop_front();
             } else {
                 *n -= 1;
@@ -396,66 +624,292 @@ impl EstimateRunner {
     }
 }
 
-impl PostProcessCmd {
-    fn estimate(&self, opts: &Opts) -> PostProcessState {
-        let src = File::open(&self.filename).expect("opening gcode file failed");
-        let mut rdr = GCodeReader::new(BufReader::new(src));
-
-        let mut runner = EstimateRunner {
-            state: PostProcessState::default(),
-            planner: opts.make_planner(),
-            buffer: VecDeque::new(),
-        };
-        runner.run(&mut rdr);
-        runner.state
+/// Whether a command pauses the print for the user, e.g. for a filament change
+fn is_pause(cmd: &GCodeCommand) -> bool {
+    match &cmd.op {
+        GCodeOperation::Traditional {
+            letter: 'M',
+            code: 0 | 1 | 600,
+            minor: None,
+            ..
+        } => true,
+        GCodeOperation::Extended { command, .. } => command == "pause",
+        _ => false,
+    }
+}
+
+/// Estimates a file without modifying it. A slicer profile given overrides the detected one
+fn estimate_file(
+    opts: &Opts,
+    filename: &Path,
+    slicer: Option<SlicerOverride>,
+    inject_m73: Option<f64>,
+) -> Result<PostProcessState, LimitError> {
+    let mut rdr = opts.gcode_reader(BufReader::new(open_file(filename)));
+
+    let mut state = PostProcessState::default();
+    if let Some(slicer) = slicer {
+        state.gcode_interceptor = slicer.interceptor();
+    }
+
+    let mut runner = EstimateRunner {
+        state,
+        planner: opts.make_planner(),
+        buffer: VecDeque::new(),
+        inject_m73: inject_m73.filter(|v| *v > 0.0),
+        next_m73: 0.0,
+        detect_slicer: slicer.is_none(),
+    };
+    runner.run(opts, &filename.to_string_lossy(), &mut rdr)?;
+    for warning in runner.planner.toolhead_state.warnings.iter() {
+        eprintln!("Warning: {warning}");
+    }
+    Ok(runner.state)
+}
+
+/// Our estimate of a file next to the one the slicer embedded in it
+#[derive(Debug)]
+pub(crate) struct SlicerComparison {
+    pub total_time: f64,
+    pub slicer_estimate: Option<f64>,
+    pub slicer: Option<SlicerPreset>,
+}
+
+/// Estimates a file and looks up the slicer's own estimate in it, leaving the file unchanged
+pub(crate) fn compare_with_slicer(
+    opts: &Opts,
+    filename: &Path,
+    slicer: Option<SlicerOverride>,
+) -> Result<SlicerComparison, LimitError> {
+    let state = estimate_file(opts, filename, slicer, None)?;
+
+    let mut rdr = BufReader::new(open_file(filename));
+    let mut buf = Vec::new();
+    let mut slicer_estimate = None;
+    while slicer_estimate.is_none()
+        && read_line(&mut rdr, &mut buf).unwrap_or_else(exit_with_io_error(filename)) > 0
+    {
+        slicer_estimate = std::str::from_utf8(&buf)
+            .ok()
+            .and_then(|line| parse_gcode(line.trim_end_matches(['\r', '\n'])).ok())
+            .and_then(|cmd| state.gcode_interceptor.slicer_estimate(&cmd));
+        buf.clear();
     }
 
+    Ok(SlicerComparison {
+        total_time: state.result.total_time,
+        slicer_estimate,
+        slicer: state.result.slicer,
+    })
+}
+
+impl PostProcessCmd {
     fn apply_changes(&self, mut state: PostProcessState) {
-        let src = File::open(&self.filename).expect("opening gcode file failed");
-        let rdr = BufReader::new(src);
+        let mut rdr = BufReader::new(open_file(&self.filename));
 
         let mut dst_name = Into::<OsString>::into(".estimate.");
-        dst_name.push(self.filename.file_name().expect("invalid file name"));
+        dst_name.push(self.filename.file_name().unwrap_or_else(|| {
+            exit_with_error(format!("{}: not a file name", self.filename.display()))
+        }));
         let dst_path = self
             .filename
             .parent()
             .unwrap_or_else(|| Path::new("/"))
             .join(dst_name);
-        let dst = File::create(&dst_path).expect("creating target gcode file failed");
+        let dst = File::create(&dst_path).unwrap_or_else(exit_with_io_error(&dst_path));
         let mut wr = BufWriter::new(dst);
 
-        for line in rdr.lines() {
-            let line = line.expect("IO error");
-            if let Ok(cmd) = parse_gcode(&line) {
-                if let Some(cmd) = state.gcode_interceptor.output_process(&cmd, &state.result) {
-                    writeln!(wr, "{}", cmd).expect("IO error");
-                } else {
-                    writeln!(wr, "{}", line).expect("IO error");
+        // Lines are copied byte for byte unless rewritten, keeping their original line endings
+        let mut buf = Vec::new();
+        let mut newline = b"\n".to_vec();
+        let mut slicer_estimate = None;
+        let mut thumbnails = ThumbnailTracker::default();
+        let mut pause_annotations = if self.annotate_pauses {
+            Self::pause_annotations(&state)
+        } else {
+            VecDeque::new()
+        };
+        for n in 0.. {
+            buf.clear();
+            if read_line(&mut rdr, &mut buf).unwrap_or_else(exit_with_io_error(&self.filename)) == 0
+            {
+                break;
+            }
+            let content_len = buf.len()
+                - buf
+                    .iter()
+                    .rev()
+                    .take_while(|&&c| c == b'\n' || c == b'\r')
+                    .count();
+            let (line, ending) = buf.split_at(content_len);
+            if !ending.is_empty() {
+                newline = ending.to_vec();
+            }
+
+            // Thumbnails are long blocks of base64 comments, copied without parsing them
+            let in_block = thumbnails.is_thumbnail(line, n + 1);
+            let cmd = std::str::from_utf8(line)
+                .ok()
+                .filter(|_| !in_block)
+                .map(|line| line.strip_prefix(UTF8_BOM).unwrap_or(line))
+                .and_then(|line| parse_gcode(line).ok());
+            if let Some(cmd) = cmd.as_ref().filter(|_| slicer_estimate.is_none()) {
+                slicer_estimate = state.gcode_interceptor.slicer_estimate(cmd);
+            }
+            while let Some((_, comment)) = pause_annotations.front().filter(|(l, _)| *l == n) {
+                wr.write_all(comment.as_bytes())
+                    .and_then(|_| wr.write_all(&newline))
+                    .unwrap_or_else(exit_with_io_error(&dst_path));
+                pause_annotations.pop_front();
+            }
+
+            let total_time = state.result.total_time;
+            let rewritten = match cmd {
+                // The print is done by the last progress update, whatever the slicer wrote there
+                Some(cmd) if is_m73(&cmd) && state.last_m73 == Some((n, false)) => {
+                    state.gcode_interceptor.output_process(&cmd, &state.result);
+                    Some(M73GcodeInterceptor::progress_command(
+                        total_time, total_time,
+                    ))
                 }
-            } else {
-                writeln!(wr, "{}", line).expect("IO error");
+                cmd => {
+                    cmd.and_then(|cmd| state.gcode_interceptor.output_process(&cmd, &state.result))
+                }
+            };
+            match rewritten {
+                // Keep a byte order mark in front of a rewritten first line
+                Some(cmd) if n == 0 && line.starts_with(UTF8_BOM.to_string().as_bytes()) => {
+                    write!(wr, "{}{}", UTF8_BOM, cmd)
+                }
+                Some(cmd) => write!(wr, "{}", cmd),
+                None => wr.write_all(line),
+            }
+            .and_then(|_| wr.write_all(&newline))
+            .unwrap_or_else(exit_with_io_error(&dst_path));
+
+            while let Some((_, elapsed)) = state.m73_injections.front().filter(|(l, _)| *l == n) {
+                let last = state.m73_injections.len() == 1 && state.last_m73 == Some((n, true));
+                let elapsed = if last { total_time } else { *elapsed };
+                let cmd = M73GcodeInterceptor::progress_command(elapsed, total_time);
+                write!(wr, "{}", cmd)
+                    .and_then(|_| wr.write_all(&newline))
+                    .unwrap_or_else(exit_with_io_error(&dst_path));
+                state.m73_injections.pop_front();
             }
         }
 
-        writeln!(
+        write!(
             wr,
             "; Processed by klipper_estimator {}, {}",
             env!("TOOL_VERSION"),
-            if let Some(slicer) = state.result.slicer {
+            if let Some(slicer) = self.slicer {
+                format!("forced slicer profile {:?}", slicer)
+            } else if let Some(slicer) = state.result.slicer {
                 format!("detected slicer {}", slicer)
             } else {
                 "no slicer detected".into()
             }
         )
-        .expect("IO error");
+        .and_then(|_| wr.write_all(&newline))
+        .unwrap_or_else(exit_with_io_error(&dst_path));
 
         // Flush output file before renaming
-        wr.flush().expect("IO error");
-        std::fs::rename(&dst_path, &self.filename).expect("rename failed");
+        wr.flush().unwrap_or_else(exit_with_io_error(&dst_path));
+        std::fs::rename(&dst_path, &self.filename)
+            .unwrap_or_else(exit_with_io_error(&self.filename));
+
+        if self.compare {
+            Self::print_comparison(slicer_estimate, state.result.total_time);
+        }
+    }
+
+    /// Comments to insert before each pause, by line number, with the time from the end of the
+    /// pause to the start of the next one, or to the end of the print
+    fn pause_annotations(state: &PostProcessState) -> VecDeque<(usize, String)> {
+        let pauses = &state.pauses;
+        let format = |t: f64| {
+            PSSSGCodeInterceptor::format_dhms(t.max(0.0))
+                .trim_start()
+                .to_string()
+        };
+        pauses
+            .iter()
+            .enumerate()
+            .map(|(i, (line, _, end))| {
+                let comment = match pauses.get(i + 1) {
+                    Some((_, start, _)) => {
+                        format!("; time until next pause: {}", format(start - end))
+                    }
+                    None => format!(
+                        "; time until end of print: {}",
+                        format(state.result.total_time - end)
+                    ),
+                };
+                (*line, comment)
+            })
+            .collect()
+    }
+
+    pub(crate) fn print_comparison(slicer_estimate: Option<f64>, total_time: f64) {
+        let format = |t: f64| {
+            PSSSGCodeInterceptor::format_dhms(t)
+                .trim_start()
+                .to_string()
+        };
+        println!("klipper_estimator estimate: {}", format(total_time));
+        match slicer_estimate {
+            Some(slicer) => {
+                let delta = total_time - slicer;
+                println!("Slicer estimate:            {}", format(slicer));
+                print!(
+                    "Difference:                 {}{}",
+                    if delta < 0.0 { "-" } else { "+" },
+                    format(delta.abs())
+                );
+                if slicer > 0.0 {
+                    print!(" ({:+.1}%)", delta / slicer * 100.0);
+                }
+                println!();
+            }
+            None => println!("Slicer estimate:            not found"),
+        }
     }
 
     pub fn run(&self, opts: &Opts) {
-        let state = self.estimate(opts);
+        let state = estimate_file(opts, &self.filename, self.slicer, self.inject_m73)
+            .unwrap_or_else(|e| exit_with_error(format!("{}: {e}", self.filename.display())));
         self.apply_changes(state);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_in_slicer_formats() {
+        assert_eq!(parse_duration("1d 2h 3m 4s"), Some(93784.0));
+        assert_eq!(parse_duration(" 1 hours 2 minutes 3 sec"), Some(3723.0));
+        assert_eq!(parse_duration("45.5s"), Some(45.5));
+        assert_eq!(parse_duration("2 days"), Some(172800.0));
+        assert_eq!(parse_duration("unknown"), None);
+    }
+
+    #[test]
+    fn progress_is_clamped_past_the_total() {
+        let cmd = M73GcodeInterceptor::progress_command(100.1, 100.0);
+        assert_eq!(cmd.to_string(), "M73 P100.000 R0");
+    }
+
+    #[test]
+    fn progress_without_time_is_done() {
+        let cmd = M73GcodeInterceptor::progress_command(0.0, 0.0);
+        assert_eq!(cmd.to_string(), "M73 P100.000 R0");
+    }
+
+    #[test]
+    fn remaining_time_rounds_to_minutes() {
+        let cmd = M73GcodeInterceptor::progress_command(30.0, 120.0);
+        assert_eq!(cmd.to_string(), "M73 P25.000 R2");
+    }
+}
@@ -1,17 +1,23 @@
+use std::collections::BTreeMap;
+
+use std::fs::File;
+use std::io::{BufRead, Read};
+use std::path::Path;
+
 use anyhow::Context;
-use lib_klipper::glam::DVec3;
-use lib_klipper::planner::{FirmwareRetractionOptions, MoveChecker, Planner, PrinterLimits};
+use lib_klipper::gcode::{GCodeCommand, GCodeReadError, GCodeReader};
+use lib_klipper::planner::{KindSource, Planner, PrinterLimits, DEFAULT_DRAIN_INTERVAL};
 
 use clap::Parser;
 use config::{Value, ValueKind};
 use once_cell::sync::OnceCell;
 use reqwest::StatusCode;
-use serde::Deserialize;
 use thiserror::Error;
 use url::Url;
 #[macro_use]
 extern crate lazy_static;
 
+mod clock;
 mod cmd;
 
 #[derive(Parser, Debug)]
@@ -25,18 +31,72 @@ pub struct Opts {
     config_moonraker_ignore_error: bool,
     #[clap(long = "config_moonraker_cache_file")]
     config_moonraker_cache_file: Option<String>,
-
+    /// Printer to query, for proxies serving multiple printers. Added to the URL path
+    #[clap(long = "config_moonraker_printer")]
+    config_moonraker_printer: Option<String>,
+    /// Path of the object query endpoint, defaults to `printer/objects/query`
+    #[clap(long = "config_moonraker_query_path")]
+    config_moonraker_query_path: Option<String>,
+
+    /// Config file to load. Can be repeated to layer files, with settings in later files
+    /// replacing those in earlier ones
     #[clap(long = "config_file")]
-    config_filename: Option<String>,
+    config_filename: Vec<String>,
 
     #[clap(short = 'c')]
     config_override: Vec<String>,
 
+    /// Where to derive move kinds from
+    #[clap(arg_enum, long, default_value_t = KindFrom::Typeonly)]
+    kind_from: KindFrom,
+
+    /// Seconds to assume for each PAUSE/M0/M1
+    #[clap(long)]
+    assume_pause: Option<f64>,
+
+    /// Seconds to assume for each point probed by G29/BED_MESH_CALIBRATE
+    #[clap(long)]
+    assume_probe_per_point: Option<f64>,
+
+    /// Seconds to assume for each tool change by `T<n>`. Given as `T<n>=<seconds>`, sets the
+    /// time for changing to that tool instead. Can be repeated
+    #[clap(long)]
+    tool_change_time: Vec<String>,
+
+    /// Time `G28` as moves to the endstops, using the homing settings of the `[stepper_*]`
+    /// sections, rather than as indeterminate time
+    #[clap(long)]
+    model_homing: bool,
+
+    /// Abort if a file results in more than this many planned moves
+    #[clap(long)]
+    max_moves: Option<usize>,
+
+    /// Maximum number of segments a single arc is split into
+    #[clap(long)]
+    max_arc_segments: Option<usize>,
+
+    /// Accept commas as decimal separators in gcode parameters, e.g. `G1 X10,5`
+    #[clap(long)]
+    decimal_comma: bool,
+
+    /// Number of commands processed between draining planned moves. Doesn't affect results, only
+    /// memory use and throughput
+    #[clap(long, default_value_t = DEFAULT_DRAIN_INTERVAL)]
+    drain_interval: usize,
+
+    /// Skip lines that aren't valid gcode with a warning, instead of stopping
+    #[clap(long)]
+    skip_errors: bool,
+
     #[clap(subcommand)]
     cmd: SubCommand,
 
     #[clap(skip)]
     config: OnceCell<PrinterLimits>,
+    // Loaded configuration sources, kept so Moonraker is only queried once
+    #[clap(skip)]
+    config_sources: OnceCell<Vec<(String, config::Config)>>,
 }
 
 impl Opts {
@@ -66,6 +126,9 @@ impl Opts {
         let parser: fn(&str) -> anyhow::Result<ValueKind> = match key {
             "max_accel_to_decel" => |v: &str| Ok(ValueKind::Float(v.parse()?)),
             "minimum_cruise_ratio" => |v: &str| Ok(ValueKind::Float(v.parse()?)),
+            "mm_per_arc_segment" => |v: &str| Ok(ValueKind::Float(v.parse()?)),
+            "overhead_seconds" => |v: &str| Ok(ValueKind::Float(v.parse()?)),
+            "bed_mesh_probe_points" => |v: &str| Ok(ValueKind::U64(v.parse()?)),
             _ => |v: &str| Ok(ValueKind::String(v.to_string())),
         };
         Ok((
@@ -78,27 +141,71 @@ impl Opts {
         ))
     }
 
-    fn load_config(&self) -> anyhow::Result<PrinterLimits> {
-        use config::Config;
+    /// The Moonraker and file configuration sources, in order of increasing precedence, each
+    /// with the name of where it comes from
+    fn config_sources(&self) -> anyhow::Result<&[(String, config::Config)]> {
+        let sources = self
+            .config_sources
+            .get_or_try_init(|| self.load_config_sources())?;
+        Ok(sources)
+    }
 
-        let builder = Config::builder();
+    fn load_config_sources(&self) -> anyhow::Result<Vec<(String, config::Config)>> {
+        use config::Config;
 
-        let builder = if let Some(url) = &self.config_moonraker {
-            builder.add_source(MoonrakerSource::new(
+        let mut sources = vec![];
+        if let Some(url) = &self.config_moonraker {
+            let source = MoonrakerSource::new(
                 url,
                 self.config_moonraker_api_key.as_deref(),
                 self.config_moonraker_ignore_error,
                 self.config_moonraker_cache_file.as_deref(),
-            ))
-        } else {
-            builder
-        };
+                self.config_moonraker_printer.as_deref(),
+                self.config_moonraker_query_path.as_deref(),
+            );
+            let name = "moonraker".to_string();
+            sources.push((name, Config::builder().add_source(source).build()?));
+        }
+        for filename in self.config_filename.iter() {
+            let source = config::File::new(filename, config::FileFormat::Json5);
+            let name = format!("file {filename}");
+            sources.push((name, Config::builder().add_source(source).build()?));
+        }
+        Ok(sources)
+    }
 
-        let builder = if let Some(filename) = &self.config_filename {
-            builder.add_source(config::File::new(filename, config::FileFormat::Json5))
-        } else {
-            builder
-        };
+    /// Names the source each top-level configuration field comes from: `override` for `-c`,
+    /// `file <name>`, `moonraker`, or `default` if none of them set it. Fields are in the order
+    /// `dump-config` outputs them.
+    fn config_provenance(&self) -> anyhow::Result<Vec<(String, &str)>> {
+        use config::Source;
+
+        let mut provenance = BTreeMap::new();
+        for (name, source) in self.config_sources()?.iter() {
+            for key in source.collect()?.into_keys() {
+                provenance.insert(key, name.as_str());
+            }
+        }
+        for opt in self.config_override.iter() {
+            let (key, _) = Self::opt_parse(opt)?;
+            let field = key.split(['.', '[']).next().unwrap_or(key);
+            provenance.insert(field.to_string(), "override");
+        }
+
+        let limits = serde_json::to_value(self.printer_limits())?;
+        let fields = limits.as_object().into_iter().flat_map(|o| o.keys());
+        Ok(fields
+            .map(|f| (f.clone(), provenance.get(f).copied().unwrap_or("default")))
+            .collect())
+    }
+
+    fn load_config(&self) -> anyhow::Result<PrinterLimits> {
+        let builder = self
+            .config_sources()?
+            .iter()
+            .fold(config::Config::builder(), |builder, (_, source)| {
+                builder.add_source(source.clone())
+            });
 
         let builder = self
             .config_override
@@ -109,15 +216,177 @@ impl Opts {
             })?;
 
         let mut limits = builder.build()?.try_deserialize::<PrinterLimits>()?;
+        // Every move's timing divides by these, Klipper refuses to start without them too
+        if limits.max_velocity <= 0.0 {
+            anyhow::bail!("max_velocity must be positive, got {}", limits.max_velocity);
+        }
+        if limits.max_acceleration <= 0.0 {
+            anyhow::bail!(
+                "max_acceleration must be positive, got {}",
+                limits.max_acceleration
+            );
+        }
         limits.recalculate();
+        if !limits.kinematics_supported() {
+            eprintln!(
+                "Warning: {} kinematics aren't modeled, estimates may be inaccurate",
+                limits.kinematics.as_deref().unwrap_or_default()
+            );
+        }
         Ok(limits)
     }
 
     fn make_planner(&self) -> Planner {
-        Planner::from_limits(self.printer_limits().clone())
+        self.make_planner_from(self.printer_limits().clone())
+    }
+
+    /// Creates a planner for the given limits, with the planner options applied
+    fn make_planner_from(&self, limits: PrinterLimits) -> Planner {
+        let mut planner = Planner::from_limits(limits);
+        planner.kind_tracker.source = match self.kind_from {
+            KindFrom::Typeonly => KindSource::TypeOnly,
+            KindFrom::Comment => KindSource::Comment,
+            KindFrom::None => KindSource::None,
+        };
+        if let Some(t) = self.assume_pause {
+            planner.pause_time = std::time::Duration::from_secs_f64(t.max(0.0));
+        }
+        if let Some(t) = self.assume_probe_per_point {
+            planner.probe_point_time = Some(std::time::Duration::from_secs_f64(t.max(0.0)));
+        }
+        for arg in self.tool_change_time.iter() {
+            let (tool, time) = Self::parse_tool_change_time(arg).unwrap_or_else(|| {
+                exit_with_error(format!(
+                    "invalid tool change time {arg:?}, expected <seconds> or T<n>=<seconds>"
+                ))
+            });
+            let time = std::time::Duration::from_secs_f64(time);
+            match tool {
+                Some(tool) => planner.tool_change_times.insert(tool, time),
+                None => planner.tool_change_time.replace(time),
+            };
+        }
+        planner.model_homing = self.model_homing;
+        planner.arc_state.max_segments = self.max_arc_segments;
+        planner
+    }
+
+    /// Parses a `--tool-change-time` value into the tool it applies to, if any, and the time
+    fn parse_tool_change_time(arg: &str) -> Option<(Option<u16>, f64)> {
+        let (tool, time) = match arg.split_once('=') {
+            Some((tool, time)) => {
+                let tool = tool.trim().strip_prefix(['T', 't'])?.parse().ok()?;
+                (Some(tool), time)
+            }
+            None => (None, arg),
+        };
+        let time = time
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|t| t.is_finite() && *t >= 0.0)?;
+        Some((tool, time))
+    }
+
+    /// Whether planned moves should be drained after the command with the given index
+    fn should_drain(&self, i: usize) -> bool {
+        i % self.drain_interval.max(1) == 0
+    }
+
+    fn gcode_reader<R: BufRead>(&self, rdr: R) -> GCodeReader<R> {
+        GCodeReader::new(rdr).decimal_comma(self.decimal_comma)
+    }
+
+    /// Warns about parameters that were skipped because they used comma decimal separators
+    fn check_decimal_comma<R: BufRead>(&self, rdr: &GCodeReader<R>) {
+        if let Some(line) = rdr.comma_decimal_line() {
+            eprintln!(
+                "Warning: line {line} appears to use a comma decimal separator, \
+                 parameters like this were ignored. Pass --decimal-comma to accept them"
+            );
+        }
+    }
+
+    /// Unwraps a command read from `input`. Exits on errors, unless `--skip-errors` was given and
+    /// only the line is affected, in which case `None` is returned.
+    fn read_command(
+        &self,
+        input: &str,
+        cmd: Result<GCodeCommand, GCodeReadError>,
+    ) -> Option<GCodeCommand> {
+        self.try_read_command(input, cmd)
+            .unwrap_or_else(|e| exit_with_error(format!("{input}: {e}")))
+    }
+
+    /// Like `read_command`, but returns errors instead of exiting
+    fn try_read_command(
+        &self,
+        input: &str,
+        cmd: Result<GCodeCommand, GCodeReadError>,
+    ) -> Result<Option<GCodeCommand>, GCodeReadError> {
+        match cmd {
+            Ok(cmd) => Ok(Some(cmd)),
+            Err(e) if self.skip_errors && e.is_line_error() => {
+                eprintln!("Warning: {input}: {e}, skipping");
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reports the number of lines skipped due to `--skip-errors`
+    fn check_skipped_lines<R: BufRead>(&self, input: &str, rdr: &GCodeReader<R>) {
+        if rdr.line_errors() > 0 {
+            eprintln!(
+                "Warning: {input}: skipped {} invalid lines, the estimate is approximate",
+                rdr.line_errors()
+            );
+        }
+    }
+
+    /// Warns about a thumbnail that ran into gcode without its end marker
+    fn check_thumbnails<R: BufRead>(&self, input: &str, rdr: &GCodeReader<R>) {
+        if let Some(line) = rdr.unterminated_thumbnail_line() {
+            eprintln!(
+                "Warning: {input}: thumbnail at line {line} has no end marker, \
+                 it was taken to end at the first line that isn't a comment"
+            );
+        }
+    }
+
+    /// Checks what has been planned so far against `--max-moves` and `--max-arc-segments`
+    fn check_limits(&self, planner: &Planner) -> Result<(), LimitError> {
+        if let Some(max_moves) = self.max_moves {
+            if planner.num_moves() > max_moves {
+                return Err(LimitError::TooManyMoves(max_moves));
+            }
+        }
+        if let (Some(segments), Some(limit)) = (
+            planner.arc_state.oversized_arc,
+            planner.arc_state.max_segments,
+        ) {
+            return Err(LimitError::TooManyArcSegments { segments, limit });
+        }
+        Ok(())
     }
 }
 
+#[derive(clap::ArgEnum, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum KindFrom {
+    Typeonly,
+    Comment,
+    None,
+}
+
+/// A file exceeding the limits set to guard against pathological files
+#[derive(Error, Debug)]
+pub enum LimitError {
+    #[error("file exceeds the limit of {0} moves")]
+    TooManyMoves(usize),
+    #[error("an arc needs {segments} segments, more than the limit of {limit}")]
+    TooManyArcSegments { segments: usize, limit: usize },
+}
+
 #[derive(Error, Debug)]
 pub enum MoonrakerConfigError {
     #[error("given URL cannot be a base URL")]
@@ -126,6 +395,10 @@ pub enum MoonrakerConfigError {
     URLParseError(#[from] url::ParseError),
     #[error("request failed: {}", .0)]
     RequestError(#[from] reqwest::Error),
+    #[error("response has no config file settings")]
+    MissingSettings,
+    #[error("invalid config file settings: {}", .0)]
+    InvalidSettings(#[from] serde_json::Error),
 }
 
 #[derive(Debug, Clone)]
@@ -134,6 +407,8 @@ struct MoonrakerSource {
     api_key: Option<String>,
     ignore_error: bool,
     cache_file: Option<String>,
+    printer: Option<String>,
+    query_path: Option<String>,
 }
 
 impl MoonrakerSource {
@@ -142,12 +417,16 @@ impl MoonrakerSource {
         api_key: Option<&str>,
         ignore_error: bool,
         cache_file: Option<&str>,
+        printer: Option<&str>,
+        query_path: Option<&str>,
     ) -> MoonrakerSource {
         MoonrakerSource {
             url: url.into(),
             api_key: api_key.map(str::to_string),
             ignore_error,
             cache_file: cache_file.map(str::to_string),
+            printer: printer.map(str::to_string),
+            query_path: query_path.map(str::to_string),
         }
     }
 }
@@ -160,12 +439,14 @@ impl config::Source for MoonrakerSource {
     fn collect(&self) -> Result<config::Map<String, config::Value>, config::ConfigError> {
         let mut limits = PrinterLimits::default();
 
-        let res = moonraker_config(&self.url, self.api_key.as_deref(), &mut limits);
+        let res = moonraker_query_url(
+            &self.url,
+            self.printer.as_deref(),
+            self.query_path.as_deref(),
+        )
+        .and_then(|url| moonraker_config(url, self.api_key.as_deref(), &mut limits));
         let cfg = if let Err(e) = res {
-            match self.remap_collection_error(e) {
-                Ok(cfg) => cfg,
-                Err(err) => return Err(err),
-            }
+            self.remap_collection_error(e)?
         } else {
             let cfg = serde_json::to_string(&limits).unwrap();
             if let Some(cache_file) = self.cache_file.as_deref() {
@@ -215,86 +496,44 @@ impl MoonrakerSource {
     }
 }
 
-fn moonraker_config(
+/// Parses a Moonraker URL, assuming `http://` if no scheme is given, as users commonly pass
+/// just a host name or address, possibly with a port.
+fn moonraker_url(source_url: &str) -> Result<Url, MoonrakerConfigError> {
+    if source_url.contains("://") {
+        Ok(Url::parse(source_url)?)
+    } else {
+        Ok(Url::parse(&format!("http://{source_url}"))?)
+    }
+}
+
+/// Builds the URL querying the config file settings, as
+/// `<source_url>/[<printer>/]<query_path>?configfile=settings`
+fn moonraker_query_url(
     source_url: &str,
-    api_key: Option<&str>,
-    target: &mut PrinterLimits,
-) -> Result<(), MoonrakerConfigError> {
-    let mut url = Url::parse(source_url)?;
+    printer: Option<&str>,
+    query_path: Option<&str>,
+) -> Result<Url, MoonrakerConfigError> {
+    let mut url = moonraker_url(source_url)?;
     url.query_pairs_mut().append_pair("configfile", "settings");
     {
         let mut path = url
             .path_segments_mut()
             .map_err(|_| MoonrakerConfigError::URLCannotBeBase)?;
-        path.extend(&["printer", "objects", "query"]);
-    }
-
-    #[derive(Debug, Deserialize)]
-    struct MoonrakerResultRoot {
-        result: MoonrakerResult,
-    }
-
-    #[derive(Debug, Deserialize)]
-    struct MoonrakerResult {
-        status: MoonrakerResultStatus,
-    }
-
-    #[derive(Debug, Deserialize)]
-    struct MoonrakerResultStatus {
-        configfile: MoonrakerConfigFile,
-    }
-
-    #[derive(Debug, Deserialize)]
-    struct MoonrakerConfigFile {
-        settings: MoonrakerConfig,
-    }
-
-    #[derive(Debug, Deserialize)]
-    struct MoonrakerConfig {
-        printer: PrinterConfig,
-        extruder: ExtruderConfig,
-        firmware_retraction: Option<FirmwareRetractionConfig>,
-        gcode_arcs: Option<GcodeArcsConfig>,
-    }
-
-    #[derive(Debug, Deserialize)]
-    struct PrinterConfig {
-        max_velocity: f64,
-        max_accel: f64,
-        max_accel_to_decel: Option<f64>,
-        minimum_cruise_ratio: Option<f64>,
-        square_corner_velocity: f64,
-
-        max_x_velocity: Option<f64>,
-        max_x_accel: Option<f64>,
-        max_y_velocity: Option<f64>,
-        max_y_accel: Option<f64>,
-        max_z_velocity: Option<f64>,
-        max_z_accel: Option<f64>,
-    }
-
-    #[derive(Debug, Deserialize)]
-    struct ExtruderConfig {
-        max_extrude_only_velocity: f64,
-        max_extrude_only_accel: f64,
-        instantaneous_corner_velocity: f64,
-    }
-
-    #[derive(Debug, Deserialize)]
-    struct FirmwareRetractionConfig {
-        retract_length: f64,
-        unretract_extra_length: f64,
-        unretract_speed: f64,
-        retract_speed: f64,
-        #[serde(default)]
-        lift_z: f64,
-    }
-
-    #[derive(Debug, Deserialize)]
-    struct GcodeArcsConfig {
-        resolution: Option<f64>,
+        path.pop_if_empty();
+        if let Some(printer) = printer {
+            path.push(printer);
+        }
+        let query_path = query_path.unwrap_or("printer/objects/query");
+        path.extend(query_path.split('/').filter(|s| !s.is_empty()));
     }
+    Ok(url)
+}
 
+fn moonraker_config(
+    url: Url,
+    api_key: Option<&str>,
+    target: &mut PrinterLimits,
+) -> Result<(), MoonrakerConfigError> {
     let client = reqwest::blocking::Client::new();
     let mut req = client.get(url);
 
@@ -302,67 +541,14 @@ fn moonraker_config(
         req = req.header("X-Api-Key", api_key);
     }
 
-    let cfg = req
+    let response = req
         .send()?
         .error_for_status()?
-        .json::<MoonrakerResultRoot>()?
-        .result
-        .status
-        .configfile
-        .settings;
-
-    target.set_max_velocity(cfg.printer.max_velocity);
-    target.set_max_acceleration(cfg.printer.max_accel);
-    if let Some(v) = cfg.printer.minimum_cruise_ratio {
-        target.set_minimum_cruise_ratio(v);
-    } else if let Some(v) = cfg.printer.max_accel_to_decel {
-        target.set_max_accel_to_decel(v);
-    }
-    target.set_square_corner_velocity(cfg.printer.square_corner_velocity);
-    target.set_instant_corner_velocity(cfg.extruder.instantaneous_corner_velocity);
-
-    target.mm_per_arc_segment = cfg.gcode_arcs.and_then(|cfg| cfg.resolution);
-
-    target.firmware_retraction = cfg.firmware_retraction.map(|fr| FirmwareRetractionOptions {
-        retract_length: fr.retract_length,
-        unretract_extra_length: fr.unretract_extra_length,
-        unretract_speed: fr.unretract_speed,
-        retract_speed: fr.retract_speed,
-        lift_z: fr.lift_z,
-    });
-
-    let limits = [
-        (
-            DVec3::X,
-            cfg.printer.max_x_velocity,
-            cfg.printer.max_x_accel,
-        ),
-        (
-            DVec3::Y,
-            cfg.printer.max_y_velocity,
-            cfg.printer.max_y_accel,
-        ),
-        (
-            DVec3::Z,
-            cfg.printer.max_z_velocity,
-            cfg.printer.max_z_accel,
-        ),
-    ];
-
-    for (axis, m, a) in limits {
-        if let (Some(max_velocity), Some(max_accel)) = (m, a) {
-            target.move_checkers.push(MoveChecker::AxisLimiter {
-                axis,
-                max_velocity,
-                max_accel,
-            });
-        }
-    }
-
-    target.move_checkers.push(MoveChecker::ExtruderLimiter {
-        max_velocity: cfg.extruder.max_extrude_only_velocity,
-        max_accel: cfg.extruder.max_extrude_only_accel,
-    });
+        .json::<serde_json::Value>()?;
+    let settings = response
+        .pointer("/result/status/configfile/settings")
+        .ok_or(MoonrakerConfigError::MissingSettings)?;
+    target.apply_moonraker_settings(settings)?;
     Ok(())
 }
 
@@ -371,7 +557,11 @@ enum SubCommand {
     Estimate(cmd::estimate::EstimateCmd),
     DumpMoves(cmd::estimate::DumpMovesCmd),
     PostProcess(cmd::post_process::PostProcessCmd),
+    Check(cmd::check::CheckCmd),
     DumpConfig(cmd::dump_config::DumpConfigCmd),
+    ListKinds(cmd::list_kinds::ListKindsCmd),
+    #[clap(hide = true)]
+    Bench(cmd::bench::BenchCmd),
 }
 
 impl SubCommand {
@@ -380,12 +570,89 @@ impl SubCommand {
             Self::Estimate(i) => i.run(opts),
             Self::DumpMoves(i) => i.run(opts),
             Self::PostProcess(i) => i.run(opts),
+            Self::Check(i) => i.run(opts),
             Self::DumpConfig(i) => i.run(opts),
+            Self::ListKinds(i) => i.run(opts),
+            Self::Bench(i) => i.run(opts),
         }
     }
 }
 
+fn exit_with_error(msg: impl std::fmt::Display) -> ! {
+    eprintln!("Error: {msg}");
+    std::process::exit(1);
+}
+
+/// Exits with an IO error on `path`, for use with `unwrap_or_else`
+fn exit_with_io_error<T>(path: &Path) -> impl FnOnce(std::io::Error) -> T + '_ {
+    move |e| exit_with_error(format!("{}: {e}", path.display()))
+}
+
+/// Opens a file, exiting with an error if that fails
+fn open_file(path: impl AsRef<Path>) -> File {
+    let path = path.as_ref();
+    File::open(path).unwrap_or_else(exit_with_io_error(path))
+}
+
+/// Opens a gcode input, either a file or `-` for stdin
+fn try_open_input(input: &str) -> std::io::Result<Box<dyn Read>> {
+    Ok(match input {
+        "-" => Box::new(std::io::stdin()),
+        filename => Box::new(File::open(filename)?),
+    })
+}
+
+/// Like `try_open_input`, exiting if that fails
+fn open_input(input: &str) -> Box<dyn Read> {
+    try_open_input(input).unwrap_or_else(exit_with_io_error(input.as_ref()))
+}
+
 fn main() {
     let opts = Opts::parse();
     opts.cmd.run(&opts);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moonraker_url_without_scheme() {
+        assert_eq!(
+            moonraker_url("192.168.1.5").unwrap().as_str(),
+            "http://192.168.1.5/"
+        );
+        assert_eq!(
+            moonraker_url("printer.local:7125").unwrap().as_str(),
+            "http://printer.local:7125/"
+        );
+    }
+
+    #[test]
+    fn moonraker_url_with_scheme_and_path() {
+        assert_eq!(
+            moonraker_url("https://example.com/moonraker/")
+                .unwrap()
+                .as_str(),
+            "https://example.com/moonraker/"
+        );
+        assert_eq!(
+            moonraker_url("http://192.168.1.5:7125").unwrap().as_str(),
+            "http://192.168.1.5:7125/"
+        );
+    }
+
+    #[test]
+    fn moonraker_url_with_printer_and_query_path() {
+        let url = moonraker_query_url("proxy.local/", Some("voron"), None).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "http://proxy.local/voron/printer/objects/query?configfile=settings"
+        );
+        let url = moonraker_query_url("proxy.local", Some("voron"), Some("/api/query/")).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "http://proxy.local/voron/api/query?configfile=settings"
+        );
+    }
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use common::Workspace;
+
+/// The number in a line like `Planning:   0.001s (12345 moves/s)`
+fn rate(output: &str, label: &str) -> f64 {
+    let line = output
+        .lines()
+        .find(|l| l.starts_with(label))
+        .unwrap_or_else(|| panic!("no {label} line in {output}"));
+    let (_, rate) = line.split_once('(').unwrap();
+    rate.split_whitespace().next().unwrap().parse().unwrap()
+}
+
+#[test]
+fn bench_reports_throughput() {
+    let ws = Workspace::new();
+    let mut gcode = "G1 F6000\n".to_string();
+    for i in 1..=1000 {
+        gcode.push_str(&format!("G1 X{} Y{}\n", i % 100, i % 7));
+    }
+    let file = ws.write("bench.gcode", &gcode);
+
+    let output = ws.run_ok(&["bench", &file]);
+    assert!(output.contains("Lines:      1001\n"), "{output}");
+    assert!(output.contains("Moves:      1000\n"), "{output}");
+    assert!(rate(&output, "Parsing:") > 0.0);
+    assert!(rate(&output, "Planning:") > 0.0);
+}
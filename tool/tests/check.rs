@@ -0,0 +1,36 @@
+mod common;
+
+use common::Workspace;
+
+/// A PrusaSlicer file of a single 1.1s move, with the given embedded estimate
+fn prusaslicer_file(ws: &Workspace, estimate: &str) -> String {
+    let gcode = format!(
+        "; generated by PrusaSlicer 2.6.0 on 2024-01-01 at 12:00:00 UTC\n\
+         G1 X100 F6000\n\
+         ; estimated printing time (normal mode) = {}\n",
+        estimate
+    );
+    ws.write("check.gcode", &gcode)
+}
+
+#[test]
+fn wrong_slicer_estimate_fails() {
+    let ws = Workspace::new();
+    let file = prusaslicer_file(&ws, "1m 1s");
+    let output = ws.run(&["check", &file]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Check failed"), "{stdout}");
+}
+
+#[test]
+fn close_slicer_estimate_passes() {
+    let ws = Workspace::new();
+    let file = prusaslicer_file(&ws, "1s");
+    // 0.1s off is 10% of the slicer's estimate
+    assert!(!ws.run(&["check", &file]).status.success());
+    let output = ws.run_ok(&["check", "--tolerance", "15%", &file]);
+    assert!(output.contains("Check passed"), "{output}");
+    let output = ws.run_ok(&["check", "--tolerance", "0.5", &file]);
+    assert!(output.contains("Check passed"), "{output}");
+}
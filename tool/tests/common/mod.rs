@@ -0,0 +1,95 @@
+//! Helpers shared by the command line tests
+
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Printer configuration the tests run with: 200mm/s, 1000mm/s² without smoothing and a 5mm/s
+/// square corner velocity
+pub const CONFIG: &str = r#"{
+    "max_velocity": 200,
+    "max_acceleration": 1000,
+    "minimum_cruise_ratio": 0,
+    "square_corner_velocity": 5
+}"#;
+
+/// A temporary directory to run the tool in, with the test configuration written to it
+pub struct Workspace {
+    dir: tempfile::TempDir,
+}
+
+impl Workspace {
+    pub fn new() -> Workspace {
+        let ws = Workspace {
+            dir: tempfile::tempdir().expect("creating temporary directory failed"),
+        };
+        ws.write("config.json", CONFIG);
+        ws
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    pub fn write(&self, name: &str, contents: &str) -> String {
+        let path = self.path(name);
+        std::fs::write(&path, contents).expect("writing file failed");
+        path.to_string_lossy().into_owned()
+    }
+
+    pub fn read(&self, name: &str) -> String {
+        std::fs::read_to_string(self.path(name)).expect("reading file failed")
+    }
+
+    /// Runs the tool with the test configuration
+    pub fn run(&self, args: &[&str]) -> Output {
+        let config = self.path("config.json");
+        Command::new(env!("CARGO_BIN_EXE_klipper_estimator"))
+            .arg("--config_file")
+            .arg(config)
+            .args(args)
+            .current_dir(self.dir.path())
+            .output()
+            .expect("running klipper_estimator failed")
+    }
+
+    /// Runs the tool, expecting it to succeed, and returns its output
+    #[track_caller]
+    pub fn run_ok(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "klipper_estimator {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).expect("output should be UTF-8")
+    }
+
+    /// Total time in seconds of the JSON estimate of a file
+    pub fn total_time(&self, args: &[&str]) -> f64 {
+        let mut args = args.to_vec();
+        args.extend(["--format", "json"]);
+        let estimate: serde_json::Value =
+            serde_json::from_str(&self.run_ok(&args)).expect("estimate should be JSON");
+        sequences_time(&estimate)
+    }
+}
+
+pub fn sequences_time(estimate: &serde_json::Value) -> f64 {
+    estimate["sequences"]
+        .as_array()
+        .expect("estimate should have sequences")
+        .iter()
+        .map(|s| s["total_time"].as_f64().unwrap())
+        .sum()
+}
+
+#[track_caller]
+pub fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+    assert!(
+        (actual - expected).abs() <= tolerance,
+        "expected {expected} ± {tolerance}, got {actual}"
+    );
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::Workspace;
+
+#[test]
+fn explain_names_where_each_field_comes_from() {
+    let ws = Workspace::new();
+    let output = ws.run_ok(&["-c", "max_velocity=123", "dump-config", "--explain"]);
+    let file = ws.path("config.json");
+    let lines: Vec<&str> = output.lines().collect();
+
+    // The override wins over the test configuration's 200mm/s
+    assert!(
+        lines.contains(&"max_velocity = 123.0 (override)"),
+        "{output}"
+    );
+    let from_file = format!("max_acceleration = 1000.0 (file {})", file.display());
+    assert!(lines.contains(&from_file.as_str()), "{output}");
+    assert!(
+        lines.contains(&"instant_corner_velocity = 1.0 (default)"),
+        "{output}"
+    );
+}
+
+#[test]
+fn later_config_files_override_earlier_ones() {
+    let ws = Workspace::new();
+    let overlay = ws.write(
+        "overlay.json",
+        r#"{ "max_velocity": 300, "square_corner_velocity": 8 }"#,
+    );
+    let args = ["--config_file", &overlay, "dump-config"];
+    let merged: serde_json::Value = serde_json::from_str(&ws.run_ok(&args)).unwrap();
+    // The test configuration comes first, so the overlay wins where both set a field
+    assert_eq!(merged["max_velocity"], 300.0);
+    assert_eq!(merged["square_corner_velocity"], 8.0);
+    assert_eq!(merged["max_acceleration"], 1000.0);
+
+    let args = [
+        "--config_file",
+        &overlay,
+        "-c",
+        "square_corner_velocity=9",
+        "dump-config",
+    ];
+    let overridden: serde_json::Value = serde_json::from_str(&ws.run_ok(&args)).unwrap();
+    assert_eq!(overridden["max_velocity"], 300.0);
+    assert_eq!(overridden["square_corner_velocity"], 9.0);
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use common::Workspace;
+
+#[test]
+fn peak_velocity_notes_unreached_feedrates() {
+    let ws = Workspace::new();
+    let file = ws.write("short.gcode", "G1 X1 F6000\nG1 X-99\nG1 X-199 F30000\n");
+    let dump = ws.run_ok(&["dump-moves", &file]);
+    let peaks: Vec<&str> = dump
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("Peak velocity: "))
+        .collect();
+    assert_eq!(
+        peaks,
+        vec![
+            "31.623 of 100.000 requested, not reached",
+            "100.000 of 100.000 requested",
+            "200.000 of 500.000 requested, not reached"
+        ]
+    );
+}
+
+/// Number of decimals of the first number after a label in the dump
+fn decimals(dump: &str, label: &str) -> usize {
+    let line = dump
+        .lines()
+        .find_map(|l| l.trim().strip_prefix(label))
+        .unwrap_or_else(|| panic!("no {label:?} in dump"));
+    let number = line
+        .trim()
+        .trim_start_matches('[')
+        .split([',', ' '])
+        .next()
+        .unwrap();
+    number.split_once('.').map_or(0, |(_, d)| d.len())
+}
+
+#[test]
+fn output_precision_defaults_and_overrides() {
+    let ws = Workspace::new();
+    let file = ws.write("line.gcode", "G1 X10.12345 F6000\n");
+
+    let dump = ws.run_ok(&["dump-moves", &file]);
+    assert_eq!(decimals(&dump, "Path:"), 3);
+    assert_eq!(decimals(&dump, "Velocity:"), 3);
+    assert_eq!(decimals(&dump, "Acceleration"), 4);
+    assert_eq!(decimals(&dump, "Max smoothed_v2:"), 4);
+
+    let dump = ws.run_ok(&["dump-moves", "--output-precision-mm", "6", &file]);
+    assert!(dump.contains("[10.123450, 0.000000"));
+    assert_eq!(decimals(&dump, "Velocity:"), 6);
+    assert_eq!(decimals(&dump, "Acceleration"), 6);
+    assert_eq!(decimals(&dump, "Max dv2:"), 6);
+}
@@ -0,0 +1,698 @@
+mod common;
+
+use common::{assert_close, sequences_time, Workspace};
+
+#[test]
+fn underruns_add_stall_time_for_dense_tiny_moves() {
+    let ws = Workspace::new();
+    // 200 moves of 0.05mm along a line, far shorter than the host needs to produce them at
+    // 100 moves/s
+    let mut gcode = "G1 X0 F6000\n".to_string();
+    for i in 1..=200 {
+        gcode.push_str(&format!("G1 X{:.2}\n", f64::from(i) * 0.05));
+    }
+    let file = ws.write("tiny.gcode", &gcode);
+    let estimate = |args: &[&str]| -> serde_json::Value {
+        let mut args = args.to_vec();
+        args.extend(["--format", "json", &file]);
+        serde_json::from_str(&ws.run_ok(&args)).unwrap()
+    };
+    let underrun_time = |estimate: &serde_json::Value| -> f64 {
+        estimate["sequences"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["total_underrun_time"].as_f64().unwrap())
+            .sum()
+    };
+
+    let ideal = estimate(&["estimate"]);
+    let stalled = estimate(&[
+        "estimate",
+        "--model-underruns",
+        "--underrun-move-rate",
+        "100",
+        "--underrun-buffer-size",
+        "10",
+    ]);
+    let stall = underrun_time(&stalled);
+
+    assert_eq!(underrun_time(&ideal), 0.0);
+    assert!(stall > 1.0, "stalled for {stall}s");
+    assert_close(
+        sequences_time(&stalled),
+        sequences_time(&ideal) + stall,
+        1e-9,
+    );
+    // The toolhead can't finish before the host produced the last move
+    assert!(sequences_time(&stalled) >= 200.0 / 100.0);
+}
+
+#[test]
+fn parallel_estimates_match_sequential_ones() {
+    let ws = Workspace::new();
+    let files: Vec<String> = (1..=8)
+        .map(|n| {
+            let mut gcode = format!(";TYPE:Perimeter\nG1 F{}\n", 1200 * n);
+            for i in 0..50 * n {
+                gcode.push_str(&format!("G1 X{} Y{} E{}\n", i % 17, i % 5, i));
+            }
+            ws.write(&format!("file{n}.gcode"), &gcode)
+        })
+        .collect();
+
+    let sequential: Vec<String> = files
+        .iter()
+        .map(|file| format!("File: {file}\n{}", ws.run_ok(&["estimate", file])))
+        .collect();
+    let mut args = vec!["estimate"];
+    args.extend(files.iter().map(String::as_str));
+    assert_eq!(ws.run_ok(&args), sequential.join("\n"));
+}
+
+#[test]
+fn zigzag_perimeters_are_planner_limited() {
+    let ws = Workspace::new();
+    let mut gcode = ";TYPE:Perimeter\nG1 F6000\n".to_string();
+    for i in 1..=20 {
+        gcode.push_str(&format!("G1 X{} Y{} E{}\n", i, (i % 2) * 2, i));
+    }
+    gcode.push_str(";TYPE:Infill\nG1 X100 E30\n");
+    let file = ws.write("zigzag.gcode", &gcode);
+    let estimate: serde_json::Value =
+        serde_json::from_str(&ws.run_ok(&["estimate", "--format", "json", &file])).unwrap();
+    let limited = &estimate["sequences"][0]["speed_limited_by_planner"];
+
+    // Each 2.2mm leg reverses the previous one, far too short to reach 100mm/s
+    let perimeters = &limited["Perimeter"];
+    assert_eq!(perimeters["count"], 20);
+    assert!(perimeters["time"].as_f64().unwrap() > 0.0);
+    assert!(limited.get("Infill").is_none());
+}
+
+#[test]
+fn sidecar_matches_json_output() {
+    let ws = Workspace::new();
+    let file = ws.write("sidecar.gcode", "G1 X100 F6000\nG1 Y100 E5\n");
+    let human = ws.run_ok(&["estimate", "--write-sidecar", &file]);
+    assert!(human.contains("Minimal time:"));
+
+    let sidecar: serde_json::Value =
+        serde_json::from_str(&ws.read("sidecar.gcode.estimate.json")).unwrap();
+    let stdout: serde_json::Value =
+        serde_json::from_str(&ws.run_ok(&["estimate", "--format", "json", &file])).unwrap();
+    assert_eq!(sidecar, stdout);
+}
+
+#[test]
+fn unwritable_sidecar_is_an_error() {
+    let ws = Workspace::new();
+    let file = ws.write("sidecar.gcode", "G1 X100 F6000\n");
+    std::fs::create_dir(ws.path("sidecar.gcode.estimate.json")).unwrap();
+    let output = ws.run(&["estimate", "--write-sidecar", &file]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("sidecar.gcode.estimate.json: "), "{stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+}
+
+#[test]
+fn deposited_extrusion_excludes_retractions_and_wipes() {
+    let ws = Workspace::new();
+    let extrusion = |gcode: &str| {
+        let file = ws.write("wipe.gcode", gcode);
+        let estimate: serde_json::Value =
+            serde_json::from_str(&ws.run_ok(&["estimate", "--format", "json", &file])).unwrap();
+        let seq = &estimate["sequences"][0];
+        (
+            seq["total_deposited_distance"].as_f64().unwrap(),
+            seq["total_extrude_distance"].as_f64().unwrap(),
+        )
+    };
+
+    let (deposited, extruded) =
+        extrusion("M83\nG1 X10 E1 F6000\nG1 E-2\nG1 X20 E-1\nG1 E3\nG1 X30 E1\n");
+    assert_close(deposited, 2.0, 1e-9);
+    assert_close(extruded, 2.0, 1e-9);
+
+    let (deposited, extruded) = extrusion("M83\nG1 X10 E1 F6000\nG1 E-2\nG1 X20 E-1\n");
+    assert_close(deposited, 1.0, 1e-9);
+    assert_close(extruded, -2.0, 1e-9);
+}
+
+#[test]
+fn every_retraction_is_timed() {
+    let ws = Workspace::new();
+    // A 20mm prime is too long to be a retraction
+    let mut gcode = "M83\nG1 E20 F2400\n".to_string();
+    for i in 1..=10 {
+        gcode.push_str(&format!(
+            "G1 X{} E1 F6000\nG1 E-1 F2400\nG1 X{} F6000\nG1 E1 F2400\n",
+            20 * i - 10,
+            20 * i
+        ));
+    }
+    let file = ws.write("retractions.gcode", &gcode);
+    let estimate: serde_json::Value =
+        serde_json::from_str(&ws.run_ok(&["estimate", "--format", "json", &file])).unwrap();
+    let retraction_time = estimate["sequences"][0]["total_retraction_time"]
+        .as_f64()
+        .unwrap();
+    // 1mm at 40mm/s each way, without extruder limits to accelerate against
+    assert_close(retraction_time, 10.0 * 2.0 / 40.0, 1e-9);
+}
+
+#[test]
+fn start_and_end_gcode_share_the_toolhead_with_the_file() {
+    let ws = Workspace::new();
+    let file = ws.write("main.gcode", "G1 X0 F6000\n");
+    let start = ws.write("start.gcode", "G1 X100 F6000\n");
+
+    // The file's move reverses the start gcode's, each taking 1.1s after the 0.25s startup
+    let time = ws.total_time(&["estimate", "--start-gcode", &start, &file]);
+    common::assert_close(time, 0.25 + 1.1 + 1.1, 1e-9);
+    let time = ws.total_time(&[
+        "estimate",
+        "--start-gcode",
+        "G1 X50 F6000\\nG1 X100",
+        "--end-gcode",
+        "G1 X100",
+        &file,
+    ]);
+    common::assert_close(time, 0.25 + 1.1 + 1.1 + 1.1, 1e-9);
+}
+
+#[test]
+fn bad_start_gcode_is_reported() {
+    let ws = Workspace::new();
+    let file = ws.write("main.gcode", "G1 X0 F6000\n");
+    let stderr = |args: &[&str]| {
+        let output = ws.run(args);
+        (
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    };
+
+    let (code, err) = stderr(&["estimate", "--start-gcode", "strat.gcode", &file]);
+    assert_eq!(code, Some(1));
+    assert!(
+        err.contains("--start-gcode strat.gcode: no such file"),
+        "{err}"
+    );
+
+    std::fs::write(ws.path("binary.gcode"), b"G1 X10\n\xff\xfe\n").unwrap();
+    let (code, err) = stderr(&["estimate", "--end-gcode", "binary.gcode", &file]);
+    assert_eq!(code, Some(1));
+    assert!(err.contains("--end-gcode binary.gcode:"), "{err}");
+    assert!(!err.contains("panicked"), "{err}");
+
+    let (code, err) = stderr(&["estimate", "--start-gcode", "; nothing", &file]);
+    assert_eq!(code, Some(0));
+    assert!(
+        err.contains("Warning: --start-gcode \"; nothing\""),
+        "{err}"
+    );
+}
+
+#[test]
+fn pressure_advance_is_reported_without_affecting_time() {
+    let ws = Workspace::new();
+    let moves = "G1 X100 F6000\nG1 X0\n";
+    let plain = ws.write("plain.gcode", moves);
+    let gcode = format!(
+        "SET_PRESSURE_ADVANCE ADVANCE=0.04\n{moves}\
+SET_PRESSURE_ADVANCE ADVANCE=0.05\nSET_PRESSURE_ADVANCE EXTRUDER=extruder1 ADVANCE=0.1\n{moves}"
+    );
+    let file = ws.write("pa.gcode", &gcode);
+
+    let output = ws.run_ok(&["estimate", "--verbose", &file]);
+    assert!(output.contains("  extruder: 0.04, 0.05\n"), "{output}");
+    assert!(output.contains("  extruder1: 0.1\n"), "{output}");
+    assert!(!ws.run_ok(&["estimate", &file]).contains("Pressure advance"));
+
+    let doubled = ws.total_time(&["estimate", &plain]) * 2.0 - 0.25;
+    common::assert_close(ws.total_time(&["estimate", &file]), doubled, 1e-9);
+}
+
+#[test]
+fn decimal_comma_files_estimate_like_decimal_point_ones() {
+    let ws = Workspace::new();
+    let comma = ws.write("comma.gcode", "G1 X10,5 F6000\nG1 Y20,25\n");
+    let point = ws.write("point.gcode", "G1 X10.5 F6000\nG1 Y20.25\n");
+
+    let expected = ws.total_time(&["estimate", &point]);
+    common::assert_close(
+        ws.total_time(&["--decimal-comma", "estimate", &comma]),
+        expected,
+        1e-9,
+    );
+
+    let output = ws.run(&["estimate", &comma]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("line 1 appears to use a comma decimal separator"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn streaming_estimator_matches_the_estimate_command() {
+    use lib_klipper::estimation::Estimator;
+    use lib_klipper::planner::{Planner, PrinterLimits};
+
+    let mut gcode = ";TYPE:Perimeter\nG1 F6000\n".to_string();
+    for i in 0..3000 {
+        gcode.push_str(&format!("G1 X{} Y{} E{}\n", i % 23, (i * 7) % 11, i));
+        if i % 500 == 0 {
+            gcode.push_str("G4 P100\n");
+        }
+    }
+    let ws = Workspace::new();
+    let file = ws.write("stream.gcode", &gcode);
+
+    // The same limits as the test configuration
+    let mut limits = PrinterLimits::default();
+    limits.set_max_velocity(200.0);
+    limits.set_max_acceleration(1000.0);
+    limits.set_minimum_cruise_ratio(0.0);
+    limits.set_square_corner_velocity(5.0);
+    let mut estimator = Estimator::new(Planner::from_limits(limits));
+    let mut partial = 0.0;
+    for line in gcode.lines() {
+        estimator.feed_line(line).unwrap();
+        assert!(estimator.partial_time() >= partial);
+        partial = estimator.partial_time();
+    }
+    let state = estimator.finish();
+    let streamed: f64 = state.sequences.iter().map(|s| s.total_time).sum();
+
+    assert!(partial > 0.0 && partial < streamed);
+    common::assert_close(streamed, ws.total_time(&["estimate", &file]), 1e-6);
+}
+
+#[test]
+fn ui_json_has_the_metadata_uis_read() {
+    let ws = Workspace::new();
+    let gcode = "\
+EXCLUDE_OBJECT_DEFINE NAME=a
+EXCLUDE_OBJECT_DEFINE NAME=b
+M83
+G1 Z0.2 F6000
+G1 X100 E500
+G1 Z0.4
+G1 X0 E500
+";
+    let file = ws.write("ui.gcode", gcode);
+    let out = ws.run_ok(&[
+        "-c",
+        "filament_density=1.25",
+        "-c",
+        "filament_diameter=1.75",
+        "estimate",
+        "--format",
+        "ui-json",
+        &file,
+    ]);
+    let metadata: serde_json::Value = serde_json::from_str(&out).unwrap();
+    let keys: Vec<&str> = metadata
+        .as_object()
+        .unwrap()
+        .keys()
+        .map(String::as_str)
+        .collect();
+    assert_eq!(
+        keys,
+        [
+            "estimated_time",
+            "filament_total",
+            "filament_weight_total",
+            "layer_count",
+            "object_height",
+            "object_count"
+        ]
+    );
+    assert_eq!(metadata["filament_total"], 1000.0);
+    // 1000mm of 1.75mm filament is 2.405cm³
+    let weight = 1000.0 * std::f64::consts::PI * 0.875f64.powi(2) / 1000.0 * 1.25;
+    common::assert_close(
+        metadata["filament_weight_total"].as_f64().unwrap(),
+        weight,
+        1e-9,
+    );
+    assert_eq!(metadata["layer_count"], 2);
+    assert_eq!(metadata["object_height"], 0.4);
+    assert_eq!(metadata["object_count"], 2);
+}
+
+#[test]
+fn top_layers_prints_the_slowest_layers_and_stats() {
+    let ws = Workspace::new();
+    // Layer n extrudes 10n mm back and forth, so higher layers are slower
+    let mut gcode = "M83\nG1 F6000\n".to_string();
+    for n in 1..=10 {
+        gcode.push_str(&format!(
+            "G1 Z{:.1}\nG1 X{} E1\nG1 X0 E1\n",
+            0.2 * n as f64,
+            10 * n
+        ));
+    }
+    let file = ws.write("layers.gcode", &gcode);
+    let output = ws.run_ok(&["estimate", "--top-layers", "5", &file]);
+
+    let rows: Vec<&str> = output
+        .lines()
+        .skip_while(|l| *l != "  Slowest layers:")
+        .skip(1)
+        .take_while(|l| l.starts_with("   "))
+        .collect();
+    assert_eq!(rows.len(), 5, "{output}");
+    assert!(rows[0].trim_start().starts_with("2"), "{output}");
+    assert_eq!(
+        output
+            .lines()
+            .filter(|l| l.starts_with("  Layer times: min "))
+            .count(),
+        1
+    );
+    assert!(!output.contains("Layer time distribution"));
+}
+
+#[test]
+fn excluding_an_object_saves_its_time_but_not_shared_travel() {
+    let ws = Workspace::new();
+    let gcode = "\
+EXCLUDE_OBJECT_DEFINE NAME=a
+EXCLUDE_OBJECT_DEFINE NAME=b
+G1 X10 F6000
+EXCLUDE_OBJECT_START NAME=a
+G1 X60 E5
+EXCLUDE_OBJECT_END NAME=a
+G1 Y50
+EXCLUDE_OBJECT_START NAME=b
+G1 X10 E10
+EXCLUDE_OBJECT_END NAME=b
+G1 Y0
+";
+    let file = ws.write("objects.gcode", gcode);
+    let full = ws.total_time(&["estimate", &file]);
+    let without_a = ws.total_time(&["estimate", "--exclude-object", "a", &file]);
+    let without_both = ws.total_time(&[
+        "estimate",
+        "--exclude-object",
+        "a",
+        "--exclude-object",
+        "b",
+        &file,
+    ]);
+
+    assert!(without_a < full, "{without_a} >= {full}");
+    assert!(without_both < without_a, "{without_both} >= {without_a}");
+    // The travel before and between the objects is still there
+    assert!(without_both > 0.25 + 0.2, "{without_both}");
+}
+
+#[test]
+fn compact_json_matches_pretty_json() {
+    let ws = Workspace::new();
+    let file = ws.write(
+        "compact.gcode",
+        ";TYPE:Perimeter\nG1 Z0.2 F6000\nG1 X100 E5\nG1 Z0.4\nG1 Y100 E10\n",
+    );
+    let pretty = ws.run_ok(&["estimate", "--format", "json", &file]);
+    let compact = ws.run_ok(&["estimate", "--format", "json", "--json-compact", &file]);
+    assert!(pretty.contains('\n'));
+    assert!(!compact.trim_end().contains(char::is_whitespace));
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&compact).unwrap(),
+        serde_json::from_str::<serde_json::Value>(&pretty).unwrap()
+    );
+    // Keys keep their order, so the outputs only differ in whitespace
+    let strip = |s: &str| s.split_whitespace().collect::<String>();
+    assert_eq!(strip(&compact), strip(&pretty));
+}
+
+#[test]
+fn watch_estimates_new_files_once() {
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    let ws = Workspace::new();
+    let dir = ws.path("uploads");
+    std::fs::create_dir(&dir).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_klipper_estimator"))
+        .arg("--config_file")
+        .arg(ws.path("config.json"))
+        .args(["estimate", "--write-sidecar", "--watch"])
+        .arg(&dir)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Give the watcher time to start
+    std::thread::sleep(Duration::from_millis(500));
+    ws.write("uploads/print.gcode", "G1 X100 F6000\n");
+
+    let sidecar = dir.join("print.gcode.estimate.json");
+    let start = Instant::now();
+    while !sidecar.exists() && start.elapsed() < Duration::from_secs(20) {
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    // Long enough for a second estimate if the file were picked up again
+    std::thread::sleep(Duration::from_millis(2500));
+    child.kill().unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    let estimate: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&sidecar).unwrap()).unwrap();
+    common::assert_close(common::sequences_time(&estimate), 0.25 + 1.1, 1e-9);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.matches("File: ").count(), 1, "{stdout}");
+}
+
+#[test]
+fn watch_keeps_going_after_a_bad_file() {
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    let ws = Workspace::new();
+    let dir = ws.path("uploads");
+    std::fs::create_dir(&dir).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_klipper_estimator"))
+        .arg("--config_file")
+        .arg(ws.path("config.json"))
+        .args([
+            "estimate",
+            "--input-format",
+            "json",
+            "--write-sidecar",
+            "--watch",
+        ])
+        .arg(&dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    ws.write("uploads/bad.gcode", "not a move list");
+    std::thread::sleep(Duration::from_millis(2500));
+    ws.write(
+        "uploads/good.gcode",
+        r#"[{"end": [100, 0, 0, 0], "feedrate": 100}]"#,
+    );
+
+    let sidecar = dir.join("good.gcode.estimate.json");
+    let start = Instant::now();
+    while !sidecar.exists() && start.elapsed() < Duration::from_secs(20) {
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    child.kill().unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("bad.gcode: invalid move list"), "{stderr}");
+    assert!(sidecar.exists(), "{stderr}");
+}
+
+#[test]
+fn bad_lines_are_reported_without_panicking() {
+    let ws = Workspace::new();
+    let file = ws.write("bad.gcode", "G1 X10 F6000\n%%%\nG1 X20\n");
+    let output = ws.run(&["estimate", &file]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("invalid gcode on line 2: %%%"), "{stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+
+    let output = ws.run(&["estimate", &ws.path("missing.gcode").to_string_lossy()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("panicked"));
+}
+
+#[test]
+fn skip_errors_estimates_around_garbage_lines() {
+    let ws = Workspace::new();
+    let gcode = "G1 X10 F6000\n%%%\nG1 X20\n@@@ garbage\nG1 X30\n%\n";
+    let file = ws.write("garbage.gcode", gcode);
+    let clean = ws.write("clean.gcode", "G1 X10 F6000\nG1 X20\nG1 X30\n");
+
+    let output = ws.run(&["--skip-errors", "estimate", "--format", "json", &file]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("line 4: @@@ garbage, skipping"), "{stderr}");
+    assert!(stderr.contains("skipped 3 invalid lines"), "{stderr}");
+    let estimate: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    common::assert_close(
+        common::sequences_time(&estimate),
+        ws.total_time(&["estimate", &clean]),
+        1e-9,
+    );
+}
+
+#[test]
+fn profile_reports_every_phase() {
+    let ws = Workspace::new();
+    let file = ws.write(
+        "profile.gcode",
+        "G1 X100 F6000\nG1 Y100\nG2 X0 Y100 I-50 J0\n",
+    );
+    let output = ws.run(&["estimate", "--profile", &file]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for phase in ["Parsing:", "Planning:", "Lookahead:", "Output:"] {
+        let line = stderr
+            .lines()
+            .find(|l| l.trim_start().starts_with(phase))
+            .unwrap_or_else(|| panic!("no {phase} in {stderr}"));
+        let time = line.trim_start()[phase.len()..].trim();
+        let seconds: f64 = time.strip_suffix('s').unwrap().parse().unwrap();
+        assert!(seconds >= 0.0, "{line}");
+    }
+}
+
+#[test]
+fn tool_changes_add_their_time() {
+    let ws = Workspace::new();
+    // Three changes, as the second T0 keeps the active tool
+    let file = ws.write(
+        "tools.gcode",
+        "G1 X10 F6000\nT1\nG1 X20\nT0\nG1 X30\nT0\nT1\nG1 X40\n",
+    );
+    // A zero change time still splits the print at each change
+    let base = ws.total_time(&["--tool-change-time", "0", "estimate", &file]);
+    let each = ws.total_time(&["--tool-change-time", "5", "estimate", &file]);
+    common::assert_close(each, base + 3.0 * 5.0, 1e-9);
+    let per_tool = ws.total_time(&[
+        "--tool-change-time",
+        "5",
+        "--tool-change-time",
+        "T1=20",
+        "estimate",
+        &file,
+    ]);
+    common::assert_close(per_tool, base + 2.0 * 20.0 + 5.0, 1e-9);
+}
+
+#[test]
+fn sweep_lists_a_time_per_value() {
+    let ws = Workspace::new();
+    // Three 100mm moves reversing direction, so each starts and ends at rest
+    let file = ws.write("sweep.gcode", "G1 X100 F12000\nG1 X0\nG1 X100\n");
+    let output = ws.run_ok(&["estimate", "--sweep", "max_acceleration=1000,3000", &file]);
+    let rows: Vec<Vec<&str>> = output
+        .lines()
+        .skip(1)
+        .map(|l| l.split_whitespace().collect())
+        .collect();
+    assert_eq!(rows, vec![vec!["1000", "2.350s"], vec!["3000", "1.950s"]]);
+}
+
+#[test]
+fn start_at_shows_layer_completion_times() {
+    let ws = Workspace::new();
+    let gcode = "G1 Z0.2 F600\nG1 X100 E1\nG1 Z0.4\nG1 X0 E1\nG1 Z0.6\nG1 X100 E1\n";
+    let file = ws.write("layers.gcode", gcode);
+    let start = "2024-01-01T12:00:00Z";
+    let output = ws.run_ok(&["estimate", "--start-at", start, &file]);
+    // Seconds since midnight of an RFC 3339 UTC time, all of them being on the same day
+    let timestamp = |line: &str| -> i64 {
+        let t = line.rsplit(' ').next().unwrap();
+        let (_, time) = t.trim_end_matches('Z').split_once('T').unwrap();
+        time.split(':')
+            .map(|n| n.parse::<i64>().unwrap())
+            .fold(0, |acc, n| acc * 60 + n)
+    };
+
+    let lines: Vec<&str> = output.lines().collect();
+    let layers = lines
+        .iter()
+        .position(|l| l.contains("Layer completion"))
+        .unwrap();
+    let completions: Vec<i64> = lines[layers + 1..layers + 4]
+        .iter()
+        .map(|l| timestamp(l))
+        .collect();
+    assert!(completions.windows(2).all(|w| w[0] < w[1]), "{output}");
+
+    let finish = lines.iter().find(|l| l.contains("Finishes at:")).unwrap();
+    let start = timestamp(start);
+    let total = ws.total_time(&["estimate", &file]);
+    assert_eq!(timestamp(finish), start + total.round() as i64);
+    assert_eq!(completions[2], timestamp(finish));
+}
+
+#[test]
+fn time_precision_only_rounds_times_in_json() {
+    let ws = Workspace::new();
+    let file = ws.write("layer.gcode", "G1 Z0.35 F600\nG1 X33.3333 E1.23456 F6000\n");
+    let out = ws.run_ok(&[
+        "estimate",
+        "--format",
+        "json",
+        "--time-precision",
+        "0",
+        &file,
+    ]);
+    let estimate: serde_json::Value = serde_json::from_str(&out).unwrap();
+    let seq = &estimate["sequences"][0];
+
+    let total_time = seq["total_time"].as_f64().unwrap();
+    assert_eq!(total_time, total_time.round());
+    assert_eq!(seq["phase_times"]["cruise"].as_f64().unwrap().fract(), 0.0);
+    assert_eq!(seq["total_distance"].as_f64().unwrap(), 33.3333 + 0.35);
+    assert_eq!(seq["total_extrude_distance"].as_f64().unwrap(), 1.23456);
+    // Layer heights are kept, only their times are rounded
+    let layer = &seq["layer_times"][0];
+    assert_eq!(layer[0].as_f64().unwrap(), 0.35);
+    assert_eq!(layer[1].as_f64().unwrap().fract(), 0.0);
+}
+
+#[test]
+fn json_moves_match_equivalent_gcode() {
+    let ws = Workspace::new();
+    let moves = ws.write(
+        "moves.json",
+        r#"[
+            {"end": [50, 0, 0.2, 2], "feedrate": 60},
+            {"end": [50, 50, 0.2, 4], "feedrate": 60},
+            {"end": [50, 50, 0.2, 3], "feedrate": 30},
+            {"start": [50, 50, 0.2, 3], "end": [0, 50, 0.2, 3], "feedrate": 150}
+        ]"#,
+    );
+    let gcode = ws.write(
+        "moves.gcode",
+        "M82\nG1 X50 Z0.2 E2 F3600\nG1 Y50 E4\nG1 E3 F1800\nG1 X0 F9000\n",
+    );
+
+    let json_time = ws.total_time(&["estimate", "--input-format", "json", &moves]);
+    common::assert_close(json_time, ws.total_time(&["estimate", &gcode]), 1e-9);
+    // The move list isn't gcode, so there are no slicer settings to read from it
+    let with_accel = ws.total_time(&[
+        "estimate",
+        "--input-format",
+        "json",
+        "--slicer-accel",
+        &moves,
+    ]);
+    common::assert_close(with_accel, json_time, 1e-9);
+}
@@ -0,0 +1,88 @@
+//! Drives the C API of the `ffi` crate and checks it matches the command line tool
+
+mod common;
+
+use std::ffi::{CStr, CString};
+
+use klipper_estimator_ffi::*;
+
+use common::Workspace;
+
+const GCODE: &str = "\
+;TYPE:External perimeter
+G1 X10 Y10 F6000
+G1 X50 Y10 E2 F3000
+G1 X50 Y50 E4
+G4 P500
+;TYPE:Internal infill
+G1 X10 Y50 E6 F6000
+G1 X10 Y10 E8
+G1 E7 F2400
+G1 X60 Y60 F9000
+";
+
+/// Estimates gcode through the C API, feeding it in chunks that split lines
+fn ffi_estimate(config: &str, gcode: &str) -> (f64, serde_json::Value) {
+    let config = CString::new(config).unwrap();
+    unsafe {
+        let est = ke_estimator_new(config.as_ptr());
+        assert!(!est.is_null());
+        for chunk in gcode.as_bytes().chunks(7) {
+            let chunk = CString::new(chunk).unwrap();
+            assert_eq!(ke_estimator_feed(est, chunk.as_ptr()), 0);
+        }
+        assert!(ke_estimator_result_json(est).is_null());
+        assert_eq!(ke_estimator_finish(est), 0);
+        let total_time = ke_estimator_total_time(est);
+        let json = ke_estimator_result_json(est);
+        assert!(!json.is_null());
+        let result = serde_json::from_str(CStr::from_ptr(json).to_str().unwrap()).unwrap();
+        ke_string_free(json);
+        ke_estimator_free(est);
+        (total_time, result)
+    }
+}
+
+#[test]
+fn c_api_matches_cli() {
+    let ws = Workspace::new();
+    let file = ws.write("print.gcode", GCODE);
+    let config = ws.run_ok(&["dump-config"]);
+    let cli: serde_json::Value =
+        serde_json::from_str(&ws.run_ok(&["estimate", "--format", "json", &file])).unwrap();
+
+    let (total_time, result) = ffi_estimate(&config, GCODE);
+    assert_eq!(result, cli);
+    assert_eq!(total_time, common::sequences_time(&cli));
+}
+
+#[test]
+fn c_api_rejects_invalid_input() {
+    let config = CString::new("not json").unwrap();
+    assert!(unsafe { ke_estimator_new(config.as_ptr()) }.is_null());
+
+    let config = CString::new("{}").unwrap();
+    let gcode = CString::new("G1 X10\n!!!\n").unwrap();
+    unsafe {
+        let est = ke_estimator_new(config.as_ptr());
+        assert_eq!(ke_estimator_feed(est, gcode.as_ptr()), -1);
+        ke_estimator_free(est);
+    }
+}
+
+#[test]
+fn c_api_feeds_the_rest_of_a_chunk_after_a_bad_line() {
+    let ws = Workspace::new();
+    let config = ws.run_ok(&["dump-config"]);
+    let (expected, _) = ffi_estimate(&config, "G1 X100 F6000\nG1 X0\n");
+
+    let config = CString::new(config).unwrap();
+    let gcode = CString::new("G1 X100 F6000\n!!!\nG1 X0\n").unwrap();
+    unsafe {
+        let est = ke_estimator_new(config.as_ptr());
+        assert_eq!(ke_estimator_feed(est, gcode.as_ptr()), -1);
+        assert_eq!(ke_estimator_finish(est), 0);
+        assert_eq!(ke_estimator_total_time(est), expected);
+        ke_estimator_free(est);
+    }
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use common::Workspace;
+
+#[test]
+fn unsupported_kinematics_are_warned_about() {
+    let ws = Workspace::new();
+    let file = ws.write("moves.gcode", "G1 X10 F6000\n");
+    let warning = "delta kinematics aren't modeled";
+
+    let output = ws.run(&["-c", "kinematics=delta", "estimate", &file]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(warning), "{stderr}");
+
+    let output = ws.run(&["-c", "kinematics=cartesian", "estimate", &file]);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("kinematics"));
+}
+
+#[test]
+fn arc_segment_length_sets_the_number_of_moves() {
+    let ws = Workspace::new();
+    // A half circle of radius 10, 10π mm long
+    let file = ws.write("arc.gcode", "G2 X20 Y0 I10 J0 F6000\n");
+    let run = |segment: &str| {
+        let setting = format!("mm_per_arc_segment={segment}");
+        let out = ws.run_ok(&["-c", &setting, "estimate", "--format", "json", &file]);
+        let estimate: serde_json::Value = serde_json::from_str(&out).unwrap();
+        let seq = &estimate["sequences"][0];
+        (
+            seq["num_moves"].as_u64().unwrap(),
+            seq["total_distance"].as_f64().unwrap(),
+            seq["total_time"].as_f64().unwrap(),
+        )
+    };
+    let (coarse_moves, coarse_distance, coarse_time) = run("1");
+    let (fine_moves, fine_distance, fine_time) = run("0.5");
+    assert_eq!((coarse_moves, fine_moves), (31, 62));
+    // Finer segments follow the circle more closely, with gentler corners between them
+    assert!(coarse_distance < fine_distance && fine_distance < 10.0 * std::f64::consts::PI);
+    assert!(coarse_time > 0.25 + coarse_distance / 100.0);
+    assert!(fine_time > 0.25 + fine_distance / 100.0);
+    assert_ne!(coarse_time, fine_time);
+
+    // Zero plans the arc as a single move to its end
+    let (moves, distance, _) = run("0");
+    assert_eq!((moves, distance), (1, 20.0));
+}
+
+#[test]
+fn non_positive_configured_limits_error() {
+    let ws = Workspace::new();
+    let file = ws.write("line.gcode", "G1 X10 F6000\n");
+    for (setting, name) in [
+        ("max_acceleration=0", "max_acceleration"),
+        ("max_velocity=-1", "max_velocity"),
+    ] {
+        let output = ws.run(&["-c", setting, "estimate", &file]);
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains(&format!("{name} must be positive")),
+            "{stderr}"
+        );
+    }
+}
+
+#[test]
+fn exceeding_max_moves_errors() {
+    let ws = Workspace::new();
+    let file = ws.write("moves.gcode", "G1 X10 F6000\nG1 X20\nG1 X30\nG1 X40\n");
+    let output = ws.run(&["--max-moves", "3", "estimate", &file]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("exceeds the limit of 3 moves"), "{stderr}");
+}
+
+#[test]
+fn max_moves_ignores_lines_that_dont_move() {
+    let ws = Workspace::new();
+    let gcode =
+        "; comment\nM117 Hello\nG1 X10 F6000\n; comment\nG1 X20\nG1 X20\nM106 S255\nG1 X30\n";
+    let file = ws.write("moves.gcode", gcode);
+    ws.run_ok(&["--max-moves", "3", "estimate", &file]);
+}
+
+#[test]
+fn max_moves_applies_to_every_file() {
+    let ws = Workspace::new();
+    let small = ws.write("small.gcode", "G1 X10 F6000\n");
+    let large = ws.write("large.gcode", "G1 X10 F6000\nG1 X20\nG1 X30\n");
+    let output = ws.run(&["--max-moves", "2", "estimate", &small, &large]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("large.gcode"), "{stderr}");
+}
+
+#[test]
+fn exceeding_max_arc_segments_errors() {
+    let ws = Workspace::new();
+    // A half circle of radius 10 needs 31 segments of 1mm
+    let file = ws.write("arc.gcode", "G1 X0 Y0 F600\nG2 X20 Y0 I10 J0\n");
+    let config = ["-c", "mm_per_arc_segment=1"];
+    ws.run_ok(
+        &[
+            &config[..],
+            &["--max-arc-segments", "31", "estimate", &file],
+        ]
+        .concat(),
+    );
+    let output = ws.run(
+        &[
+            &config[..],
+            &["--max-arc-segments", "30", "estimate", &file],
+        ]
+        .concat(),
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("31 segments, more than the limit of 30"),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use common::Workspace;
+
+#[test]
+fn lists_each_feature_type_with_its_moves() {
+    let ws = Workspace::new();
+    let gcode = "\
+G1 X5 F6000
+;TYPE:External perimeter
+G1 X10 E1
+G1 Y10 E2
+;TYPE:Solid infill
+G1 X0 E3
+G1 Y0 E4
+G1 X10 Y10 E5
+;TYPE:Perimeter
+G1 X0 E6 ; wipe
+";
+    let file = ws.write("kinds.gcode", gcode);
+    let output = ws.run_ok(&["list-kinds", &file]);
+    let kinds: Vec<(usize, &str)> = output
+        .lines()
+        .map(|l| {
+            let (n, kind) = l.trim_start().split_once(' ').unwrap();
+            (n.parse().unwrap(), kind)
+        })
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            (2, "External perimeter"),
+            (1, "Perimeter"),
+            (3, "Solid infill"),
+            (1, "Travel")
+        ]
+    );
+}
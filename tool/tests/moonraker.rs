@@ -0,0 +1,71 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+
+use common::Workspace;
+use serde_json::json;
+
+/// Serves a single Moonraker `printer/objects/query` response with the given
+/// `configfile.settings`, returning the URL to query it at
+fn serve_settings(settings: serde_json::Value) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let body = json!({ "result": { "status": { "configfile": { "settings": settings } } } });
+    std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+            line.clear();
+        }
+        let body = body.to_string();
+        let mut stream = reader.into_inner();
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+    });
+    url
+}
+
+/// The `configfile.settings` Klipper requires, extended with the given sections
+fn settings(sections: serde_json::Value) -> serde_json::Value {
+    let mut settings = json!({
+        "printer": {
+            "max_velocity": 200,
+            "max_accel": 1000,
+            "square_corner_velocity": 5
+        },
+        "extruder": {
+            "max_extrude_only_velocity": 50,
+            "max_extrude_only_accel": 1000,
+            "instantaneous_corner_velocity": 1
+        }
+    });
+    for (name, section) in sections.as_object().unwrap() {
+        settings[name] = section.clone();
+    }
+    settings
+}
+
+fn mm_per_arc_segment(sections: serde_json::Value) -> Option<f64> {
+    let ws = Workspace::new();
+    let url = serve_settings(settings(sections));
+    let out = ws.run_ok(&["--config_moonraker_url", &url, "dump-config"]);
+    let limits: serde_json::Value = serde_json::from_str(&out).unwrap();
+    limits["mm_per_arc_segment"].as_f64()
+}
+
+#[test]
+fn gcode_arcs_resolution_defaults_to_1mm() {
+    assert_eq!(mm_per_arc_segment(json!({})), None);
+    assert_eq!(mm_per_arc_segment(json!({ "gcode_arcs": {} })), Some(1.0));
+    assert_eq!(
+        mm_per_arc_segment(json!({ "gcode_arcs": { "resolution": 0.1 } })),
+        Some(0.1)
+    );
+}
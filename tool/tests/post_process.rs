@@ -0,0 +1,289 @@
+mod common;
+
+use common::Workspace;
+
+/// Progress values of the `M73` lines in a file
+fn m73_values(gcode: &str) -> Vec<(f64, f64)> {
+    gcode
+        .lines()
+        .filter_map(|l| l.strip_prefix("M73 "))
+        .map(|params| {
+            let mut p = None;
+            let mut r = None;
+            for param in params.split_whitespace() {
+                let (key, value) = param.split_at(1);
+                assert!(!value.starts_with('-'), "negative value in M73 {params}");
+                match key {
+                    "P" => p = value.parse::<f64>().ok(),
+                    "R" => r = value.parse::<f64>().ok(),
+                    _ => {}
+                }
+            }
+            (p.unwrap(), r.unwrap())
+        })
+        .collect()
+}
+
+#[test]
+fn m73_is_injected_once_per_interval() {
+    let ws = Workspace::new();
+    // Moves of 1.1s each, shorter than the interval, so every interval gets its own M73
+    let mut gcode = "G1 X100 F6000\n".to_string();
+    for _ in 0..5 {
+        gcode.push_str("G1 X0\nG1 X100\n");
+    }
+    let file = ws.write("interval.gcode", &gcode);
+    let total = ws.total_time(&["estimate", &file]);
+    ws.run_ok(&["post-process", "--inject-m73", "2", &file]);
+
+    let values = m73_values(&ws.read("interval.gcode"));
+    assert_eq!(values.len(), (total / 2.0).floor() as usize + 1);
+}
+
+#[test]
+fn forced_slicer_rewrites_header_less_files() {
+    let ws = Workspace::new();
+    // 1.1s for the move, rounded up
+    let gcode = "G1 X100 F6000\n; estimated printing time (normal mode) = 1h 5m\n";
+
+    let file = ws.write("none.gcode", gcode);
+    ws.run_ok(&["post-process", "--slicer", "none", &file]);
+    assert!(ws.read("none.gcode").starts_with(gcode));
+
+    let file = ws.write("prusa.gcode", gcode);
+    ws.run_ok(&["post-process", "--slicer", "prusa", &file]);
+    assert!(ws
+        .read("prusa.gcode")
+        .contains("; estimated printing time (normal mode) = 2s\n"));
+
+    // Not picked up by the generic rewriting without a detected or forced slicer
+    let gcode = ";TIME:3900\nG1 X100 F6000\n";
+    let file = ws.write("detected.gcode", gcode);
+    ws.run_ok(&["post-process", &file]);
+    assert!(ws.read("detected.gcode").starts_with(gcode));
+
+    let file = ws.write("cura.gcode", gcode);
+    ws.run_ok(&["post-process", "--slicer", "cura", &file]);
+    assert!(ws.read("cura.gcode").starts_with(";TIME:2\n"));
+}
+
+#[test]
+fn cura_time_elapsed_keeps_fractional_seconds() {
+    let ws = Workspace::new();
+    let gcode = "\
+;FLAVOR:Marlin
+;TIME:100
+;Generated with Cura_SteamEngine 5.0.0
+G1 X100 F6000
+;TIME_ELAPSED:1
+G1 X0
+;TIME_ELAPSED:2
+";
+    let file = ws.write("cura.gcode", gcode);
+    ws.run_ok(&["post-process", &file]);
+
+    let output = ws.read("cura.gcode");
+    let elapsed: Vec<f64> = output
+        .lines()
+        .filter_map(|l| l.strip_prefix(";TIME_ELAPSED:"))
+        .map(|v| v.parse().unwrap())
+        .collect();
+    // The second move reverses the first, so both take the full 1.1s
+    assert_eq!(elapsed.len(), 2);
+    common::assert_close(elapsed[0], 1.1, 1e-6);
+    common::assert_close(elapsed[1], 2.2, 1e-6);
+    assert!(output.contains(";TIME:3\n"));
+}
+
+#[test]
+fn kisslicer_build_time_is_rewritten() {
+    let ws = Workspace::new();
+    let gcode = "; KISSlicer - PRO\n; Estimated Build Time:   65.00 minutes\nG1 X100 F6000\n";
+    let file = ws.write("kiss.gcode", gcode);
+    ws.run_ok(&["post-process", &file]);
+    assert!(ws
+        .read("kiss.gcode")
+        .contains("; Estimated Build Time:   0.02 minutes\n"));
+}
+
+#[test]
+fn generic_fallback_rewrites_custom_headers() {
+    let ws = Workspace::new();
+    let gcode = "; My Slicer 1.0\n; Print time: 3900\n; estimated time = 1h 5m\nG1 X100 F6000\n";
+    let file = ws.write("generic.gcode", gcode);
+    ws.run_ok(&["post-process", &file]);
+    let output = ws.read("generic.gcode");
+    // Plain numbers stay plain seconds, durations stay durations
+    assert!(output.contains("; Print time: 2\n"), "{output}");
+    assert!(output.contains("; estimated time = 2s\n"), "{output}");
+}
+
+#[test]
+fn long_lines_are_kept_byte_for_byte() {
+    let ws = Workspace::new();
+    let polygon = (0..5000)
+        .map(|i| format!("[{}.{},{}]", i, i % 10, i * 2))
+        .collect::<Vec<_>>()
+        .join(",");
+    let define = format!("EXCLUDE_OBJECT_DEFINE NAME=part CENTER=1,2 POLYGON=[{polygon}]\n");
+    let comment = format!("; {polygon}\n");
+    let gcode = format!("{define}{comment}G1 X100 F6000\n");
+    let file = ws.write("long.gcode", &gcode);
+    ws.run_ok(&["post-process", &file]);
+    let output = ws.read("long.gcode");
+    assert!(output.contains(&define));
+    assert!(output.contains(&comment));
+}
+
+#[test]
+fn compare_reports_the_difference_to_the_slicer() {
+    let ws = Workspace::new();
+    let gcode = "\
+; generated by PrusaSlicer 2.6.0 on 2024-01-01 at 12:00:00 UTC
+G1 X100 F6000
+; estimated printing time (normal mode) = 1m 1s
+";
+    let file = ws.write("compare.gcode", gcode);
+    let output = ws.run_ok(&["post-process", "--compare", &file]);
+    // 1.1s against the slicer's 61s
+    assert_eq!(
+        output,
+        "\
+klipper_estimator estimate: 2s
+Slicer estimate:            1m 1s
+Difference:                 -1m 0s (-98.2%)
+"
+    );
+}
+
+#[test]
+fn byte_order_mark_and_crlf_are_kept() {
+    let ws = Workspace::new();
+    let gcode = "\u{feff};TIME:3900\r\n;TYPE:WALL-OUTER\r\nG1 X100 F6000\r\n";
+    let file = ws.write("bom.gcode", gcode);
+    ws.run_ok(&["post-process", "--slicer", "cura", &file]);
+    // The first line is rewritten behind the BOM, the rest is untouched
+    let output = ws.read("bom.gcode");
+    assert!(
+        output.starts_with("\u{feff};TIME:2\r\n;TYPE:WALL-OUTER\r\nG1 X100 F6000\r\n"),
+        "{output:?}"
+    );
+}
+
+#[test]
+fn pauses_are_annotated_with_the_time_to_the_next() {
+    let ws = Workspace::new();
+    let gcode = "G1 X100 F6000\nM600\nG1 X0\nG1 X100\nPAUSE\nG1 X0\n";
+    let file = ws.write("pauses.gcode", gcode);
+    ws.run_ok(&["post-process", "--annotate-pauses", &file]);
+    // 2.2s of moves between the pauses and 1.1s after the last one, rounded up
+    assert!(ws.read("pauses.gcode").starts_with(
+        "\
+G1 X100 F6000
+; time until next pause: 3s
+M600
+G1 X0
+G1 X100
+; time until end of print: 2s
+PAUSE
+G1 X0
+"
+    ));
+}
+
+#[test]
+fn m73_progress_stays_in_range_and_ends_done() {
+    let ws = Workspace::new();
+    let gcode = "\
+; generated by PrusaSlicer 2.6.0 on 2024-01-01 at 12:00:00 UTC
+M73 P0 R1
+G1 X100 F6000
+M73 P50 R1
+G1 Y100
+M73 P99 R1
+G1 X0
+";
+    let file = ws.write("m73.gcode", gcode);
+    ws.run_ok(&["post-process", &file]);
+
+    let values = m73_values(&ws.read("m73.gcode"));
+    assert_eq!(values.len(), 3);
+    for (p, r) in values.iter() {
+        assert!((0.0..=100.0).contains(p), "progress {p} out of range");
+        assert!(*r >= 0.0);
+    }
+    assert!(values.windows(2).all(|w| w[0].0 <= w[1].0));
+    assert_eq!(values.last(), Some(&(100.0, 0.0)));
+}
+
+#[test]
+fn injected_m73_ends_done() {
+    let ws = Workspace::new();
+    let gcode = "G1 X100 F6000\nG1 Y100\nG1 X0\nG1 Y0\n";
+    let file = ws.write("inject.gcode", gcode);
+    ws.run_ok(&["post-process", "--inject-m73", "1", &file]);
+
+    let values = m73_values(&ws.read("inject.gcode"));
+    assert!(values.len() > 1);
+    assert!(values.windows(2).all(|w| w[0].0 < w[1].0));
+    assert_eq!(values.last(), Some(&(100.0, 0.0)));
+}
+
+#[test]
+fn thumbnails_are_kept_byte_for_byte() {
+    let ws = Workspace::new();
+    let thumbnail = "\
+; thumbnail begin 16x16 96\r
+; iVBORw0KGgoAAAANSUhEUgAAABAAAAAQCAYAAAAf8/9hAAAAAXNSR0IArs4c6QAAAARnQU1BAACx\r
+; jwv8YQUAAAAJcEhZcwAADsMAAA7DAcdvqGQAAAAZdEVYdFNvZnR3YXJlAHBhaW50Lm5ldCA0LjAu\r
+; thumbnail end\r
+";
+    let gcode = format!(
+        "; generated by PrusaSlicer 2.6.0 on 2024-01-01 at 12:00:00 UTC\r\n{thumbnail}G1 X100 F6000\r\n"
+    );
+    let file = ws.write("thumbnail.gcode", &gcode);
+    ws.run_ok(&["post-process", &file]);
+    assert!(ws.read("thumbnail.gcode").contains(thumbnail));
+}
+
+#[test]
+fn unterminated_thumbnail_doesnt_hide_the_rest_of_the_file() {
+    let ws = Workspace::new();
+    let gcode = "\
+; generated by PrusaSlicer 2.6.0 on 2024-01-01 at 12:00:00 UTC
+; thumbnail begin 16x16 10
+; abc
+M73 P0 R1
+G1 X100 F6000
+M73 P99 R1
+G1 X0
+";
+    let file = ws.write("unterminated.gcode", gcode);
+    let output = ws.run(&["post-process", &file]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("thumbnail at line 2 has no end marker"),
+        "{stderr}"
+    );
+
+    let processed = ws.read("unterminated.gcode");
+    assert!(processed.contains("; abc\n"));
+    let values = m73_values(&processed);
+    assert_eq!(values.len(), 2);
+    assert_eq!(values.last(), Some(&(100.0, 0.0)));
+}
+
+#[test]
+fn unwritable_output_is_an_error() {
+    let ws = Workspace::new();
+    let file = ws.write("main.gcode", "G1 X100 F6000\n");
+    // The processed file is written next to the original before replacing it
+    std::fs::create_dir(ws.path(".estimate.main.gcode")).unwrap();
+    let output = ws.run(&["post-process", &file]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(".estimate.main.gcode: "), "{stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+    assert_eq!(ws.read("main.gcode"), "G1 X100 F6000\n");
+}